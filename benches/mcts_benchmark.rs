@@ -0,0 +1,44 @@
+extern crate uttt;
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use uttt::moves::*;
+use uttt::engine;
+use uttt::engine::mcts::*;
+
+const SEARCH_MILLIS: u64 = 200;
+
+// one iteration is one rollout, so that criterion reports rollouts per
+// second. Every sample is a search of its own, for a fixed time so that
+// more threads can do more rollouts
+fn criterion_benchmark(c: &mut Criterion) {
+    let pos = Position::new();
+    init_moves();
+    engine::init_engine();
+    let mut group = c.benchmark_group("mcts rollouts");
+    group.throughput(Throughput::Elements(1));
+    group.sample_size(10);
+    let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = 1;
+    while threads <= max_threads {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(19));
+                mcts.set_threads(threads);
+                let start = Instant::now();
+                let (_, n_rollouts) = mcts.go(MCTSLimits::movetime(SEARCH_MILLIS), Arc::new(AtomicBool::new(false)));
+                let per_rollout = start.elapsed().as_secs_f64() / n_rollouts as f64;
+                return Duration::from_secs_f64(per_rollout * iters as f64);
+            });
+        });
+        threads *= 2;
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
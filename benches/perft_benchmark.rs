@@ -9,8 +9,8 @@ fn criterion_benchmark(c: &mut Criterion) {
     init_moves();
     engine::init_engine();
     c.bench_function("perft 5", |b| b.iter(|| perft(black_box(5), black_box(&mut pos))));
+    c.bench_function("perft 5 unmake", |b| b.iter(|| perft_unmake(black_box(5), black_box(&mut pos))));
 }
 
 criterion_group!(benches, criterion_benchmark);
 criterion_main!(benches);
-
@@ -1,15 +1,20 @@
 extern crate uttt;
 
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use uttt::moves::*;
 use uttt::engine;
 
 fn criterion_benchmark(c: &mut Criterion) {
-    let mut pos = Position::new();
+    let pos = Position::new();
     init_moves();
     engine::init_engine();
-    panic!("Fix best_move first. Implement generic termination function");
-    //c.bench_function("search 6", |b| b.iter(|| engine::best_move(black_box(6), black_box(&mut pos))));
+    // fresh transposition table every time, otherwise the search is instant
+    c.bench_function("search 6", |b| b.iter(|| {
+        let manager = engine::Manager::with_hash_size(black_box(pos), 1);
+        manager.search(engine::SearchLimits::depth(black_box(6)), Arc::new(AtomicBool::new(false)))
+    }));
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,661 @@
+/*
+Ultimate boards of any level, for when the u128 bitboards of moves.rs don't
+fit. Level 1 is plain tic-tac-toe, level 2 is ultimate tic-tac-toe (the same
+game as Position, only slower) and level 3 is "ultimate ultimate"
+tic-tac-toe, where every cell of a level 2 board is a block of its own.
+
+Cells are numbered in base 9, one digit per level from the largest block
+down, so level 2 numbers are the same as Idx: cell c of block b is b * 9 + c.
+At level 3, cell c of block b of superblock s is s * 81 + b * 9 + c.
+
+A move sends the opponent to the block at the same place, one level down:
+at level 3, cell c of block b of any superblock sends them to block c of
+superblock b. If that block is closed (won or full), they can play anywhere
+in the superblock, and if that is closed too, anywhere on the board.
+
+Rulesets work the same way at every level, except that won blocks are always
+closed, and a full level 1 board is a draw, as there are no blocks to count.
+When a tied block that counts for both sides completes a row for both at
+once, the block is tied too, and the whole board goes to the side that made
+the move, like in Position::get_result.
+
+BGN for a board is the same as for a Position, with the level first and
+9^(level - 1) blocks per side. The focus block is the block of cells the
+side to move was sent to, or the number of blocks at the start.
+*/
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::format::*;
+use crate::moves::*;
+use crate::rules::*;
+
+// a cell number, see the top of this file
+pub type Cell = u16;
+
+// level 4 has 6561 cells, and block numbers wouldn't fit in PositionError
+pub const MAX_LEVEL: u8 = 3;
+
+const CELL_WORDS: usize = 12;
+
+// a set of cells of a board of any level, like Moves for Position
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub struct Cells([u64; CELL_WORDS]);
+
+impl Cells {
+    pub fn new() -> Cells {
+        Cells([0; CELL_WORDS])
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn contains(&self, cell: Cell) -> bool {
+        self.0[cell as usize / 64] & (1 << (cell % 64)) != 0
+    }
+
+    pub fn add(&mut self, cell: Cell) {
+        self.0[cell as usize / 64] |= 1 << (cell % 64);
+    }
+
+    pub fn remove(&mut self, cell: Cell) {
+        self.0[cell as usize / 64] &= !(1 << (cell % 64));
+    }
+
+    pub fn intersect(&self, other: Cells) -> Cells {
+        let mut ret = *self;
+        for (word, other_word) in ret.0.iter_mut().zip(other.0.iter()) {
+            *word &= other_word;
+        }
+        return ret;
+    }
+
+    pub fn subtract(&self, other: Cells) -> Cells {
+        let mut ret = *self;
+        for (word, other_word) in ret.0.iter_mut().zip(other.0.iter()) {
+            *word &= !other_word;
+        }
+        return ret;
+    }
+
+    // the nth cell, counting up from 0
+    pub fn nth_move(&self, mut n: usize) -> Cell {
+        for (i, word) in self.0.iter().enumerate() {
+            let count = word.count_ones() as usize;
+            if n < count {
+                let mut word = *word;
+                for _ in 0..n {
+                    word &= word - 1;
+                }
+                return (i * 64 + word.trailing_zeros() as usize) as Cell;
+            }
+            n -= count;
+        }
+        panic!("nth_move past the end of the set");
+    }
+}
+
+impl Iterator for Cells {
+    type Item = Cell;
+
+    fn next(&mut self) -> Option<Cell> {
+        for (i, word) in self.0.iter_mut().enumerate() {
+            if *word != 0 {
+                let bit = word.trailing_zeros();
+                *word &= *word - 1;
+                return Some((i * 64) as Cell + bit as Cell);
+            }
+        }
+        return None;
+    }
+}
+
+impl fmt::Debug for Cells {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(*self).finish()
+    }
+}
+
+// the children that each side won, and the ones that filled up without a
+// winner. At height 1 the children are cells, "won" by whoever played there,
+// and won blocks are filled in with the winner's cells like in Bitboard
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct Node {
+    won: [B33; 2],
+    tied: B33,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Status {
+    Open,
+    Won(Side),
+    Tied,
+}
+
+impl Node {
+    fn closed(&self) -> B33 {
+        self.won[0] | self.won[1] | self.tied
+    }
+
+    // mover is the side that just moved if this is the whole board, see the
+    // top of this file
+    fn status(&self, ruleset: Ruleset, mover: Option<Side>) -> Status {
+        let both = match ruleset.tied_blocks {
+            TiedBlocks::Dead => 0,
+            TiedBlocks::CountForBoth => self.tied,
+        };
+        let rows = [get_block_won(self.won[0] | both), get_block_won(self.won[1] | both)];
+        match (rows[0], rows[1], mover) {
+            (true, true, Some(side)) => Status::Won(side),
+            (true, true, None) => Status::Tied,
+            (true, false, _) => Status::Won(Side::X),
+            (false, true, _) => Status::Won(Side::O),
+            (false, false, _) if self.closed() == BLOCK_OCC => Status::Tied,
+            (false, false, _) => Status::Open,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq)]
+pub struct Board {
+    level: u8,
+    // nodes[h - 1] are the blocks of height h: blocks of cells for h = 1,
+    // blocks of those for h = 2, and so on up to the whole board
+    nodes: Vec<Vec<Node>>,
+    to_move: Side,
+    // the block of height 1 the side to move was sent to, None at the start
+    focus: Option<Cell>,
+    result: GameResult,
+    ruleset: Ruleset,
+    hash: u64,
+}
+
+impl Hash for Board {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+    }
+}
+
+// splitmix64, so the keys don't need tables for every level
+fn zobrist(key: u64) -> u64 {
+    let mut z = key.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+fn zobrist_cell(cell: Cell, side: Side) -> u64 {
+    zobrist((cell as u64) << 1 | side as u64)
+}
+
+fn zobrist_focus(focus: Option<Cell>) -> u64 {
+    match focus {
+        Some(block) => zobrist(1 << 32 | block as u64),
+        None => 0,
+    }
+}
+
+fn zobrist_side(side: Side) -> u64 {
+    match side {
+        Side::X => 0,
+        Side::O => zobrist(1 << 33),
+    }
+}
+
+// 0 for the default ruleset, like moves.rs
+fn zobrist_ruleset(ruleset: Ruleset) -> u64 {
+    match ruleset.code() {
+        0 => 0,
+        code => zobrist(1 << 34 | code as u64),
+    }
+}
+
+impl Board {
+    pub fn new(level: u8) -> Board {
+        Board::with_ruleset(level, Ruleset::default())
+    }
+
+    // NOTE won blocks are always closed, see the top of this file
+    pub fn with_ruleset(level: u8, ruleset: Ruleset) -> Board {
+        assert!((1..=MAX_LEVEL).contains(&level), "level {} isn't from 1 to {}", level, MAX_LEVEL);
+        assert!(!ruleset.won_blocks.is_open(), "won blocks are always closed on a Board");
+        let nodes = (1..=level).map(|h| vec![Node::default(); 9usize.pow((level - h) as u32)]).collect();
+        return Board {
+            level: level,
+            nodes: nodes,
+            to_move: Side::X,
+            focus: None,
+            result: GameResult::Ongoing,
+            ruleset: ruleset,
+            hash: zobrist_ruleset(ruleset),
+        };
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn n_cells(&self) -> usize {
+        9usize.pow(self.level as u32)
+    }
+
+    // the number of blocks of cells
+    pub fn n_blocks(&self) -> usize {
+        self.nodes[0].len()
+    }
+
+    pub fn ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
+    pub fn side_to_move(&self) -> Side {
+        self.to_move
+    }
+
+    // the block of cells the side to move was sent to, which may be closed
+    pub fn focus(&self) -> Option<Cell> {
+        self.focus
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn get_result(&self) -> GameResult {
+        self.result
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result != GameResult::Ongoing
+    }
+
+    // the side on cell, counting the filled in cells of won blocks for the
+    // winner
+    pub fn get(&self, cell: Cell) -> Option<Side> {
+        let node = self.nodes[0][cell as usize / 9];
+        let bit = 1 << (cell % 9);
+        for side in Side::iterator() {
+            if node.won[*side as usize] & bit != 0 {
+                return Some(*side);
+            }
+        }
+        return None;
+    }
+
+    // whether block i of height h is closed, going by its parent
+    fn is_closed(&self, h: usize, i: usize) -> bool {
+        self.nodes[h][i / 9].closed() & (1 << (i % 9)) != 0
+    }
+
+    pub fn legal_moves(&self) -> Cells {
+        debug_assert!(!self.is_over());
+        let level = self.level as usize;
+        // down from the whole board towards the focus block, as long as the
+        // blocks on the way are open
+        let (mut h, mut i) = (level, 0);
+        if let Some(focus) = self.focus {
+            while h > 1 {
+                let child = focus as usize / 9usize.pow(h as u32 - 2);
+                if self.is_closed(h - 1, child) {
+                    break;
+                }
+                h -= 1;
+                i = child;
+            }
+        }
+        let mut ret = Cells::new();
+        self.push_moves(h, i, &mut ret);
+        return ret;
+    }
+
+    // the empty cells of the open blocks under block i of height h
+    fn push_moves(&self, h: usize, i: usize, moves: &mut Cells) {
+        let closed = self.nodes[h - 1][i].closed();
+        for child in 0..9 {
+            if closed & (1 << child) != 0 {
+                continue;
+            }
+            if h == 1 {
+                moves.add((i * 9 + child) as Cell);
+            } else {
+                self.push_moves(h - 1, i * 9 + child, moves);
+            }
+        }
+    }
+
+    pub fn make_move(&mut self, cell: Cell) {
+        debug_assert!(self.legal_moves().contains(cell));
+        let level = self.level as usize;
+        let side = self.to_move;
+        let mut i = cell as usize / 9;
+        self.nodes[0][i].won[side as usize] |= 1 << (cell % 9);
+        self.hash ^= zobrist_cell(cell, side);
+
+        // a block that was decided by the move can decide its parent
+        for h in 1..=level {
+            let root_mover = if h == level { Some(side) } else { None };
+            let status = self.nodes[h - 1][i].status(self.ruleset, root_mover);
+            if h == level {
+                self.result = self.result_from(status);
+                break;
+            }
+            match status {
+                Status::Open => break,
+                Status::Won(winner) => {
+                    if h == 1 {
+                        self.fill(i, winner);
+                    }
+                    self.nodes[h][i / 9].won[winner as usize] |= 1 << (i % 9);
+                },
+                Status::Tied => self.nodes[h][i / 9].tied |= 1 << (i % 9),
+            }
+            i /= 9;
+        }
+
+        let focus = Some(cell % self.n_blocks() as Cell);
+        self.hash ^= zobrist_focus(self.focus) ^ zobrist_focus(focus);
+        self.focus = focus;
+        self.hash ^= zobrist_side(self.to_move) ^ zobrist_side(side.other());
+        self.to_move = side.other();
+    }
+
+    // fills in won block i of cells, see Bitboard::set
+    fn fill(&mut self, i: usize, winner: Side) {
+        let own = &mut self.nodes[0][i].won[winner as usize];
+        for child in 0..9 {
+            if *own & (1 << child) == 0 {
+                self.hash ^= zobrist_cell((i * 9 + child) as Cell, winner);
+            }
+        }
+        *own = BLOCK_OCC;
+    }
+
+    fn result_from(&self, root_status: Status) -> GameResult {
+        match root_status {
+            Status::Open => GameResult::Ongoing,
+            Status::Won(side) => GameResult::won_by(side),
+            Status::Tied if self.level == 1 || self.ruleset.full_board == FullBoard::Draw => GameResult::Draw,
+            Status::Tied => {
+                let root = self.nodes[self.level as usize - 1][0];
+                let n_x = root.won[Side::X as usize].count_ones();
+                let n_o = root.won[Side::O as usize].count_ones();
+                match n_x.cmp(&n_o) {
+                    std::cmp::Ordering::Greater => GameResult::XWon,
+                    std::cmp::Ordering::Less => GameResult::OWon,
+                    std::cmp::Ordering::Equal => GameResult::Draw,
+                }
+            },
+        }
+    }
+
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist_focus(self.focus) ^ zobrist_side(self.to_move) ^ zobrist_ruleset(self.ruleset);
+        for (i, node) in self.nodes[0].iter().enumerate() {
+            for side in Side::iterator() {
+                for child in 0..9 {
+                    if node.won[*side as usize] & (1 << child) != 0 {
+                        hash ^= zobrist_cell((i * 9 + child) as Cell, *side);
+                    }
+                }
+            }
+        }
+        return hash;
+    }
+
+    // the blocks above the blocks of cells, and the result, from the cells
+    fn settle(&mut self) {
+        let level = self.level as usize;
+        for h in 1..=level {
+            let root_mover = if h == level { Some(self.to_move.other()) } else { None };
+            for i in 0..self.nodes[h - 1].len() {
+                let status = self.nodes[h - 1][i].status(self.ruleset, root_mover);
+                if h == level {
+                    self.result = self.result_from(status);
+                    continue;
+                }
+                match status {
+                    Status::Open => {},
+                    Status::Won(winner) => self.nodes[h][i / 9].won[winner as usize] |= 1 << (i % 9),
+                    Status::Tied => self.nodes[h][i / 9].tied |= 1 << (i % 9),
+                }
+            }
+        }
+    }
+
+    /*
+    Checks that the board could have come up in a game, as far as the
+    blocks of cells go, like Position::validate. Boards made with make_move
+    always pass.
+    */
+    pub fn validate(&self) -> Result<(), PositionError> {
+        // possible number of pieces for each side, as (min, max)
+        let mut counts = [(0i32, 0i32); 2];
+        for (i, node) in self.nodes[0].iter().enumerate() {
+            let block = i as u8;
+            let occs = node.won;
+            let filled = [occs[0] == BLOCK_OCC, occs[1] == BLOCK_OCC];
+            if self.level > 1 && (filled[0] || filled[1]) {
+                if filled[0] && filled[1] {
+                    return Err(PositionError::CapturedByBoth { block: block });
+                }
+                let winner = if filled[0] { 0 } else { 1 };
+                let other = 1 - winner;
+                let n_other = occs[other].count_ones() as i32;
+                if n_other > 6 {
+                    return Err(PositionError::CapturedByBoth { block: block });
+                }
+                if get_block_won(occs[other]) {
+                    return Err(PositionError::BadCapture { block: block, side: [Side::X, Side::O][other] });
+                }
+                counts[other].0 += n_other;
+                counts[other].1 += n_other;
+                counts[winner].0 += 3;
+                counts[winner].1 += 9 - n_other;
+                continue;
+            }
+            if occs[0] & occs[1] != 0 {
+                return Err(PositionError::Overlap { block: block });
+            }
+            for side in Side::iterator() {
+                // won blocks are filled in, except the whole board
+                if self.level > 1 && get_block_won(occs[*side as usize]) {
+                    return Err(PositionError::BadCapture { block: block, side: *side });
+                }
+                let n = occs[*side as usize].count_ones() as i32;
+                counts[*side as usize].0 += n;
+                counts[*side as usize].1 += n;
+            }
+        }
+        // X moves first
+        let diff = match self.to_move {
+            Side::X => 0,
+            Side::O => 1,
+        };
+        let (x_count, o_count) = (counts[0], counts[1]);
+        if diff < x_count.0 - o_count.1 || diff > x_count.1 - o_count.0 {
+            return Err(PositionError::PieceCount);
+        }
+
+        let root = self.nodes[self.level as usize - 1][0];
+        if get_block_won(root.won[self.to_move as usize]) {
+            return Err(PositionError::BadWinner);
+        }
+
+        // the previous move was in the same place as the focus block, in
+        // any of the blocks one level up
+        if let Some(focus) = self.focus {
+            let last_mover = self.to_move.other() as usize;
+            let n_blocks = self.n_blocks();
+            let played = (0..9).any(|j| {
+                let cell = j * n_blocks + focus as usize;
+                self.nodes[0][cell / 9].won[last_mover] & (1 << (cell % 9)) != 0
+            });
+            if !played {
+                return Err(PositionError::LastBlock(focus as u8));
+            }
+        }
+
+        if self.hash != self.compute_hash() {
+            return Err(PositionError::Hash);
+        }
+        return Ok(());
+    }
+
+    // '<level> <X board> <O board> <focus block> <side to move> [<ruleset>]',
+    // see the top of this file and Position::from_bgn
+    pub fn from_bgn(repr: &str) -> Result<Board, ParseError> {
+        let mut tokens = repr.split_whitespace();
+        let mut next_token = |expected: &str| -> Result<&str, ParseError> {
+            tokens.next().ok_or(ParseError::expected(repr.len(), expected, ""))
+        };
+
+        let level_tok = next_token("level")?;
+        let level = match level_tok.parse::<u8>() {
+            Ok(level) if (1..=MAX_LEVEL).contains(&level) => level,
+            _ => return Err(ParseError::expected(offset_in(repr, level_tok), "a level from 1 to 3", level_tok)),
+        };
+        let n_blocks = 9usize.pow(level as u32 - 1);
+
+        let x_board = next_token("X board")?;
+        let x_blocks = parse_bgn_board(x_board, offset_in(repr, x_board), n_blocks)?;
+        let o_board = next_token("O board")?;
+        let o_blocks = parse_bgn_board(o_board, offset_in(repr, o_board), n_blocks)?;
+
+        let focus_tok = next_token("focus block")?;
+        let focus = match focus_tok {
+            "-" => None,
+            _ => match focus_tok.parse::<usize>() {
+                Ok(block) if block == n_blocks => None,
+                Ok(block) if block < n_blocks => Some(block as Cell),
+                _ => return Err(ParseError::expected(offset_in(repr, focus_tok), "a block index or '-'", focus_tok)),
+            },
+        };
+
+        let to_move_tok = next_token("side to move")?;
+        let to_move = match to_move_tok {
+            "X" => Side::X,
+            "O" => Side::O,
+            other => return Err(ParseError::expected(offset_in(repr, to_move_tok), "'X' or 'O'", other)),
+        };
+
+        let mut ruleset = Ruleset::default();
+        if let Some(tok) = tokens.next() {
+            ruleset = match tok.parse::<Ruleset>() {
+                Ok(ruleset) if !ruleset.won_blocks.is_open() => ruleset,
+                _ => return Err(ParseError::expected(offset_in(repr, tok), "a ruleset with closed won blocks", tok)),
+            };
+        }
+        if let Some(tok) = tokens.next() {
+            return Err(ParseError::expected(offset_in(repr, tok), "end of input", tok));
+        }
+
+        let mut board = Board::with_ruleset(level, ruleset);
+        board.to_move = to_move;
+        board.focus = focus;
+        for (i, blocks) in x_blocks.iter().zip(o_blocks.iter()).enumerate() {
+            for (side, block) in [blocks.0, blocks.1].iter().enumerate() {
+                if block.owner_mark {
+                    let found = format!("{:x}!", block.occ);
+                    return Err(ParseError::expected(block.offset, "no '!', won blocks are closed", &found));
+                }
+                board.nodes[0][i].won[side] = block.occ;
+            }
+        }
+        board.settle();
+        board.hash = board.compute_hash();
+        if let Err(err) = board.validate() {
+            return Err(ParseError {
+                offset: 0,
+                kind: ParseErrorKind::Invalid(err),
+            });
+        }
+        return Ok(board);
+    }
+
+    pub fn to_bgn(&self) -> String {
+        let boards = Side::iterator()
+            .map(|side| {
+                let blocks = self.nodes[0].iter().map(|node| format!("{:x}", node.won[*side as usize]));
+                blocks.collect::<Vec<_>>().join("/")
+            })
+            .collect::<Vec<_>>();
+        let focus = match self.focus {
+            Some(block) => block as usize,
+            None => self.n_blocks(),
+        };
+        let mut ret = format!("{} {} {} {} {}", self.level, boards[0], boards[1], focus, self.to_move);
+        if self.ruleset != Ruleset::default() {
+            ret.push_str(&format!(" {}", self.ruleset));
+        }
+        return ret;
+    }
+
+    // rows of cells with '.' for empty ones, like Position::from_compact_board.
+    // Blocks of height h are separated by h '|'s, and rows of them by a line
+    // of '-' (height 1) or '=' (higher)
+    pub fn to_pretty_board(&self) -> String {
+        let width = 3usize.pow(self.level as u32);
+        // the height of the largest block boundary before row or col i, the
+        // number of trailing zeros of i in base 3
+        let boundary = |i: usize| -> usize {
+            let (mut h, mut rest) = (0, i);
+            while h < self.level as usize {
+                match rest % 3 {
+                    0 => rest /= 3,
+                    _ => break,
+                }
+                h += 1;
+            }
+            return h;
+        };
+        let mut lines = Vec::new();
+        for row in 0..width {
+            let mut line = String::new();
+            for col in 0..width {
+                if col > 0 {
+                    line.push_str(&"|".repeat(boundary(col)));
+                }
+                // one base 9 digit per level, see the top of this file
+                let mut cell = 0;
+                for k in (0..self.level as u32).rev() {
+                    let digit = (row / 3usize.pow(k) % 3) * 3 + col / 3usize.pow(k) % 3;
+                    cell = cell * 9 + digit;
+                }
+                line.push(match self.get(cell as Cell) {
+                    Some(Side::X) => 'X',
+                    Some(Side::O) => 'O',
+                    None => '.',
+                });
+            }
+            if row > 0 && boundary(row) > 0 {
+                let sep = if boundary(row) == 1 { "-" } else { "=" };
+                lines.push(sep.repeat(line.len()));
+            }
+            lines.push(line);
+        }
+        return lines.join("\n");
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_bgn())
+    }
+}
+
+impl fmt::Debug for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Board({})", self.to_bgn())
+    }
+}
+
+impl FromStr for Board {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Board, ParseError> {
+        Board::from_bgn(repr)
+    }
+}
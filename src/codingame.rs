@@ -1,5 +1,7 @@
 // codingame more like codinggae amirite
 use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::time::{Instant};
 use rand::SeedableRng;
 use rand::rngs::SmallRng;
@@ -9,6 +11,11 @@ extern crate uttt;
 use uttt::engine::*;
 use uttt::moves::*;
 use uttt::engine::mcts::*;
+use uttt::engine::rollout::*;
+use uttt::engine::selection::*;
+
+// out of Codingame's 100ms, leaving some for reading input and answering
+const MOVE_MILLIS: u64 = 80;
 
 macro_rules! parse_input {
     ($x:expr, $t:ident) => ($x.trim().parse::<$t>().unwrap())
@@ -28,9 +35,43 @@ fn main() {
         Some(val) => val.parse().expect(&format!("Could not parse c value '{}'", val)[..]),
         None => 0.85,
     };
+    // the rest are for trying out variants: rollout policy, prior visits,
+    // prior bias, selection policy and final move criterion (see
+    // MCTSWorker::set_rollout_policy, set_priors, engine/selection.rs and
+    // set_final_move)
+    let policy: RolloutPolicy = match std::env::args().nth(2) {
+        Some(val) => val.parse().expect(&format!("Could not parse rollout policy '{}'", val)[..]),
+        None => RolloutPolicy::Uniform,
+    };
+    let mut priors = Priors::default();
+    if let Some(val) = std::env::args().nth(3) {
+        priors.visits = val.parse().expect(&format!("Could not parse prior visits '{}'", val)[..]);
+    }
+    if let Some(val) = std::env::args().nth(4) {
+        priors.bias = val.parse().expect(&format!("Could not parse prior bias '{}'", val)[..]);
+    }
+    let selection: SelectionKind = match std::env::args().nth(5) {
+        Some(val) => val.parse().expect(&format!("Could not parse selection policy '{}'", val)[..]),
+        None => SelectionKind::Ucb1,
+    };
+    let final_move: FinalMoveCriterion = match std::env::args().nth(6) {
+        Some(val) => val.parse().expect(&format!("Could not parse final move criterion '{}'", val)[..]),
+        None => FinalMoveCriterion::MaxVisits,
+    };
     init_moves();
     init_engine();
+    match selection {
+        SelectionKind::Ucb1 => play(Ucb1 { c: c }, policy, priors, final_move),
+        SelectionKind::Ucb1Tuned => play(Ucb1Tuned { c: c }, policy, priors, final_move),
+        SelectionKind::Puct => play(Puct { c: c }, policy, priors, final_move),
+        SelectionKind::Rave => play(Rave { c: c, k: RAVE_K }, policy, priors, final_move),
+    }
+}
+
+fn play<S: SelectionPolicy>(selection: S, policy: RolloutPolicy, priors: Priors, final_move: FinalMoveCriterion) {
     let mut pos = Position::new();
+    // kept from move to move, see MCTSWorker::advance
+    let mut mcts: Option<MCTSWorker<SmallRng, Position, S>> = None;
     loop {
         let line = next_line();
         let inputs = line.split(" ").collect::<Vec<_>>();
@@ -49,29 +90,39 @@ fn main() {
             pos.make_move(40);
             continue;
         }
-        let index = (opp_col/3)*9 + (opp_col % 3) + (opp_row/3)*27 + 3*(opp_row %3);
-        pos.make_move(index as u8);
+        let index = Square::from_row_col(opp_row as u8, opp_col as u8).unwrap().idx();
+        pos.make_move(index);
+        match mcts.as_mut() {
+            Some(mcts) => mcts.advance(index),
+            None => {
+                //let rng = SmallRng::seed_from_u64(12345);
+                let rng = SmallRng::from_entropy();
+                let mut worker = MCTSWorker::with_selection(pos, selection.clone(), rng);
+                worker.set_rollout_policy(policy);
+                worker.set_priors(priors);
+                worker.set_final_move(final_move);
+                mcts = Some(worker);
+            },
+        }
+        let mcts = mcts.as_mut().unwrap();
 
         let now = Instant::now();
         //let manager = Manager::from_position(pos);
         //let res = manager.search_fixed_time(100);
         //let idx = res.best_move;
-        //let rng = SmallRng::seed_from_u64(12345);
-        let rng = SmallRng::from_entropy();
-        let mut mcts = MCTSWorker::new(pos, c, rng);
-        let (res, n_rollouts) = mcts.go(100);
+        let (res, n_rollouts) = mcts.go(MCTSLimits::movetime(MOVE_MILLIS), Arc::new(AtomicBool::new(false)));
         let idx = res.best_move;
         let eval = res.value;
 
         let elapsed = now.elapsed();
         //eprintln!("elapsed: {} ms. move: {}, eval: {}", elapsed.as_millis(), res.best_move, res.eval);
-        let col = ((idx/9) % 3)*3 + (idx % 3);
-        let row = ((idx/9) / 3)*3 + (idx % 9)/3;
+        let square = Square::from_idx(idx).unwrap();
         pos.make_move(idx);
+        mcts.advance(idx);
         println!(
             "{} {} {}/{}",
-            row,
-            col,
+            square.row(),
+            square.col(),
             eval,
             n_rollouts,
         );
@@ -2,9 +2,37 @@
 pub type Score = f32;
 
 // don't exceed these pls
-pub(crate) const SCORE_LOSS: f32 = -1e6;
 pub(crate) const SCORE_WIN: f32 = 1e6;
 pub(crate) const SCORE_NEG_INF: f32 = -1e7;
 pub(crate) const SCORE_POS_INF: f32 = 1e7;
 
 pub(crate) const MAX_SEARCH_PLIES: u16 = 40;
+
+// default size of the transposition table, in megabytes
+pub(crate) const DEFAULT_TT_MB: usize = 16;
+
+// initial half-width of the aspiration window around the previous score.
+// Once the window grows past the max, the search falls back to a full window
+pub(crate) const ASPIRATION_WINDOW: Score = 5.0;
+pub(crate) const ASPIRATION_MAX_WINDOW: Score = 200.0;
+
+// how often (in nodes) each worker checks the clock and the node budget
+pub(crate) const NODE_POLL_INTERVAL: u64 = 1024;
+
+// time that is never used up when searching on a clock, to account for
+// overhead and communication
+pub(crate) const CLOCK_MARGIN_MILLIS: u64 = 50;
+
+// how often (in rollouts) MCTSWorker checks the clock and the stop flag
+pub(crate) const MCTS_POLL_INTERVAL: u32 = 16;
+
+// z of the confidence intervals of MCTS root moves, 95% two-sided
+pub(crate) const MCTS_CONFIDENCE_Z: f32 = 1.96;
+
+// how far past the rollout and time limits the RobustMax final move
+// criterion may search for its moves to agree, as a fraction of the limit
+pub(crate) const MCTS_ROBUST_EXTENSION: f32 = 0.25;
+
+// the share of MCTSWorker's memory cap that pruning the tree leaves it at,
+// so that it doesn't have to prune again right away
+pub(crate) const MCTS_PRUNE_TARGET: f32 = 0.75;
@@ -2,7 +2,8 @@ use crate::engine::config::*;
 use crate::engine::utils::*;
 use crate::moves::*;
 
-pub type EvalFn = fn(&Position) -> Score;
+// the static evaluation used by alpha-beta, for the side to move
+pub type EvalFn<G = Position> = fn(&G) -> Score;
 
 // 262144 = 2^18
 // note that this is not optimal size (<< 3^9) but it's a hassle
@@ -110,12 +111,26 @@ pub fn eval_block(x_occ: B33, o_occ: B33) -> Score {
     }
 }
 
+#[inline(always)]
+fn bool_to_occ(filled: bool) -> B33 {
+    if filled { BLOCK_OCC } else { 0 }
+}
+
 // NOTE: called when pos is not won/lost/drawn; may not work
 // correctly otherwise, and no checks are performed
 pub fn eval(pos: &Position) -> Score {
     let side2move = side_multiplier(pos.to_move);
     let mut ret: Score = 0.0;
+    // open won blocks still hold both sides' pieces, so score them as if
+    // they were closed
+    let open = pos.ruleset.won_blocks.is_open();
+    let captured = [pos.bitboards[0].captured_occ(), pos.bitboards[1].captured_occ()];
     for bi in 0..9 {
+        if open && (captured[0] | captured[1]) & (1 << bi) != 0 {
+            let x_owns = captured[0] & (1 << bi) != 0;
+            ret += eval_block(bool_to_occ(x_owns), bool_to_occ(!x_owns));
+            continue;
+        }
         ret += eval_block(
             pos.bitboards[0].get_block(bi),
             pos.bitboards[1].get_block(bi),
@@ -134,6 +149,36 @@ pub fn eval(pos: &Position) -> Score {
     */
 }
 
+// how much mov changes eval for the side to move, looking only at its
+// block and, if it wins the block, the big board. Tied blocks that count
+// for both sides are scored as if nobody had them
+pub fn eval_move(pos: &Position, mov: Idx) -> Score {
+    let bi = mov / 9;
+    let captured = [pos.bitboards[0].captured_occ(), pos.bitboards[1].captured_occ()];
+    if pos.ruleset.won_blocks.is_open() && (captured[0] | captured[1]) & (1 << bi) != 0 {
+        // scored as already closed, whatever goes in it
+        return 0.0;
+    }
+    let me = pos.to_move as usize;
+    let mut before = [pos.bitboards[0].get_block(bi), pos.bitboards[1].get_block(bi)];
+    let mut after = before;
+    after[me] |= 1 << (mov % 9);
+    let won = get_block_won(after[me]);
+    if won {
+        // scored like eval does once the block is filled in
+        after = [0, 0];
+        after[me] = BLOCK_OCC;
+    }
+    let mut ret = eval_block(after[0], after[1]) - eval_block(before[0], before[1]);
+    if won {
+        before = captured;
+        after = captured;
+        after[me] |= 1 << bi;
+        ret += (eval_block(after[0], after[1]) - eval_block(before[0], before[1])) * BIG_SCORE_MULT;
+    }
+    return ret * side_multiplier(pos.to_move);
+}
+
 pub fn basic_eval(pos: &Position) -> Score {
     let side2move = side_multiplier(pos.to_move);
     return (pos.bitboards[0].captured_occ().count_ones() as Score
@@ -1,142 +1,814 @@
+use crate::engine::config::*;
 use crate::engine::utils::*;
 use crate::engine::eval::*;
+use crate::engine::rollout::*;
+use crate::engine::selection::*;
+use crate::game::*;
 use crate::moves::*;
-use rand::Rng;
-use std::time::Instant;
+use rand::{Rng, SeedableRng};
+use crate::format::ParseError;
+use rand::rngs::SmallRng;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 type Value = f32;
 type NodeIdx = u32;
 
-pub struct MCTSResult {
-    pub best_move: Idx,
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MCTSResult<M = Idx> {
+    pub best_move: M,
     pub value: Value,
+    // whether value is the game-theoretic value, found by the solver
+    pub proven: bool,
 }
 
-// a Monte-Carlo Tree Node
-struct TreeNode {
-    position: Position,
-    children: Vec<NodeIdx>,
+// when MCTSWorker::go stops. Any combination of limits can be given, like
+// for SearchLimits; the search stops at whichever is hit first, or when the
+// stop flag passed to go is set
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MCTSLimits {
+    pub movetime_millis: Option<u64>,
+    // over all threads, split evenly between them
+    pub rollouts: Option<u32>,
+    // nodes in each tree, counting the ones kept by advance. Nodes are
+    // added a whole expansion at a time, so a tree can go a bit over
+    pub nodes: Option<usize>,
+    // the same, in megabytes
+    pub memory_mb: Option<usize>,
+    // stop once the most visited root move can't be overtaken in the
+    // rollouts that the time or rollout limit leaves. Only for the
+    // MaxVisits and RobustMax final move criteria, as the others don't go
+    // by visits
+    pub early_stop: bool,
+}
+
+impl MCTSLimits {
+    pub fn movetime(millis: u64) -> MCTSLimits {
+        MCTSLimits { movetime_millis: Some(millis), ..MCTSLimits::default() }
+    }
+
+    pub fn rollouts(rollouts: u32) -> MCTSLimits {
+        MCTSLimits { rollouts: Some(rollouts), ..MCTSLimits::default() }
+    }
+
+    pub fn nodes(nodes: usize) -> MCTSLimits {
+        MCTSLimits { nodes: Some(nodes), ..MCTSLimits::default() }
+    }
+
+    pub fn memory(memory_mb: usize) -> MCTSLimits {
+        MCTSLimits { memory_mb: Some(memory_mb), ..MCTSLimits::default() }
+    }
+
+    // only stops through the stop flag
+    pub fn infinite() -> MCTSLimits {
+        MCTSLimits::default()
+    }
+
+    pub fn with_early_stop(self) -> MCTSLimits {
+        MCTSLimits { early_stop: true, ..self }
+    }
+}
+
+// how much the static evaluation steers the tree. Each new node gets a
+// prior value from Game::evaluate, which counts as visits rollouts' worth of
+// results (in the exploration term too, so unvisited children are no longer
+// all tried before any is tried twice), and bias adds a progressive bias of
+// bias * prior / (n + 1) to the selection score, which fades as the node is
+// visited. Both are off by default, which is plain UCT
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Priors {
+    pub visits: f32,
+    pub bias: f32,
+}
+
+impl Priors {
+    pub fn is_off(&self) -> bool {
+        self.visits == 0.0 && self.bias == 0.0
+    }
+}
+
+// a Monte-Carlo Tree Node. Nodes don't keep their position, which is
+// replayed from the root on the way down: every rollout adds a whole
+// expansion of nodes, so they are kept small
+#[derive(Clone)]
+struct TreeNode<G: Game> {
+    // the move that led here from the parent, default at the root
+    mov: G::Move,
+    // the children are all_nodes[first_child..first_child + n_children],
+    // none for a leaf
+    n_children: u16,
+    first_child: NodeIdx,
     n: f32,  // number of times visited this node
     value: Value,
+    // what the static evaluation makes of the position, as a value. Only
+    // set when priors are on
+    prior: Value,
+    // the game-theoretic value, once known. Set for finished games when
+    // they are first visited, and by update_proof for the nodes above them
+    proven: Option<Value>,
 }
 
-impl TreeNode {
-    fn new(pos: Position) -> TreeNode {
-        //let mult = side_multiplier(pos.to_move);
-        //let score = 0.5 + mult * eval(&pos) / 304.0;
+// what only some selection policies need. The worker keeps these next to
+// its nodes, at the same index, only if the policy uses them
+#[derive(Copy, Clone, Debug, Default)]
+struct ExtraStats {
+    // of the squared results, for the variance
+    sq_sum: Value,
+    // see ChildInfo
+    amaf_n: f32,
+    amaf_value: Value,
+}
+
+impl<G: Game> TreeNode<G> {
+    // a node that hasn't been visited, and isn't known to end the game yet
+    fn new(mov: G::Move) -> TreeNode<G> {
         TreeNode {
-            position: pos,
-            children: Vec::new(),
+            mov: mov,
+            n_children: 0,
+            first_child: 0,
             n: 0.0,
             value: 0.0,
+            prior: 0.5,
+            proven: None,
         }
     }
+
+    // the node for pos, found by making mov
+    fn with_position(pos: &G, mov: G::Move) -> TreeNode<G> {
+        return TreeNode { proven: game_value(pos), ..TreeNode::new(mov) };
+    }
+
+    fn with_prior(pos: &G, mov: G::Move) -> TreeNode<G> {
+        let prior = match game_value(pos) {
+            Some(value) => value,
+            None => {
+                let score = pos.evaluate() * side_multiplier(pos.side_to_move());
+                (0.5 + score / get_double_max_score()).clamp(0.0, 1.0)
+            },
+        };
+        return TreeNode { prior: prior, ..TreeNode::with_position(pos, mov) };
+    }
+
+    #[inline]
+    fn children(&self) -> std::ops::Range<usize> {
+        let first = self.first_child as usize;
+        return first..first + self.n_children as usize;
+    }
 }
 
-/* TODO */
-pub struct MCTSWorker<R: Rng> {
-    all_nodes: Vec<TreeNode>,
-    c: Value, // C parameter
+// the result of a finished game, as a value
+fn game_value<G: Game>(pos: &G) -> Option<Value> {
+    if pos.is_over() {
+        return Some(0.5 + 0.5 * result_sign(pos.get_result()));
+    }
+    return None;
+}
+
+// how the move to play is picked from the children of a node once the
+// search is done, see choose_child. Proven wins always come first and
+// proven losses last
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FinalMoveCriterion {
+    // the most visited child, which is what UCT converges to
+    #[default]
+    MaxVisits,
+    // the child with the best mean result. Noisy for children with few visits
+    MaxValue,
+    // the most visited child, when it also has the best value. When it
+    // hasn't, go searches on (see MCTS_ROBUST_EXTENSION) for them to agree,
+    // and takes the most visited one if they still don't
+    RobustMax,
+    // the child with the best lower end of its confidence interval
+    SecureChild,
+}
+
+impl fmt::Display for FinalMoveCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FinalMoveCriterion::MaxVisits => write!(f, "max-visits"),
+            FinalMoveCriterion::MaxValue => write!(f, "max-value"),
+            FinalMoveCriterion::RobustMax => write!(f, "robust-max"),
+            FinalMoveCriterion::SecureChild => write!(f, "secure-child"),
+        }
+    }
+}
+
+impl FromStr for FinalMoveCriterion {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<FinalMoveCriterion, ParseError> {
+        match repr.trim() {
+            "max-visits" => Ok(FinalMoveCriterion::MaxVisits),
+            "max-value" => Ok(FinalMoveCriterion::MaxValue),
+            "robust-max" => Ok(FinalMoveCriterion::RobustMax),
+            "secure-child" => Ok(FinalMoveCriterion::SecureChild),
+            other => Err(ParseError::expected(0, "'max-visits', 'max-value', 'robust-max' or 'secure-child'", other)),
+        }
+    }
+}
+
+// what the search found out about a move, adding up the trees of all
+// threads at the root. Values are from X's point of view, and the
+// confidence interval is the Wilson score interval around value (see
+// MCTS_CONFIDENCE_Z), counting a draw as half a win. A proven move's
+// interval is just its proven value
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MoveStats<M = Idx> {
+    pub mov: M,
+    pub visits: f32,
+    // the mean result of the rollouts through the move
+    pub value: Value,
+    pub ci_low: Value,
+    pub ci_high: Value,
+    // the game-theoretic value, once the solver knows it
+    pub proven: Option<Value>,
+}
+
+impl<M: Copy> MoveStats<M> {
+    pub fn new(mov: M, visits: f32, value: Value, proven: Option<Value>) -> MoveStats<M> {
+        let (ci_low, ci_high) = match proven {
+            Some(value) => (value, value),
+            None => wilson_interval(value, visits),
+        };
+        MoveStats {
+            mov: mov,
+            visits: visits,
+            value: value,
+            ci_low: ci_low,
+            ci_high: ci_high,
+            proven: proven,
+        }
+    }
+
+    fn result(&self) -> MCTSResult<M> {
+        MCTSResult {
+            best_move: self.mov,
+            value: self.proven.unwrap_or(self.value),
+            proven: self.proven.is_some(),
+        }
+    }
+}
+
+// of a mean result p over n rollouts. Nothing is known without rollouts
+fn wilson_interval(p: Value, n: f32) -> (Value, Value) {
+    if n == 0.0 {
+        return (0.0, 1.0);
+    }
+    let z2 = MCTS_CONFIDENCE_Z * MCTS_CONFIDENCE_Z;
+    let denom = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denom;
+    let half_width = MCTS_CONFIDENCE_Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denom;
+    return (f32::max(0.0, center - half_width), f32::min(1.0, center + half_width));
+}
+
+// every root move at the end of a search, most visited first. Display
+// prints one line per move
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MCTSReport<M = Idx> {
+    pub side_to_move: Side,
+    pub moves: Vec<MoveStats<M>>,
+}
+
+impl<M: fmt::Debug> fmt::Display for MCTSReport<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} to move, values for X", self.side_to_move)?;
+        for stats in &self.moves {
+            write!(
+                f,
+                "\n{:?}: {} visits, value {:.3} [{:.3}, {:.3}]",
+                stats.mov, stats.visits, stats.value, stats.ci_low, stats.ci_high,
+            )?;
+            if let Some(value) = stats.proven {
+                write!(f, ", proven {}", value)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+// a value from X's point of view turned into the point of view of the side
+// with multiplier mult, or back: 1 is a win for that side
+fn for_side(value: Value, mult: f32) -> Value {
+    0.5 + (value - 0.5) * mult
+}
+
+// the child to play for the side with multiplier mult: a proven win if
+// there is one, otherwise the best child by the criterion that isn't a
+// proven loss. Ties go to the first child
+fn choose_child<M>(children: &[MoveStats<M>], mult: f32, criterion: FinalMoveCriterion) -> Option<usize> {
+    /* NOTE score is for determining which node to select as best,
+    while value is the supposed value of the node. One can have
+    a different score and value */
+    let rank = |child: &MoveStats<M>| {
+        let value = match child.proven {
+            Some(value) => for_side(value, mult),
+            None => 0.5,
+        };
+        if value == 1.0 {
+            return 2;
+        }
+        if value == 0.0 {
+            return 0;
+        }
+        return 1;
+    };
+    let score = |child: &MoveStats<M>| {
+        if child.visits == 0.0 && child.proven.is_none() {
+            // nothing to go by
+            return f32::NEG_INFINITY;
+        }
+        match criterion {
+            FinalMoveCriterion::MaxVisits | FinalMoveCriterion::RobustMax => child.visits,
+            FinalMoveCriterion::MaxValue => for_side(child.proven.unwrap_or(child.value), mult),
+            FinalMoveCriterion::SecureChild => f32::min(for_side(child.ci_low, mult), for_side(child.ci_high, mult)),
+        }
+    };
+    let mut best: Option<usize> = None;
+    for (i, child) in children.iter().enumerate() {
+        let better = match best {
+            Some(best) => (rank(child), score(child)) > (rank(&children[best]), score(&children[best])),
+            None => true,
+        };
+        if better {
+            best = Some(i);
+        }
+    }
+    return best;
+}
+
+// UCT search on any Game, Position by default. Values are from X's point
+// of view: 1 is a win for X. Finished games deep in the tree are solved
+// MCTS-Solver style: see update_proof. The selection policy is a type
+// parameter rather than a trait object, since it's asked about every child
+// on the way down
+pub struct MCTSWorker<R: Rng, G: Game = Position, S: SelectionPolicy = Ucb1> {
+    // the position at all_nodes[0]
+    root: G,
+    all_nodes: Vec<TreeNode<G>>,
+    // empty unless the selection policy uses them, see keeps_extra
+    extra: Vec<ExtraStats>,
+    selection: S,
     rng: R,
+    // see set_threads
+    threads: usize,
+    rollout_policy: RolloutPolicy,
+    priors: Priors,
+    final_move: FinalMoveCriterion,
+    // the root moves as of the last go, over all threads. Empty before
+    // that, and once the root changes
+    root_stats: Vec<MoveStats<G::Move>>,
+    // see set_memory_cap
+    memory_cap_mb: Option<usize>,
 }
 
-impl<R: Rng> MCTSWorker<R> {
-    pub fn new(pos: Position, c: Value, rng: R) -> MCTSWorker<R> {
-        let mut worker = MCTSWorker::<R> {
+impl<R: Rng, G: Game> MCTSWorker<R, G> {
+    // UCB1 with c as the exploration constant
+    pub fn new(pos: G, c: Value, rng: R) -> MCTSWorker<R, G> {
+        return MCTSWorker::with_selection(pos, Ucb1 { c: c }, rng);
+    }
+}
+
+impl<R: Rng, G: Game, S: SelectionPolicy> MCTSWorker<R, G, S> {
+    // see selection.rs for the policies
+    pub fn with_selection(pos: G, selection: S, rng: R) -> MCTSWorker<R, G, S> {
+        let mut worker = MCTSWorker::<R, G, S> {
+            root: pos.clone(),
             all_nodes: Vec::new(),
-            c: c,
+            extra: Vec::new(),
+            selection: selection,
             rng: rng,
+            threads: 1,
+            rollout_policy: RolloutPolicy::Uniform,
+            priors: Priors::default(),
+            final_move: FinalMoveCriterion::MaxVisits,
+            root_stats: Vec::new(),
+            memory_cap_mb: None,
         };
-        let root = TreeNode::new(pos);
-        worker.all_nodes.push(root);
+        worker.set_root(pos);
         return worker;
     }
 
-    pub fn go(&mut self, millis: u64) -> (MCTSResult, u32) {
-        let now = Instant::now();
-        // rollout once on root position to initialize the tree
-        let mut n_rollouts = 0;
+    // a tree of just pos
+    fn set_root(&mut self, pos: G) {
+        self.all_nodes = vec![TreeNode::with_position(&pos, G::Move::default())];
+        self.root = pos;
+        self.root_stats.clear();
+        self.extra.clear();
+        if self.keeps_extra() {
+            self.extra.push(ExtraStats::default());
+        }
+    }
+
+    #[inline]
+    fn keeps_extra(&self) -> bool {
+        self.selection.uses_variance() || self.selection.uses_amaf()
+    }
+
+    // nodes in the tree, including the root
+    pub fn n_nodes(&self) -> usize {
+        self.all_nodes.len()
+    }
+
+    // what a node takes up, with its share of the side table
+    fn node_bytes(&self) -> usize {
+        let extra = if self.keeps_extra() { std::mem::size_of::<ExtraStats>() } else { 0 };
+        return std::mem::size_of::<TreeNode<G>>() + extra;
+    }
+
+    // what the tree takes up, counting the room set aside for it to grow
+    pub fn memory_bytes(&self) -> usize {
+        let extra = self.extra.capacity() * std::mem::size_of::<ExtraStats>();
+        return self.all_nodes.capacity() * std::mem::size_of::<TreeNode<G>>() + extra;
+    }
+
+    // the most nodes a tree kept under cap_bytes may have. all_nodes gets
+    // that much room up front (see reserve_nodes), and while prune compacts
+    // it, a copy with as much room and an index per node is there too
+    fn capped_nodes(&self, cap_bytes: usize) -> usize {
+        return cap_bytes / (2 * self.node_bytes() + std::mem::size_of::<usize>());
+    }
+
+    // room for capacity nodes, so that the tree doesn't grow by doubling
+    // past it
+    fn reserve_nodes(&mut self, capacity: usize) {
+        self.all_nodes.reserve_exact(capacity.saturating_sub(self.all_nodes.len()));
+        if self.keeps_extra() {
+            self.extra.reserve_exact(capacity.saturating_sub(self.extra.len()));
+        }
+    }
+
+    // makes mov on the root, keeping the subtree under it and dropping the
+    // rest, so that the next search starts from what was learnt about the
+    // new position. Call it with both our moves and the opponent's
+    pub fn advance(&mut self, mov: G::Move) {
+        debug_assert!(self.root.legal_moves().contains(mov));
+        let mut pos = self.root.clone();
+        pos.make_move(mov);
+        match self.find_child(0, mov) {
+            Some(root) => {
+                self.compact(root, f32::NEG_INFINITY, 0);
+                self.root = pos;
+                self.root_stats.clear();
+            },
+            // never expanded, so there is nothing to keep
+            None => self.set_root(pos),
+        }
+    }
+
+    // rebuilds all_nodes from the subtree at root, which becomes
+    // all_nodes[0]. Only the nodes visited more than min_visits keep their
+    // children, and the others become leaves again, to be expanded anew.
+    // The root always keeps its children. The copy is breadth first, so
+    // that siblings stay next to each other like after expansion, and gets
+    // room for capacity nodes, which it mustn't go over
+    fn compact(&mut self, root: usize, min_visits: f32, capacity: usize) {
+        let old_nodes = std::mem::take(&mut self.all_nodes);
+        let mut new_nodes = Vec::with_capacity(capacity);
+        new_nodes.push(old_nodes[root].clone());
+        new_nodes[0].mov = G::Move::default();
+        // where each new node was
+        let mut old_idx = Vec::with_capacity(capacity);
+        old_idx.push(root);
+        let mut i = 0;
+        while i < new_nodes.len() {
+            let children = new_nodes[i].children();
+            if i == 0 || new_nodes[i].n > min_visits {
+                new_nodes[i].first_child = new_nodes.len() as NodeIdx;
+                new_nodes.extend_from_slice(&old_nodes[children.clone()]);
+                old_idx.extend(children);
+            } else {
+                new_nodes[i].n_children = 0;
+                new_nodes[i].first_child = 0;
+            }
+            i += 1;
+        }
+        drop(old_nodes);
+        self.all_nodes = new_nodes;
+        if self.keeps_extra() {
+            let mut extra = Vec::with_capacity(capacity);
+            extra.extend(old_idx.iter().map(|&idx| self.extra[idx]));
+            self.extra = extra;
+        }
+    }
+
+    // makes room by dropping the least visited subtrees, until about target
+    // nodes are left: the nodes with the most visits keep their children.
+    // A child has fewer visits than its parent, so what is kept is still a
+    // tree from the root. See compact for capacity
+    fn prune(&mut self, target: usize, capacity: usize) {
+        let mut expanded = self.all_nodes[1..].iter()
+            .filter(|node| node.n_children > 0)
+            .map(|node| (node.n, node.n_children as usize))
+            .collect::<Vec<_>>();
+        expanded.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let mut kept = 1 + self.all_nodes[0].n_children as usize;
+        let mut min_visits = f32::NEG_INFINITY;
+        for (n, n_children) in expanded {
+            if kept + n_children > target {
+                // and so do the others with as many visits
+                min_visits = n;
+                break;
+            }
+            kept += n_children;
+        }
+        self.compact(0, min_visits, capacity);
+    }
+
+    // root parallelism: every thread but this one grows a tree of its own
+    // from the root, and the visits of the root moves are added up at the
+    // end. The helper trees are thrown away after each search, so only
+    // this thread's tree is kept by advance. 0 means one thread per core
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    // how rollouts pick their moves, uniformly at random by default
+    pub fn set_rollout_policy(&mut self, policy: RolloutPolicy) {
+        self.rollout_policy = policy;
+    }
+
+    // only nodes created from now on get a prior, so set them before
+    // searching
+    pub fn set_priors(&mut self, priors: Priors) {
+        self.priors = priors;
+    }
+
+    // how go and pv pick their moves, the most visited by default
+    pub fn set_final_move(&mut self, criterion: FinalMoveCriterion) {
+        self.final_move = criterion;
+    }
+
+    // keeps the trees of all threads together under memory_mb megabytes
+    // for as long as the search goes on, by dropping the least visited
+    // subtrees whenever one fills up (see MCTS_PRUNE_TARGET). Each tree gets
+    // its share up front. Unlike MCTSLimits::memory, which ends the search
+    // there. None, the default, lets the trees grow
+    pub fn set_memory_cap(&mut self, memory_mb: Option<usize>) {
+        self.memory_cap_mb = memory_mb;
+    }
+
+    // the rollouts on this worker's tree, until a limit is hit. The limits
+    // are only looked at once the root has children, so that there is
+    // always a move to return. decided is set on an early stop, which ends
+    // the other threads' searches too. cap_bytes is this tree's share of
+    // the memory cap
+    fn search(&mut self, limits: &MCTSLimits, max_rollouts: Option<u32>, cap_bytes: Option<usize>, stop: &AtomicBool, decided: &AtomicBool, start: Instant) -> u32 {
+        let deadline = limits.movetime_millis.map(|millis| start + Duration::from_millis(millis));
+        // how far RobustMax may go past the rollout and time limits, see
+        // unsettled
+        let extension = if self.final_move == FinalMoveCriterion::RobustMax { MCTS_ROBUST_EXTENSION } else { 0.0 };
+        let extended_rollouts = max_rollouts.map(|max| max + (max as f32 * extension) as u32);
+        let extended_deadline = limits.movetime_millis.map(|millis| {
+            start + Duration::from_millis(millis + (millis as f32 * extension) as u64)
+        });
+        let node_size = self.node_bytes();
+        let max_nodes = std::cmp::min(
+            limits.nodes.unwrap_or(usize::MAX),
+            limits.memory_mb.map_or(usize::MAX, |mb| mb * 1024 * 1024 / node_size),
+        );
+        let capacity = cap_bytes.map(|bytes| self.capped_nodes(bytes));
+        if let Some(capacity) = capacity {
+            self.reserve_nodes(capacity);
+        }
+        // past this, the next expansion could go over the capacity
+        let prune_at = capacity.map(|capacity| capacity.saturating_sub(self.root.n_move_indices()));
+        let mut n_rollouts: u32 = 0;
+        // rollouts since the clock and the flags were last looked at
+        let mut unpolled = MCTS_POLL_INTERVAL;
         loop {
-            if n_rollouts % 500 == 0 {
-                if now.elapsed().as_millis() as u64 > millis - 20 {
-                    return (self.get_best(), n_rollouts);
+            if self.all_nodes[0].proven.is_some() {
+                // nothing left to find out, for any thread
+                decided.store(true, Ordering::Relaxed);
+                return n_rollouts;
+            }
+            if self.all_nodes[0].n_children > 0 {
+                let out_of_rollouts = match (max_rollouts, extended_rollouts) {
+                    (Some(max), Some(extended)) => n_rollouts >= extended || (n_rollouts >= max && !self.unsettled()),
+                    _ => false,
+                };
+                if out_of_rollouts || self.all_nodes.len() >= max_nodes {
+                    return n_rollouts;
+                }
+                if unpolled >= MCTS_POLL_INTERVAL {
+                    unpolled = 0;
+                    if stop.load(Ordering::Relaxed) || decided.load(Ordering::Relaxed) {
+                        return n_rollouts;
+                    }
+                    let now = Instant::now();
+                    let out_of_time = match (deadline, extended_deadline) {
+                        (Some(deadline), Some(extended)) => now >= extended || (now >= deadline && !self.unsettled()),
+                        _ => false,
+                    };
+                    if out_of_time {
+                        return n_rollouts;
+                    }
+                    let by_visits = matches!(self.final_move, FinalMoveCriterion::MaxVisits | FinalMoveCriterion::RobustMax);
+                    if limits.early_stop && by_visits {
+                        // as many rollouts as are left at the current rate
+                        let time_left = deadline.map(|deadline| {
+                            let rate = n_rollouts as f64 / f64::max((now - start).as_secs_f64(), 1e-6);
+                            (rate * deadline.saturating_duration_since(now).as_secs_f64()) as u32
+                        });
+                        let rollouts_left = max_rollouts.map(|max| max.saturating_sub(n_rollouts));
+                        let left = match (time_left, rollouts_left) {
+                            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+                            (a, b) => a.or(b),
+                        };
+                        if let Some(left) = left {
+                            if self.root_decided(left) && !self.unsettled() {
+                                decided.store(true, Ordering::Relaxed);
+                                return n_rollouts;
+                            }
+                        }
+                    }
+                }
+            }
+            if let (Some(prune_at), Some(capacity)) = (prune_at, capacity) {
+                if self.all_nodes.len() > prune_at {
+                    self.prune((prune_at as f32 * MCTS_PRUNE_TARGET) as usize, capacity);
                 }
             }
-            self.treewalk(0);
+            self.treewalk();
             n_rollouts += 1;
+            unpolled += 1;
         }
     }
 
-    fn treewalk(&mut self, mut idx: NodeIdx) {
+    // whether the most visited root move stays so after rollouts_left more
+    fn root_decided(&self, rollouts_left: u32) -> bool {
+        let mut visits = self.child_stats(0).iter().map(|child| child.visits).collect::<Vec<_>>();
+        if visits.len() < 2 {
+            return true;
+        }
+        visits.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        return visits[0] - visits[1] > rollouts_left as f32;
+    }
+
+    // whether RobustMax's most visited root move and the one with the best
+    // value are different ones on this tree, so that it's worth searching on
+    fn unsettled(&self) -> bool {
+        if self.final_move != FinalMoveCriterion::RobustMax {
+            return false;
+        }
+        let children = self.child_stats(0);
+        let mult = side_multiplier(self.root.side_to_move());
+        return choose_child(&children, mult, FinalMoveCriterion::MaxVisits)
+            != choose_child(&children, mult, FinalMoveCriterion::MaxValue);
+    }
+
+    // one rollout from the root. The position of each node on the way is
+    // replayed from the root's
+    fn treewalk(&mut self) {
+        let mut pos = self.root.clone();
+        let mut idx: usize = 0;
+        // with the side to move at each
         let mut explored_nodes = Vec::new();
         loop {
-            let len = self.all_nodes.len();
-            let node = &self.all_nodes[idx as usize];
-            let localpos = node.position;
-            explored_nodes.push(idx);
-            if node.n == 0.0 {
-                /*
-                let node = &mut self.all_nodes[idx as usize];
-                node.value = 0.5 + eval(&localpos) * side_multiplier(localpos.to_move) / get_double_max_score();
-                node.n = 1.0;
-                */
+            let side = pos.side_to_move();
+            explored_nodes.push((idx as NodeIdx, side));
+            let node = &self.all_nodes[idx];
+            if node.n == 0.0 && node.proven.is_none() {
+                // a child is only looked at once it's visited
+                self.all_nodes[idx].proven = game_value(&pos);
+            }
+            let node = &self.all_nodes[idx];
+            if let Some(r) = node.proven {
+                // no need to guess, whether the game is over or solved
+                if self.selection.uses_amaf() {
+                    self.update_amaf(&explored_nodes, &[], r);
+                }
+                self.backpropagate(r, explored_nodes);
+                return;
+            }
+            // the root is expanded right away, so that every rollout goes
+            // through exactly one root move
+            if node.n == 0.0 && idx != 0 {
                 break;
             }
-            if node.children.len() == 0 {
+            if node.n_children == 0 {
                 // is leaf node
-                /* begin mut borrow of node */
-                if localpos.is_won(localpos.to_move.other()) || localpos.is_drawn() {
-                    // early end; since game is already over we can directly take its value
-                    let r = node.value;
-                    self.backpropagate(r, explored_nodes);
-                    return;
-                }
-
-                /* do mutable borrow here since need to modify children */
-                let node = &mut self.all_nodes[idx as usize];
-                // expand
-                let moves = localpos.legal_moves();
-                for i in 0..moves.size() {
-                    node.children.push((len + i) as NodeIdx);
-                }
-                /* end mut borrow of node */
-
-                for mov in moves {
-                    let mut newpos = localpos;
-                    newpos.make_move(mov);
-                    self.all_nodes.push(TreeNode::new(newpos));
-                }
-                /* re-borrow nodes to set moves */
-                let node = &mut self.all_nodes[idx as usize];
-                idx = node.children[0];
+                self.expand(idx, &pos);
+                idx = self.all_nodes[idx].first_child as usize;
             } else {
-                explored_nodes.push(idx);
                 // find best child
-                let best_idx = self.select_move(node, self.c);
-                idx = node.children[best_idx] as NodeIdx;
+                idx = node.first_child as usize + self.select_move(node, side_multiplier(side));
             }
+            pos.make_move(self.all_nodes[idx].mov);
+        }
+        let mut played = Vec::new();
+        let r = self.rollout(pos, &mut played);
+        if self.selection.uses_amaf() {
+            self.update_amaf(&explored_nodes, &played, r);
         }
-        let localpos = (&self.all_nodes[idx as usize]).position;
-        let r = self.rollout(localpos);
         self.backpropagate(r, explored_nodes);
     }
 
-    // returns the best move index and the corresponding node index
-    fn select_move(&self, node: &TreeNode, c: f32) -> usize {
-        let mut best: f32 = std::f32::NEG_INFINITY;
+    // gives the leaf at idx, whose position is pos, its children. They are
+    // just moves until they are visited, unless priors are needed, which
+    // takes their positions. They are all created here, since a move-only
+    // node is a few bytes: children() and select_move need siblings next to
+    // each other, and adding them one at a time would take a child list or
+    // slots set aside per node, which cost about as much. What waits for
+    // the first visit is making the move and looking at the position
+    fn expand(&mut self, idx: usize, pos: &G) {
+        let moves = pos.legal_moves();
+        let first_child = self.all_nodes.len();
+        let eager = !self.priors.is_off() || self.selection.uses_priors();
+        for mov in moves {
+            let child = if eager {
+                let mut newpos = pos.clone();
+                newpos.make_move(mov);
+                TreeNode::with_prior(&newpos, mov)
+            } else {
+                TreeNode::new(mov)
+            };
+            self.all_nodes.push(child);
+        }
+        if self.keeps_extra() {
+            self.extra.resize(self.all_nodes.len(), ExtraStats::default());
+        }
+        let node = &mut self.all_nodes[idx];
+        node.first_child = first_child as NodeIdx;
+        node.n_children = moves.size() as u16;
+    }
+
+    // the index among node's children of the child to walk down to, by the
+    // selection policy and the priors. mult is for the side to move at node
+    fn select_move(&self, node: &TreeNode<G>, mult: f32) -> usize {
+        let mut best: f32 = f32::NEG_INFINITY;
         let mut best_idx: usize = 300;
         
-        debug_assert!(node.n >= 2.0);
-        let ln = natural_log(node.n);
-        let mult = side_multiplier(node.position.to_move); // TODO move this outside the loop
+        debug_assert!(node.n >= 1.0);
+        let parent = ParentInfo {
+            n: node.n,
+            // other nodes have been visited twice once they have children,
+            // but the root is expanded on its first visit. ln 1 would leave
+            // the unvisited children's exploration term at 0 / 0
+            ln_n: natural_log(f32::max(node.n, 2.0)),
+        };
+        let priors = self.priors;
+        let selection = &self.selection;
+        let children = &self.all_nodes[node.children()];
+        // never worth playing. There is always another child, or this node
+        // would be a proven loss too
+        let pruned = |child: &TreeNode<G>| match child.proven {
+            Some(value) => for_side(value, mult) == 0.0,
+            None => false,
+        };
+        let mut prior_sum = 0.0;
+        if selection.uses_priors() {
+            for child in children {
+                if !pruned(child) {
+                    prior_sum += for_side(child.prior, mult);
+                }
+            }
+        }
         
-        for i in 0..node.children.len() {
-            let child = &self.all_nodes[node.children[i] as usize];
-            let n = child.n as Value;
-            let ucb = child.value * mult + c * (ln / n).sqrt();
-            debug_assert!(!ucb.is_nan());
-            if ucb > best {
-                best = ucb;
+        for (i, child) in children.iter().enumerate() {
+            if pruned(child) {
+                continue;
+            }
+            let extra = if self.keeps_extra() {
+                self.extra[node.first_child as usize + i]
+            } else {
+                ExtraStats::default()
+            };
+            let mut info = ChildInfo {
+                n: child.n,
+                value: for_side(child.value, mult),
+                variance: f32::max(0.0, extra.sq_sum / child.n - child.value * child.value),
+                prior: 1.0 / children.len() as f32,
+                amaf_n: extra.amaf_n,
+                amaf_value: for_side(extra.amaf_value, mult),
+            };
+            if child.n == 0.0 {
+                info.value = 0.5;
+            }
+            if priors.visits > 0.0 {
+                // the prior counts as that many results already in, so
+                // unvisited children are no longer tried first
+                info.n = child.n + priors.visits;
+                info.value = for_side((child.value * child.n + child.prior * priors.visits) / info.n, mult);
+            }
+            if prior_sum > 0.0 {
+                info.prior = for_side(child.prior, mult) / prior_sum;
+            }
+            let mut score = selection.score(&parent, &info);
+            if priors.bias != 0.0 {
+                score += priors.bias * for_side(child.prior, mult) / (child.n + 1.0);
+            }
+            debug_assert!(!score.is_nan());
+            if score > best {
+                best = score;
                 best_idx = i;
             }
         }
@@ -144,90 +816,225 @@ impl<R: Rng> MCTSWorker<R> {
         return best_idx;
     }
 
-    fn backpropagate(&mut self, r: Value, explored_nodes: Vec<u32>) {
-        for idx in &explored_nodes {
-            let mut node = &mut self.all_nodes[*idx as usize];
+    // AMAF: every child of a node on the path gets the result if its move
+    // was played later on in the simulation, by the side to move at the
+    // node. played is the rollout's moves, by move index
+    fn update_amaf(&mut self, explored_nodes: &[(NodeIdx, Side)], played: &[(usize, Side)], r: Value) {
+        let mut first_played: Vec<Option<Side>> = vec![None; self.root.n_move_indices()];
+        for &(index, side) in played.iter().rev() {
+            first_played[index] = Some(side);
+        }
+        let mut below: Option<NodeIdx> = None;
+        for &(idx, side) in explored_nodes.iter().rev() {
+            if let Some(below) = below {
+                first_played[G::move_index(self.all_nodes[below as usize].mov)] = Some(side);
+            }
+            for child in self.all_nodes[idx as usize].children() {
+                if first_played[G::move_index(self.all_nodes[child].mov)] == Some(side) {
+                    let stats = &mut self.extra[child];
+                    stats.amaf_n += 1.0;
+                    stats.amaf_value += (r - stats.amaf_value) / stats.amaf_n;
+                }
+            }
+            below = Some(idx);
+        }
+    }
+
+    fn backpropagate(&mut self, r: Value, explored_nodes: Vec<(NodeIdx, Side)>) {
+        for &(idx, _) in &explored_nodes {
+            let node = &mut self.all_nodes[idx as usize];
             node.n += 1.0;
             node.value = node.value + (r - node.value) / (node.n as Value);
         }
+        if self.keeps_extra() {
+            for &(idx, _) in &explored_nodes {
+                self.extra[idx as usize].sq_sum += r * r;
+            }
+        }
+        // a newly proven node may prove the ones above it
+        for &(idx, side) in explored_nodes.iter().rev() {
+            if !self.update_proof(idx as usize, side_multiplier(side)) {
+                break;
+            }
+        }
+    }
+
+    // MCTS-Solver: a node is a proven win for the side to move as soon as
+    // one of its children is, and otherwise proven once all its children
+    // are, as the best of them. mult is for the side to move. Returns
+    // whether the node is proven
+    fn update_proof(&mut self, idx: usize, mult: f32) -> bool {
+        let node = &self.all_nodes[idx];
+        if node.proven.is_some() {
+            return true;
+        }
+        if node.n_children == 0 {
+            return false;
+        }
+        let mut best: Value = 0.0;
+        let mut all_proven = true;
+        for child in &self.all_nodes[node.children()] {
+            match child.proven {
+                Some(value) => best = best.max(for_side(value, mult)),
+                None => all_proven = false,
+            }
+        }
+        if best == 1.0 || all_proven {
+            self.all_nodes[idx].proven = Some(for_side(best, mult));
+            return true;
+        }
+        return false;
     }
 
-    fn rollout(&mut self, mut pos: Position) -> Value {
+    // plays pos out and returns the result. The moves go into played if
+    // the selection policy uses AMAF
+    fn rollout(&mut self, mut pos: G, played: &mut Vec<(usize, Side)>) -> Value {
+        let amaf = self.selection.uses_amaf();
         loop {
-            if pos.is_won(pos.to_move.other()) {
-                return (pos.to_move != Side::X) as i32 as Value;
-            } else if pos.is_drawn() {
-                let sign = codingame_drawn(&pos);
+            if pos.is_over() {
+                let sign = result_sign(pos.get_result());
                 return 0.5 + 0.5 * sign;
             }
-            let moves = pos.legal_moves();
-            let n_moves = moves.size();
-            let j = self.rng.gen_range(0, n_moves);
-            let mov = moves.nth_move(j as u8);
-
+            let mov = self.rollout_policy.pick_move(&pos, &mut self.rng);
+            if amaf {
+                played.push((G::move_index(mov), pos.side_to_move()));
+            }
             pos.make_move(mov);
         }
     }
 
-    fn get_best(&self) -> MCTSResult {
-        let mut best_move = NULL_IDX;
-        /* NOTE score is for determining which node to select as best,
-        while value is the supposed value of the node. One can have
-        a different score and value */
-        let mut best_score = std::f32::NEG_INFINITY;
-        let mut best_value = std::f32::NEG_INFINITY;
-        let mut i = 1;
-        for mov in self.all_nodes[0].position.legal_moves() {
-            let child = &self.all_nodes[i as usize];
-            // TODO is this a good criterion
-            let score = child.n as f32;
-            if score > best_score {
-                best_score = score;
-                best_move = mov;
-                best_value = child.value;
+    fn child_stats(&self, idx: usize) -> Vec<MoveStats<G::Move>> {
+        return self.all_nodes[self.all_nodes[idx].children()].iter().map(|child| {
+            MoveStats::new(child.mov, child.n, child.value, child.proven)
+        }).collect();
+    }
+
+    // the node index of the child of idx that mov leads to, if it's there
+    fn find_child(&self, idx: usize, mov: G::Move) -> Option<usize> {
+        return self.all_nodes[idx].children().find(|&i| self.all_nodes[i].mov == mov);
+    }
+
+    // the line the search expects from the root
+    pub fn pv(&self) -> Vec<MCTSResult<G::Move>> {
+        return self.pv_after(&[]);
+    }
+
+    // the line the search expects once moves are played from the root: at
+    // every node, the child the final move criterion picks for the side to
+    // move there. At the root, that's the move go returned. Empty if moves
+    // leave the tree, and it ends where the tree does
+    pub fn pv_after(&self, moves: &[G::Move]) -> Vec<MCTSResult<G::Move>> {
+        let mut cur = 0;
+        let mut pos = self.root.clone();
+        for &mov in moves {
+            cur = match self.find_child(cur, mov) {
+                Some(i) => i,
+                None => return Vec::new(),
+            };
+            pos.make_move(mov);
+        }
+        let mut ret = Vec::new();
+        while self.all_nodes[cur].n_children > 0 {
+            let children = if cur == 0 && !self.root_stats.is_empty() {
+                self.root_stats.clone()
+            } else {
+                self.child_stats(cur)
+            };
+            let mult = side_multiplier(pos.side_to_move());
+            let best = match choose_child(&children, mult, self.final_move) {
+                Some(best) => &children[best],
+                None => break,
+            };
+            if best.visits == 0.0 && best.proven.is_none() {
+                // a guess, not something the search found
+                break;
             }
-            i += 1;
+            ret.push(best.result());
+            cur = match self.find_child(cur, best.mov) {
+                Some(i) => i,
+                None => break,
+            };
+            pos.make_move(best.mov);
         }
-        assert!(best_move != NULL_IDX);
-        return MCTSResult {
-            best_move: best_move,
-            value: best_value,
+        return ret;
+    }
+
+    // every root move with what the last go found out about it, over all
+    // threads. Before any search, the root's children in this tree, if any
+    pub fn report(&self) -> MCTSReport<G::Move> {
+        let mut moves = if self.root_stats.is_empty() {
+            self.child_stats(0)
+        } else {
+            self.root_stats.clone()
+        };
+        moves.sort_by(|a, b| b.visits.partial_cmp(&a.visits).unwrap());
+        return MCTSReport {
+            side_to_move: self.root.side_to_move(),
+            moves: moves,
         };
     }
+}
 
-    pub fn pv(&self) -> Vec<MCTSResult> {
-        let mut cur = 0;
-        let mut ret = Vec::new();
-        while self.all_nodes[cur].children.len() != 0 {
-            let node = &self.all_nodes[cur];
-
-            let mut best_move = NULL_IDX;
-            /* NOTE score is for determining which node to select as best,
-            while value is the supposed value of the node. One can have
-            a different score and value */
-            let mut best_score = std::f32::NEG_INFINITY;
-            let mut best_value = std::f32::NEG_INFINITY;
-            let mut best_i = 0;
-            let mut i = node.children[0];
-            for mov in node.position.legal_moves() {
-                let child = &self.all_nodes[i as usize];
-                // TODO is this a good criterion
-                let score = child.n as f32;
-                if score > best_score {
-                    best_score = score;
-                    best_move = mov;
-                    best_value = child.value;
-                    best_i = i;
+impl<R: Rng, G: Game + Send + 'static, S: SelectionPolicy> MCTSWorker<R, G, S> where G::Move: Send {
+    // searches until one of the limits is hit or stop is set, on as many
+    // threads as set_threads says, or until the root is solved. Returns the
+    // root move to play (see set_final_move) and the number of rollouts.
+    // The position must not be over
+    pub fn go(&mut self, limits: MCTSLimits, stop: Arc<AtomicBool>) -> (MCTSResult<G::Move>, u32) {
+        let start = Instant::now();
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        // this thread takes what doesn't divide evenly
+        let share = limits.rollouts.map(|rollouts| rollouts / threads as u32);
+        let own_share = limits.rollouts.map(|rollouts| rollouts - rollouts / threads as u32 * (threads as u32 - 1));
+        // the memory cap is for all the trees together
+        let cap_bytes = self.memory_cap_mb.map(|mb| mb * 1024 * 1024 / threads);
+        let decided = Arc::new(AtomicBool::new(false));
+        let mut handles = Vec::new();
+        for _ in 1..threads {
+            let pos = self.root.clone();
+            let selection = self.selection.clone();
+            let (policy, priors, final_move) = (self.rollout_policy, self.priors, self.final_move);
+            let seed = self.rng.gen::<u64>();
+            let localstop = Arc::clone(&stop);
+            let localdecided = Arc::clone(&decided);
+            handles.push(std::thread::spawn(move || {
+                let mut worker = MCTSWorker::with_selection(pos, selection, SmallRng::seed_from_u64(seed));
+                worker.set_rollout_policy(policy);
+                worker.set_priors(priors);
+                worker.set_final_move(final_move);
+                let n_rollouts = worker.search(&limits, share, cap_bytes, &localstop, &localdecided, start);
+                return (worker.child_stats(0), n_rollouts);
+            }));
+        }
+
+        let mut n_rollouts = self.search(&limits, own_share, cap_bytes, &stop, &decided, start);
+        let mut children = self.child_stats(0);
+        for handle in handles {
+            let (other, other_rollouts) = handle.join().unwrap();
+            n_rollouts += other_rollouts;
+            for stats in other {
+                match children.iter_mut().find(|child| child.mov == stats.mov) {
+                    Some(child) => {
+                        let total = child.visits + stats.visits;
+                        let mut value = child.value;
+                        if total > 0.0 {
+                            value = (child.value * child.visits + stats.value * stats.visits) / total;
+                        }
+                        // proofs are the same in every tree
+                        *child = MoveStats::new(child.mov, total, value, child.proven.or(stats.proven));
+                    },
+                    None => children.push(stats),
                 }
-                i += 1;
             }
-            cur = best_i as usize;
-            assert!(best_move != NULL_IDX);
-            ret.push(MCTSResult {
-                best_move: best_move,
-                value: best_value,
-            });
         }
-        return ret;
+
+        let mult = side_multiplier(self.root.side_to_move());
+        let best = choose_child(&children, mult, self.final_move).expect("the root hasn't been expanded");
+        let res = children[best].result();
+        self.root_stats = children;
+        return (res, n_rollouts);
     }
 }
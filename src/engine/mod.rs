@@ -2,17 +2,23 @@ pub mod config;
 pub mod eval;
 pub mod utils;
 pub mod mcts;
+pub mod rollout;
+pub mod selection;
+pub mod tt;
+pub mod ordering;
 
-use std::time::{Duration};
-use std::thread;
+use std::time::{Duration, Instant};
 use std::thread::JoinHandle;
-use std::sync::{Arc};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::fmt;
 
 use crate::engine::config::*;
 use crate::engine::eval::*;
 use crate::engine::utils::*;
+use crate::engine::tt::*;
+use crate::engine::ordering::*;
+use crate::game::*;
 use crate::moves::*;
 
 // used to break out of recursion
@@ -24,164 +30,469 @@ impl fmt::Debug for StopSearch {
     }
 }
 
-// keeps track of the current search state, e.g. node count, time,
-// etc. Used across alpha_beta_dfs
-pub struct SearchResult {
-    pub best_move: Idx,
+// when to stop searching. Any combination of limits can be given; the
+// search stops at whichever is hit first, or when the stop flag passed to
+// Manager::search is set
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchLimits {
+    pub depth: Option<u16>,
+    pub movetime_millis: Option<u64>,
+    // includes quiescence nodes
+    pub nodes: Option<u64>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u16) -> SearchLimits {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+
+    pub fn movetime(millis: u64) -> SearchLimits {
+        SearchLimits { movetime_millis: Some(millis), ..SearchLimits::default() }
+    }
+
+    pub fn nodes(nodes: u64) -> SearchLimits {
+        SearchLimits { nodes: Some(nodes), ..SearchLimits::default() }
+    }
+
+    // only stops through the stop flag, or once MAX_SEARCH_PLIES is reached
+    pub fn infinite() -> SearchLimits {
+        SearchLimits::default()
+    }
+}
+
+// time left on both clocks, and the increment each side gets per move
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Clock {
+    pub x_millis: u64,
+    pub o_millis: u64,
+    pub x_inc_millis: u64,
+    pub o_inc_millis: u64,
+}
+
+// the limits as checked by the workers while searching. Shared across threads
+struct SearchBudget {
+    stop: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    // nodes searched by all workers, updated every NODE_POLL_INTERVAL nodes
+    nodes: AtomicU64,
+}
+
+// the outcome of a search, or of one completed depth when sent as progress
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResult<M = Idx> {
+    pub best_move: M,
     pub eval: Score,
+    // last fully searched depth
+    pub depth: u16,
+    // deepest ply reached, including quiescence search
+    pub seldepth: u16,
+    // nodes searched, including quiescence nodes
+    pub nodes: u64,
+    pub nps: u64,
+    pub elapsed: Duration,
+    // principal variation, starting with best_move
+    pub pv: Vec<M>,
+    // scores of the root moves at the last completed depth. Most of them
+    // were only proven to be worse than the best move, see RootMove::bound
+    pub root_moves: Vec<RootMove<M>>,
+    // true if the search was stopped in the middle of an iteration, whose
+    // result was then thrown away
+    pub cut_off: bool,
+    pub stats: SearchStats,
+}
+
+impl<M: Copy> SearchResult<M> {
+    fn new(best_move: M) -> SearchResult<M> {
+        SearchResult {
+            best_move: best_move,
+            eval: SCORE_NEG_INF,
+            depth: 0,
+            seldepth: 0,
+            nodes: 0,
+            nps: 0,
+            elapsed: Duration::from_secs(0),
+            pv: vec![best_move],
+            root_moves: Vec::new(),
+            cut_off: false,
+            stats: SearchStats::default(),
+        }
+    }
+
+    // updates everything that follows from the stats and the time taken
+    fn set_time(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+        self.seldepth = self.stats.seldepth;
+        self.nodes = self.stats.nodes + self.stats.qnodes;
+        self.nps = (self.nodes as f64 / f64::max(elapsed.as_secs_f64(), 1e-3)) as u64;
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RootMove<M = Idx> {
+    pub mov: M,
+    pub score: Score,
+    // with PVS and aspiration windows most root moves only get a bound
+    pub bound: Bound,
+}
+
+impl<M> RootMove<M> {
+    fn new(mov: M, score: Score, alpha: Score, beta: Score) -> RootMove<M> {
+        let bound = if score <= alpha {
+            Bound::Upper
+        } else if score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        RootMove {
+            mov: mov,
+            score: score,
+            bound: bound,
+        }
+    }
+}
+
+// result of searching all root moves at one depth
+struct RootIteration<M> {
+    score: Score,
+    pv: Vec<M>,
+    root_moves: Vec<RootMove<M>>,
+    stats: SearchStats,
+}
+
+// node counts, mainly for measuring how well move ordering works
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchStats {
+    // calls to alpha_beta_dfs
+    pub nodes: u64,
+    // calls to quiesce_search
+    pub qnodes: u64,
+    pub tt_hits: u64,
+    // nodes that returned straight from the transposition table
+    pub tt_cutoffs: u64,
+    pub beta_cutoffs: u64,
+    // beta cutoffs caused by the first move searched
+    pub first_move_cutoffs: u64,
+    // beta cutoffs, indexed by MoveSource
+    pub cutoffs_by_source: [u64; N_MOVE_SOURCES],
+    // deepest ply reached
+    pub seldepth: u16,
+}
+
+impl SearchStats {
+    pub fn add(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        self.tt_hits += other.tt_hits;
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        for i in 0..N_MOVE_SOURCES {
+            self.cutoffs_by_source[i] += other.cutoffs_by_source[i];
+        }
+        self.seldepth = std::cmp::max(self.seldepth, other.seldepth);
+    }
 }
 
-#[derive(Copy, Clone)]
-pub struct Manager {
-    position: Position,
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first_pct = 100.0 * self.first_move_cutoffs as f32 / std::cmp::max(self.beta_cutoffs, 1) as f32;
+        write!(
+            f,
+            "nodes {}, qnodes {}, tt hits {}, tt cutoffs {}, beta cutoffs {} ({:.1}% first move; hash {}, capture {}, killer {}, quiet {})",
+            self.nodes,
+            self.qnodes,
+            self.tt_hits,
+            self.tt_cutoffs,
+            self.beta_cutoffs,
+            first_pct,
+            self.cutoffs_by_source[MoveSource::Hash as usize],
+            self.cutoffs_by_source[MoveSource::Capture as usize],
+            self.cutoffs_by_source[MoveSource::Killer as usize],
+            self.cutoffs_by_source[MoveSource::Quiet as usize],
+        )
+    }
+}
+
+// alpha-beta search on any Game, Position by default
+#[derive(Clone)]
+pub struct Manager<G: Game = Position> {
+    position: G,
+    // shared by all workers, and kept across searches
+    tt: Arc<TranspositionTable>,
+    ordering: MoveOrdering,
+    // gets a SearchResult after every completed depth
+    progress: Option<mpsc::Sender<SearchResult<G::Move>>>,
+    // max threads searching root moves at once. 0 means one per root move
+    threads: usize,
 }
 
 // NOTE for now Manager is synchrnous, but we may wish to make it async
 // later.
-impl Manager {
-    pub fn from_position(pos: Position) -> Manager {
+impl<G: Game + Send + 'static> Manager<G> where G::Move: Send {
+    pub fn from_position(pos: G) -> Manager<G> {
+        Manager::with_hash_size(pos, DEFAULT_TT_MB)
+    }
+
+    pub fn with_hash_size(pos: G, tt_mb: usize) -> Manager<G> {
         Manager {
             position: pos,
+            tt: Arc::new(TranspositionTable::new(tt_mb)),
+            ordering: MoveOrdering::all(),
+            progress: None,
+            threads: 0,
         }
     }
 
-    fn search_fixed_time_inner(&self, stop_search: Arc<AtomicBool>) -> SearchResult {
+    // instead of printing progress to stderr, send a SearchResult through tx
+    // every time a depth is completed
+    pub fn set_progress_channel(&mut self, tx: mpsc::Sender<SearchResult<G::Move>>) {
+        self.progress = Some(tx);
+    }
+
+    // mainly for comparing node counts with and without some heuristic
+    pub fn set_move_ordering(&mut self, ordering: MoveOrdering) {
+        self.ordering = ordering;
+    }
+
+    // 1 searches everything on the calling thread. 0 (the default) spawns a
+    // thread per remaining root move
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    // resizing throws away whatever the table had learnt
+    pub fn set_hash_size(&mut self, tt_mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(tt_mb));
+    }
+
+    // keeps the transposition table, which is useful when searching
+    // successive positions of the same game
+    pub fn set_position(&mut self, pos: G) {
+        self.position = pos;
+    }
+
+    // iterative deepening search, which returns once any of the limits is
+    // hit or stop is set. The position must not be over
+    pub fn search(&self, limits: SearchLimits, stop: Arc<AtomicBool>) -> SearchResult<G::Move> {
+        let start = Instant::now();
+        let budget = Arc::new(SearchBudget {
+            stop: stop,
+            deadline: limits.movetime_millis.map(|millis| start + Duration::from_millis(millis)),
+            max_nodes: limits.nodes,
+            nodes: AtomicU64::new(0),
+        });
+        // depth 0 would have nothing to search the root moves to
+        let max_depth = limits.depth.unwrap_or(MAX_SEARCH_PLIES).clamp(1, MAX_SEARCH_PLIES);
+        // the first few depths take no time at all
+        let min_depth = std::cmp::min(4, max_depth);
+
         let moves = self.position.legal_moves();
-        let n_moves = moves.size();
-        // moves to explore before going parellel
-        let till_parallel = std::cmp::min(n_moves / 2, 4);
-
-        let mut best = moves.peek();
-        debug_assert!(best != NULL_IDX);
-        let mut best_score = SCORE_NEG_INF;
-
-        for depth in 4..=MAX_SEARCH_PLIES {
-            let mut moves_copy = moves;
-            // search last best move first
-            {
-                moves_copy.remove(best);
-
-                let mut localpos = self.position;
-                localpos.make_move(best);
-                let localstop = Arc::clone(&stop_search);
-                let worker = Worker::new(self.position, localstop);
-                let result = worker.alpha_beta_dfs(depth - 1, localpos, SCORE_NEG_INF, -best_score);
-                let score = match result {
-                    Ok(sc) => -sc,
-                    Err(_) => return SearchResult {
-                        eval: best_score,
-                        best_move: best,
-                    }
+        let mut result = SearchResult::new(moves.nth_move(0));
+
+        // searches the moves before going parallel. Reused across depths
+        // so that it keeps its killer/history tables
+        let mut worker = self.new_worker(Arc::clone(&budget));
+
+        for depth in min_depth..=max_depth {
+            // aspiration window around the last score. Not worth it on the
+            // first iteration, or once a win/loss has been found
+            let mut delta = ASPIRATION_WINDOW;
+            let (mut alpha, mut beta) = if depth == min_depth || result.eval.abs() >= SCORE_WIN {
+                (SCORE_NEG_INF, SCORE_POS_INF)
+            } else {
+                (result.eval - delta, result.eval + delta)
+            };
+
+            loop {
+                let root = self.search_root(depth, alpha, beta, result.best_move, &mut worker, &budget);
+                result.stats.add(&std::mem::take(&mut worker.stats));
+                let iteration = match root {
+                    Ok(iteration) => iteration,
+                    Err(thread_stats) => {
+                        result.stats.add(&thread_stats);
+                        result.cut_off = true;
+                        result.set_time(start.elapsed());
+                        return result;
+                    },
                 };
-                if score > best_score {
-                    best_score = score;
-                    // don't need to update best
+                result.stats.add(&iteration.stats);
+
+                // widen the window on the side that failed, and give up on
+                // aspiration altogether if it keeps failing
+                delta *= 4.0;
+                if iteration.score <= alpha {
+                    alpha = if delta > ASPIRATION_MAX_WINDOW { SCORE_NEG_INF } else { iteration.score - delta };
+                } else if iteration.score >= beta {
+                    beta = if delta > ASPIRATION_MAX_WINDOW { SCORE_POS_INF } else { iteration.score + delta };
+                } else {
+                    result.depth = depth;
+                    result.eval = iteration.score;
+                    result.best_move = iteration.pv[0];
+                    result.pv = iteration.pv;
+                    result.root_moves = iteration.root_moves;
+                    self.extend_pv_from_tt(&mut result.pv, depth as usize);
+                    break;
                 }
             }
+            result.set_time(start.elapsed());
+            match &self.progress {
+                Some(tx) => {
+                    // the receiver may have lost interest, which is fine
+                    let _ = tx.send(result.clone());
+                },
+                None => eprintln!("depth {}, best {:?}, eval {}, pv {:?}, {}", depth, result.best_move, result.eval, result.pv, result.stats),
+            }
+        }
+        return result;
+    }
 
-            let mut move_idx = 1;  // already explored one move
-            while move_idx < till_parallel {
-                let mov = moves_copy.next().unwrap();
-                let mut localpos = self.position;
-                localpos.make_move(mov);
+    // searches every root move to the given depth with PVS, with first (the
+    // previous best move) searched first. On a stop, the stats of the parallel
+    // workers are returned as the error
+    fn search_root(&self, depth: u16, mut alpha: Score, beta: Score, first: G::Move, worker: &mut Worker<G>, budget: &Arc<SearchBudget>)
+            -> Result<RootIteration<G::Move>, SearchStats> {
+        let mut moves = self.position.legal_moves();
+        let n_moves = moves.size();
+        // moves to explore before going parellel
+        let till_parallel = if self.threads == 1 {
+            n_moves
+        } else {
+            std::cmp::max(std::cmp::min(n_moves / 2, 4), 1)
+        };
 
-                let localstop = Arc::clone(&stop_search);
-                let worker = Worker::new(self.position, localstop);
-                let result = worker.alpha_beta_dfs(depth - 1, localpos, SCORE_NEG_INF, -best_score);
-                let score = match result {
-                    Ok(sc) => -sc,
-                    Err(_) => return SearchResult {
-                        eval: best_score,
-                        best_move: best,
-                    }
-                };
-                if score > best_score {
-                    best_score = score;
-                    best = mov;
-                }
-                move_idx += 1;
-            }
+        moves.remove(first);
+        let mut ret = RootIteration {
+            score: SCORE_NEG_INF,
+            pv: Vec::new(),
+            root_moves: Vec::new(),
+            stats: SearchStats::default(),
+        };
 
-            let mut handles = Vec::<JoinHandle<Result<Score, StopSearch>>>::new();
-            let mut rem_moves = Vec::<Idx>::new();
+        let mut move_idx = 0;
+        while move_idx < till_parallel {
+            let mov = if move_idx == 0 { first } else { moves.next().unwrap() };
+            let mut localpos = self.position.clone();
+            localpos.make_move(mov);
+
+            let score = match worker.search_root_move(depth, localpos, alpha, beta, move_idx == 0) {
+                Ok(sc) => sc,
+                Err(_) => return Err(SearchStats::default()),
+            };
+            ret.root_moves.push(RootMove::new(mov, score, alpha, beta));
+            if score > ret.score {
+                ret.score = score;
+                ret.pv = worker.pv_line(mov);
+            }
+            if score > alpha {
+                alpha = score;
+            }
+            if alpha >= beta {
+                return Ok(ret);
+            }
+            move_idx += 1;
+        }
 
-            // search the remaining moves in parallel
-            while move_idx < n_moves {
-                let mov = moves_copy.next().unwrap();
-                let mut localpos = self.position;
+        // search the remaining moves in parallel, at most self.threads at a time
+        let rem_moves = moves.collect::<Vec<G::Move>>();
+        let batch_size = if self.threads == 0 { std::cmp::max(rem_moves.len(), 1) } else { self.threads };
+        let mut stop_now = false;
+        for batch in rem_moves.chunks(batch_size) {
+            let mut handles = Vec::<JoinHandle<(Result<Score, StopSearch>, Vec<G::Move>, SearchStats)>>::new();
+            for &mov in batch {
+                let mut localpos = self.position.clone();
                 localpos.make_move(mov);
 
-                let localstop = Arc::clone(&stop_search);
+                let mut worker = self.new_worker(Arc::clone(budget));
                 let handle = std::thread::spawn(move || {
-                    let worker = Worker::new(localpos, localstop);
-                    return worker.alpha_beta_dfs(depth - 1, localpos, SCORE_NEG_INF, -best_score);
+                    let res = worker.search_root_move(depth, localpos, alpha, beta, false);
+                    return (res, worker.pv_line(mov), worker.stats);
                 });
                 handles.push(handle);
-
-                rem_moves.push(mov);
-                move_idx += 1;
             }
 
-            let mut i = 0;
-            let mut stop_now = false;
-            for handle in handles {
-                let res = handle.join().unwrap();
-                let mov = rem_moves[i];
+            for (handle, &mov) in handles.into_iter().zip(batch) {
+                let (res, pv, thread_stats) = handle.join().unwrap();
+                ret.stats.add(&thread_stats);
                 match res {
                     Ok(score) => {
-                        let score = -score;
-                        if score > best_score {
-                            best_score = score;
-                            best = mov;
+                        ret.root_moves.push(RootMove::new(mov, score, alpha, beta));
+                        if score > ret.score {
+                            ret.score = score;
+                            ret.pv = pv;
                         }
                     },
                     Err(_) => {
                         stop_now = true;
                     },
                 }
-                i += 1;
             }
-            if stop_now {
+            if stop_now || ret.score >= beta {
                 break;
             }
-            eprintln!("depth {}, best {}, eval {}", depth, best, best_score);
+            // later batches get the tighter window
+            alpha = alpha.max(ret.score);
         }
-        return SearchResult{
-            eval: best_score,
-            best_move: best,
-        };
+        if stop_now {
+            return Err(ret.stats);
+        }
+        // fail-hard, like alpha_beta_dfs
+        ret.score = ret.score.max(alpha).min(beta);
+        return Ok(ret);
     }
 
-    pub fn search_fixed_time(&self, alloc_millis: u64) -> SearchResult {
-        // flag to stop search
-        let stop_search = Arc::new(AtomicBool::new(false));
+    // the triangular PV table loses the line at transposition table cutoffs,
+    // so continue it with the stored best moves
+    fn extend_pv_from_tt(&self, pv: &mut Vec<G::Move>, max_len: usize) {
+        let mut pos = self.position.clone();
+        for mov in pv.iter() {
+            pos.make_move(*mov);
+        }
+        while pv.len() < max_len && !pos.is_over() {
+            let mov = match self.tt.probe(pos.hash()).and_then(|entry| entry.best_move) {
+                Some(index) => G::index_move(index),
+                None => break,
+            };
+            if !pos.legal_moves().contains(mov) {
+                break;
+            }
+            pos.make_move(mov);
+            pv.push(mov);
+        }
+    }
 
+    pub fn search_fixed_time(&self, alloc_millis: u64) -> SearchResult<G::Move> {
         // expect something reasonable
         assert!(alloc_millis > 30);
 
-        let localstop = Arc::clone(&stop_search);
-        // HACK. perhaps better is have a function that is not a member of Manager
-        // but instead takes a position
-        let me = self.clone();
-        let handle = std::thread::spawn(move || {
-            return me.search_fixed_time_inner(localstop);
-        });
+        // leave some time for joining the threads
+        return self.search(SearchLimits::movetime(alloc_millis - 25), Arc::new(AtomicBool::new(false)));
+    }
 
-        // wait
-        thread::sleep(Duration::from_millis(alloc_millis - 25));
+    fn new_worker(&self, budget: Arc<SearchBudget>) -> Worker<G> {
+        return Worker::new(Arc::clone(&self.tt), budget, self.ordering, self.position.n_move_indices());
+    }
+}
 
-        // tell threads to stop
-        stop_search.swap(true, Ordering::Relaxed);
-        
-        let result = handle.join().unwrap();
-        return result;
+// the clock only makes sense for ultimate tic-tac-toe
+impl Manager {
+    // searches for as long as the clock allows
+    pub fn search_free(&self, clock: Clock, stop: Arc<AtomicBool>) -> SearchResult {
+        return self.search(self.free_limits(clock), stop);
     }
-    
-    pub fn search_free(&mut self, x_millis: u64, o_millis: u64) {
+
+    // decides how long to think for, given the time left on the clocks
+    pub fn free_limits(&self, clock: Clock) -> SearchLimits {
         // TODO use the other player's time
-        let my_millis = match self.position.to_move {
-            Side::X => x_millis,
-            Side::O => o_millis,
+        let (my_millis, my_inc) = match self.position.to_move {
+            Side::X => (clock.x_millis, clock.x_inc_millis),
+            Side::O => (clock.o_millis, clock.o_inc_millis),
         };
 
         //let alloc_time = my_time / (81 - pos)
@@ -191,40 +502,84 @@ impl Manager {
         // divide remaining time by remaining moves
         // multiply by something to be a bit more generous
         // very late game
-        let alloc_millis: u128;
+        let mut alloc_millis: u64;
         if cur_ply > 60 {
             // should be over soon, so take one third
-            alloc_millis = std::cmp::max(1000, my_millis as u128 / 3);
+            alloc_millis = std::cmp::max(1000, my_millis / 3);
         } else {
             // be optimistic and assume the game is over by 60 plies
-            alloc_millis = (my_millis as f32 / (65.0 - cur_ply as f32)) as u128;
+            alloc_millis = (my_millis as f32 / (65.0 - cur_ply as f32)) as u64;
 
             // guarantee 5 seconds
             //alloc_millis = std::cmp::max(alloc_millis, 5000);
         }
+        // the increment comes back after this move anyways
+        alloc_millis += my_inc * 3 / 4;
+        // but never flag
+        alloc_millis = std::cmp::min(alloc_millis, my_millis.saturating_sub(CLOCK_MARGIN_MILLIS));
+        alloc_millis = std::cmp::max(alloc_millis, 1);
 
         eprintln!("NOTE: secs remaining: {}; allocated {}", my_millis as f32 / 1000.0, alloc_millis as f32 / 1000.0);
-        self.search_fixed_time(alloc_millis as u64);
+        return SearchLimits::movetime(alloc_millis);
     }
 }
 
 // one worker per thread. used for searching
-struct Worker {
-    position: Position,
-    eval_fn: EvalFn,
-    stop: Arc<AtomicBool>,
+struct Worker<G: Game> {
+    eval_fn: EvalFn<G>,
+    tt: Arc<TranspositionTable>,
+    heuristics: Heuristics<G>,
+    stats: SearchStats,
+    // triangular PV table: pv[ply][ply..pv_len[ply]] is the best line
+    // found so far from ply on
+    pv: [[G::Move; PV_SIZE]; PV_SIZE],
+    pv_len: [usize; PV_SIZE],
+    // one per ply, see OrderedMoves
+    move_lists: Vec<OrderedMoves<G::Move>>,
+    budget: Arc<SearchBudget>,
+    // nodes not yet added to budget.nodes
+    unpolled_nodes: u64,
 }
 
-impl Worker {
-    // note: takes ownership of tx and stop, so need to make clone
-    pub fn new(pos: Position, stop: Arc<AtomicBool>) -> Worker {
+const PV_SIZE: usize = MAX_SEARCH_PLIES as usize + 2;
+
+impl<G: Game> Worker<G> {
+    // note: takes ownership of tt and budget, so need to make clone
+    pub fn new(tt: Arc<TranspositionTable>, budget: Arc<SearchBudget>, ordering: MoveOrdering, n_move_indices: usize) -> Worker<G> {
         Worker {
-            position: pos,
-            eval_fn: eval, // default to eval; might change later
-            stop: stop,
+            eval_fn: G::evaluate, // eval for Position; might change later
+            tt: tt,
+            heuristics: Heuristics::new(ordering, n_move_indices),
+            stats: SearchStats::default(),
+            pv: [[G::Move::default(); PV_SIZE]; PV_SIZE],
+            pv_len: [0; PV_SIZE],
+            move_lists: (0..PV_SIZE).map(|_| OrderedMoves::new()).collect(),
+            budget: budget,
+            unpolled_nodes: 0,
         }
     }
 
+    // searches one root move (pos is the position after it) with PVS: unless
+    // it is the first move, try a null window first and only re-search with
+    // the full window if the move turns out better than alpha. Returns the
+    // score for the side at the root
+    fn search_root_move(&mut self, depth: u16, pos: G, alpha: Score, beta: Score, first: bool) -> Result<Score, StopSearch> {
+        if !first {
+            let score = -self.alpha_beta_dfs(depth - 1, 1, pos.clone(), -score_above(alpha), -alpha)?;
+            if score <= alpha || score >= beta {
+                return Ok(score);
+            }
+        }
+        return Ok(-self.alpha_beta_dfs(depth - 1, 1, pos, -beta, -alpha)?);
+    }
+
+    // principal variation of the last search_root_move, starting with root_move
+    fn pv_line(&self, root_move: G::Move) -> Vec<G::Move> {
+        let mut line = vec![root_move];
+        line.extend_from_slice(&self.pv[1][1..self.pv_len[1]]);
+        return line;
+    }
+
     /*
     // alpha-beta iterative deepening search
     fn limited_search(&self, pos: &Position, alpha: Score, beta: Score) -> SearchResult {
@@ -232,85 +587,175 @@ impl Worker {
     }
     */
 
-    // alpha-beta negamax search using DFS
+    // alpha-beta negamax search using DFS. ply is the distance from the root
     // TODO return SearchResult instead
-    fn alpha_beta_dfs(&self, depth: u16, pos: Position, alpha: Score, beta: Score) -> Result<Score, StopSearch> {
-        debug_assert!(pos.assert());
-
-        // this move has won -- it's terrible for the current side
-        // note only the last moved side could have won so only
-        // one call to is_won() is made
-        // TODO do we need to check for this? would alpha-beta take care of this
-        if pos.is_won(pos.to_move.other()) {
-            //state.nodes_searched += 1;
-            return self.check_time(SCORE_LOSS);
-        } else if pos.is_drawn() {
-            //state.nodes_searched += 1;
-            // NOTE that one side could still be considered won in some rulesets by comparing
-            // the total number of blocks occupied
-            let mult = codingame_drawn(&pos);
-            return self.check_time(mult * side_multiplier(pos.to_move) * SCORE_WIN);
+    fn alpha_beta_dfs(&mut self, depth: u16, ply: u16, pos: G, alpha: Score, beta: Score) -> Result<Score, StopSearch> {
+        self.stats.nodes += 1;
+        self.poll_limits()?;
+        self.pv_len[ply as usize] = ply as usize;
+        self.stats.seldepth = std::cmp::max(self.stats.seldepth, ply);
+
+        // usually the last move has won, which is terrible for the side to
+        // move. Otherwise it's a full board, or a row for the side to move
+        // through a tied block, and who wins depends on the ruleset
+        if pos.is_over() {
+            return self.check_time(self.result_score(&pos));
         } else if depth == 0 {
-            //state.nodes_searched += 1;
             let f = self.eval_fn;
-            let my_1occ = self.position.get_1occ(self.position.to_move);
-            let their_1occ = self.position.get_1occ(self.position.to_move.other());
-            return self.quiesce_search(pos, my_1occ, their_1occ, f);
+            let side = pos.side_to_move();
+            let my_1occ = pos.captures(side);
+            let their_1occ = pos.captures(side.other());
+            return self.quiesce_search(ply, pos, my_1occ, their_1occ, f);
         }
 
-        let moves = pos.legal_moves();
         let mut alpha = alpha;
+        let mut beta = beta;
+        let mut hash_move = None;
+        if let Some(entry) = self.tt.probe(pos.hash()) {
+            self.stats.tt_hits += 1;
+            hash_move = entry.best_move.map(G::index_move);
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => {
+                        self.stats.tt_cutoffs += 1;
+                        return Ok(entry.score);
+                    },
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                }
+                if alpha >= beta {
+                    self.stats.tt_cutoffs += 1;
+                    return Ok(entry.score);
+                }
+            }
+        }
+
+        // taken out while searching the children, which use the lists
+        // of later plies. Lost if the search is stopped, which is fine
+        let mut moves = std::mem::take(&mut self.move_lists[ply as usize]);
+        self.heuristics.order(&pos, hash_move, ply, &mut moves);
+        let mut best_move = None;
         /*
         let DROP_CUTOFF = 30;
         if moves.size() >= DROP_CUTOFF {
             let indices = (0..DROP_CUTOFF).collect::<Vec<_>>();
             
         }*/
-        /*
-        let my_1occ = pos.get_1occ(pos.to_move);
-        let captures = moves.intersect(my_1occ);
-        let moves = moves.subtract(captures);
-        for mov in captures {
-            let mut temp = pos.clone();
-            temp.make_move(mov);
-            let score = -self.alpha_beta_dfs(depth - 1, temp, -beta, -alpha, state)?;
-            if score >= beta {
-                return Ok(beta);
-            }
-            if score > alpha {
-                alpha = score;
-            }
-        }
-        */
 
-        for mov in moves {
+        for i in 0..moves.len() {
+            let (mov, source) = moves.get(i);
             let mut temp = pos.clone();
             temp.make_move(mov);
-            let score = -self.alpha_beta_dfs(depth - 1, temp, -beta, -alpha)?;
+            // principal variation search: the first move is assumed to be
+            // the best, so the rest only need to be proven worse with a null
+            // window. If that fails, re-search with the full window
+            let mut score;
+            if i == 0 {
+                score = -self.alpha_beta_dfs(depth - 1, ply + 1, temp, -beta, -alpha)?;
+            } else {
+                score = -self.alpha_beta_dfs(depth - 1, ply + 1, temp.clone(), -score_above(alpha), -alpha)?;
+                if score > alpha && score < beta {
+                    score = -self.alpha_beta_dfs(depth - 1, ply + 1, temp, -beta, -alpha)?;
+                }
+            }
             if score >= beta {
+                self.stats.beta_cutoffs += 1;
+                self.stats.first_move_cutoffs += (i == 0) as u64;
+                self.stats.cutoffs_by_source[source as usize] += 1;
+                self.heuristics.record_cutoff(pos.side_to_move(), mov, source, ply, depth);
+                self.tt.store(pos.hash(), TTEntry {
+                    depth: depth,
+                    bound: Bound::Lower,
+                    score: beta,
+                    best_move: Some(G::move_index(mov)),
+                });
+                self.move_lists[ply as usize] = moves;
                 return Ok(beta);
             }
             if score > alpha {
                 alpha = score;
+                best_move = Some(mov);
+                self.update_pv(ply as usize, mov);
             }
         }
+
+        // if no move raised alpha, this is only an upper bound and we don't
+        // know which move was best
+        let bound = if best_move.is_some() { Bound::Exact } else { Bound::Upper };
+        self.tt.store(pos.hash(), TTEntry {
+            depth: depth,
+            bound: bound,
+            score: alpha,
+            best_move: best_move.map(G::move_index),
+        });
+        self.move_lists[ply as usize] = moves;
         return Ok(alpha);
     }
 
+    #[inline(always)]
+    fn update_pv(&mut self, ply: usize, mov: G::Move) {
+        self.pv[ply][ply] = mov;
+        let child_len = self.pv_len[ply + 1];
+        for i in (ply + 1)..child_len {
+            self.pv[ply][i] = self.pv[ply + 1][i];
+        }
+        self.pv_len[ply] = std::cmp::max(child_len, ply + 1);
+    }
+
+    // the score of a finished game for the side to move
+    #[inline(always)]
+    fn result_score(&self, pos: &G) -> Score {
+        return result_sign(pos.get_result()) * side_multiplier(pos.side_to_move()) * SCORE_WIN;
+    }
+
     // called when a leaf node is reached. If timed out, return Err(StopSearch). Otherwise
     // return the given eval wrapped in Result
     #[inline(always)]
     fn check_time(&self, eval: Score) -> Result<Score, StopSearch> {
-        if self.stop.load(Ordering::Relaxed) {
+        if self.budget.stop.load(Ordering::Relaxed) {
             return Err(StopSearch);
         }
         return Ok(eval);
     }
 
+    // called on every node. Checking the clock and the shared node count
+    // is too slow to do every time, so it's only done every
+    // NODE_POLL_INTERVAL nodes. Hitting a limit stops all workers
+    #[inline(always)]
+    fn poll_limits(&mut self) -> Result<(), StopSearch> {
+        self.unpolled_nodes += 1;
+        if self.unpolled_nodes < NODE_POLL_INTERVAL {
+            return Ok(());
+        }
+        let budget = &self.budget;
+        let nodes = budget.nodes.fetch_add(self.unpolled_nodes, Ordering::Relaxed) + self.unpolled_nodes;
+        self.unpolled_nodes = 0;
+        let out_of_nodes = match budget.max_nodes {
+            Some(max) => nodes >= max,
+            None => false,
+        };
+        let out_of_time = match budget.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        };
+        if out_of_nodes || out_of_time {
+            budget.stop.store(true, Ordering::Relaxed);
+            return Err(StopSearch);
+        }
+        return Ok(());
+    }
+
     // my_1occ is the occupancy of moves I can make to capture a block.
     // alpha/beta is not used for now since the search space is assumed to be small
     #[inline(always)]
-    fn quiesce_search(&self, pos: Position, mut my_1occ: Moves, mut their_1occ: Moves, eval_fn: EvalFn) -> Result<Score, StopSearch> {
+    fn quiesce_search(&mut self, ply: u16, pos: G, mut my_1occ: G::Moves, mut their_1occ: G::Moves, eval_fn: EvalFn<G>) -> Result<Score, StopSearch> {
+        self.stats.qnodes += 1;
+        self.poll_limits()?;
+        self.stats.seldepth = std::cmp::max(self.stats.seldepth, ply);
+        // a capture can end the game
+        if pos.is_over() {
+            return self.check_time(self.result_score(&pos));
+        }
         let captures = pos.legal_moves().intersect(my_1occ);
         if captures.size() != 0 {
             let mut best = SCORE_NEG_INF;
@@ -320,7 +765,7 @@ impl Worker {
 
                 my_1occ.remove(mov);
                 their_1occ.remove(mov);
-                let score = -self.quiesce_search(temp, their_1occ, my_1occ, eval_fn)?;
+                let score = -self.quiesce_search(ply + 1, temp, their_1occ, my_1occ, eval_fn)?;
                 if score > best {
                     best = score;
                 }
@@ -0,0 +1,190 @@
+use crate::engine::config::*;
+use crate::game::*;
+use crate::moves::*;
+
+/*
+Move ordering for alpha_beta_dfs. Moves are tried in this order:
+  1. the hash move, i.e. the best move stored in the transposition table
+  2. captures, i.e. moves that complete a block (see Game::captures)
+  3. killer moves: quiet moves that caused a beta cutoff at the same ply
+  4. the remaining quiet moves, sorted by the history heuristic
+Each heuristic can be switched off through MoveOrdering so that their effect
+on the node count can be measured.
+*/
+
+// which heuristic put a move where it is in the ordering
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MoveSource {
+    Hash = 0,
+    Capture = 1,
+    Killer = 2,
+    Quiet = 3,
+}
+
+pub const N_MOVE_SOURCES: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct MoveOrdering {
+    pub hash_move: bool,
+    pub captures: bool,
+    pub killers: bool,
+    pub history: bool,
+}
+
+impl MoveOrdering {
+    pub fn all() -> MoveOrdering {
+        MoveOrdering {
+            hash_move: true,
+            captures: true,
+            killers: true,
+            history: true,
+        }
+    }
+
+    // plain bit order, which is what alpha_beta_dfs used to do
+    pub fn none() -> MoveOrdering {
+        MoveOrdering {
+            hash_move: false,
+            captures: false,
+            killers: false,
+            history: false,
+        }
+    }
+}
+
+const N_KILLERS: usize = 2;
+const N_KILLER_PLIES: usize = MAX_SEARCH_PLIES as usize + 1;
+
+// reused from node to node, so that ordering doesn't allocate
+#[derive(Default)]
+pub(crate) struct OrderedMoves<M> {
+    moves: Vec<(M, MoveSource)>,
+}
+
+impl<M: Copy> OrderedMoves<M> {
+    pub(crate) fn new() -> OrderedMoves<M> {
+        OrderedMoves {
+            moves: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    fn push(&mut self, mov: M, source: MoveSource) {
+        self.moves.push((mov, source));
+    }
+
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    #[inline(always)]
+    pub(crate) fn get(&self, i: usize) -> (M, MoveSource) {
+        self.moves[i]
+    }
+}
+
+// killer and history tables. One per worker, since they are updated
+// on every cutoff
+pub(crate) struct Heuristics<G: Game> {
+    ordering: MoveOrdering,
+    killers: [[Option<G::Move>; N_KILLERS]; N_KILLER_PLIES],
+    // indexed by side, then Game::move_index
+    history: [Vec<u32>; 2],
+}
+
+impl<G: Game> Heuristics<G> {
+    // n_move_indices is the game's, see Game::move_index
+    pub(crate) fn new(ordering: MoveOrdering, n_move_indices: usize) -> Heuristics<G> {
+        Heuristics {
+            ordering: ordering,
+            killers: [[None; N_KILLERS]; N_KILLER_PLIES],
+            history: [vec![0; n_move_indices], vec![0; n_move_indices]],
+        }
+    }
+
+    // fills ret with the legal moves. hash_move may be illegal in case of a
+    // hash collision
+    pub(crate) fn order(&self, pos: &G, hash_move: Option<G::Move>, ply: u16, ret: &mut OrderedMoves<G::Move>) {
+        let mut moves = pos.legal_moves();
+        ret.moves.clear();
+
+        if let Some(hash_move) = hash_move {
+            if self.ordering.hash_move && moves.contains(hash_move) {
+                ret.push(hash_move, MoveSource::Hash);
+                moves.remove(hash_move);
+            }
+        }
+
+        if self.ordering.captures {
+            let captures = moves.intersect(pos.captures(pos.side_to_move()));
+            for mov in captures {
+                ret.push(mov, MoveSource::Capture);
+            }
+            moves = moves.subtract(captures);
+        }
+
+        if self.ordering.killers && (ply as usize) < N_KILLER_PLIES {
+            for killer in self.killers[ply as usize].iter().flatten() {
+                let killer = *killer;
+                if moves.contains(killer) {
+                    ret.push(killer, MoveSource::Killer);
+                    moves.remove(killer);
+                }
+            }
+        }
+
+        let quiet_start = ret.len();
+        for mov in moves {
+            ret.push(mov, MoveSource::Quiet);
+        }
+        if self.ordering.history {
+            let history = &self.history[pos.side_to_move() as usize];
+            ret.moves[quiet_start..].sort_by_key(|&(mov, _)| std::cmp::Reverse(history[G::move_index(mov)]));
+        }
+    }
+
+    // called when mov caused a beta cutoff. Captures are already ordered
+    // early, so only quiet moves are recorded
+    pub(crate) fn record_cutoff(&mut self, side: Side, mov: G::Move, source: MoveSource, ply: u16, depth: u16) {
+        if source == MoveSource::Capture {
+            return;
+        }
+        if (ply as usize) < N_KILLER_PLIES {
+            let killers = &mut self.killers[ply as usize];
+            if killers[0] != Some(mov) {
+                killers[1] = killers[0];
+                killers[0] = Some(mov);
+            }
+        }
+        let entry = &mut self.history[side as usize][G::move_index(mov)];
+        *entry = entry.saturating_add(depth as u32 * depth as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        init_moves();
+        // X to move in block 0, and X can capture block 0 with 2
+        let pos = Position::from_move_list("1, 14, 48, 27, 0, 6, 62, 72, 3, 34, 67, 36").unwrap();
+        let mut heuristics = Heuristics::<Position>::new(MoveOrdering::all(), 81);
+        heuristics.record_cutoff(Side::X, 7, MoveSource::Quiet, 2, 3);
+        let mut moves = OrderedMoves::new();
+        heuristics.order(&pos, Some(8), 2, &mut moves);
+        assert_eq!(moves.len(), pos.legal_moves().size());
+        assert_eq!(moves.get(0), (8, MoveSource::Hash));
+        assert_eq!(moves.get(1), (2, MoveSource::Capture));
+        assert_eq!(moves.get(2), (7, MoveSource::Killer));
+        assert_eq!(moves.get(3).1, MoveSource::Quiet);
+
+        Heuristics::<Position>::new(MoveOrdering::none(), 81).order(&pos, Some(8), 2, &mut moves);
+        let plain = pos.legal_moves().collect::<Vec<_>>();
+        for i in 0..moves.len() {
+            assert_eq!(moves.get(i), (plain[i], MoveSource::Quiet));
+        }
+    }
+}
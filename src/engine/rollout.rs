@@ -0,0 +1,123 @@
+/*
+How MCTS plays out a position to the end. Uniform random moves are what the
+engine always did, and are still the default; the others spend a bit of
+time per move on the Game hooks (captures, sends_to_free_move, eval_move)
+to play more like a person would. Whether that is worth the rollouts it
+costs is for a match to decide.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::format::ParseError;
+use crate::game::*;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RolloutPolicy {
+    #[default]
+    Uniform,
+    // wins a block whenever it can
+    Captures,
+    // doesn't give the opponent a free move if anything else is legal
+    AvoidFreeMoves,
+    // the move with the best Game::eval_move, or a uniform one with the
+    // given probability
+    EpsilonGreedy(f32),
+}
+
+impl RolloutPolicy {
+    // the next move of a rollout. The game must not be over
+    pub fn pick_move<G: Game, R: Rng>(&self, pos: &G, rng: &mut R) -> G::Move {
+        let moves = pos.legal_moves();
+        match *self {
+            RolloutPolicy::Uniform => {
+                return uniform(moves, rng);
+            },
+            RolloutPolicy::Captures => {
+                let captures = moves.intersect(pos.captures(pos.side_to_move()));
+                if captures.size() > 0 {
+                    return uniform(captures, rng);
+                }
+                return uniform(moves, rng);
+            },
+            RolloutPolicy::AvoidFreeMoves => {
+                let mut safe = moves;
+                for mov in moves {
+                    if pos.sends_to_free_move(mov) {
+                        safe.remove(mov);
+                    }
+                }
+                if safe.size() > 0 {
+                    return uniform(safe, rng);
+                }
+                return uniform(moves, rng);
+            },
+            RolloutPolicy::EpsilonGreedy(epsilon) => {
+                if rng.gen::<f32>() < epsilon {
+                    return uniform(moves, rng);
+                }
+                // ties are common, so pick among them at random
+                let mut best = f32::NEG_INFINITY;
+                let mut best_move = G::Move::default();
+                let mut n_best = 0;
+                for mov in moves {
+                    let gain = pos.eval_move(mov);
+                    if gain > best {
+                        best = gain;
+                        best_move = mov;
+                        n_best = 1;
+                    } else if gain == best {
+                        n_best += 1;
+                        if rng.gen_range(0, n_best) == 0 {
+                            best_move = mov;
+                        }
+                    }
+                }
+                return best_move;
+            },
+        }
+    }
+}
+
+#[inline]
+fn uniform<M, S: MoveSet<M>, R: Rng>(moves: S, rng: &mut R) -> M {
+    let j = rng.gen_range(0, moves.size());
+    return moves.nth_move(j);
+}
+
+impl fmt::Display for RolloutPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RolloutPolicy::Uniform => write!(f, "uniform"),
+            RolloutPolicy::Captures => write!(f, "captures"),
+            RolloutPolicy::AvoidFreeMoves => write!(f, "avoid-free"),
+            RolloutPolicy::EpsilonGreedy(epsilon) => write!(f, "greedy:{}", epsilon),
+        }
+    }
+}
+
+impl FromStr for RolloutPolicy {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<RolloutPolicy, ParseError> {
+        let repr = repr.trim();
+        match repr {
+            "uniform" => return Ok(RolloutPolicy::Uniform),
+            "captures" => return Ok(RolloutPolicy::Captures),
+            "avoid-free" => return Ok(RolloutPolicy::AvoidFreeMoves),
+            _ => {},
+        }
+        let expected = "'uniform', 'captures', 'avoid-free' or 'greedy:<epsilon>'";
+        let epsilon = match repr.strip_prefix("greedy:") {
+            Some(epsilon) => epsilon,
+            None => return Err(ParseError::expected(0, expected, repr)),
+        };
+        match epsilon.parse::<f32>() {
+            Ok(epsilon) if (0.0..=1.0).contains(&epsilon) => Ok(RolloutPolicy::EpsilonGreedy(epsilon)),
+            _ => Err(ParseError::expected(7, "an epsilon between 0 and 1", epsilon)),
+        }
+    }
+}
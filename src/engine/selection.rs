@@ -0,0 +1,175 @@
+/*
+How MCTS picks the child to walk down to. MCTSWorker (see with_selection)
+asks its SelectionPolicy for a score for every child of the node it's at and
+takes the highest; proven losses are never asked about (see update_proof in
+mcts.rs). Everything a policy is given is from the point of view of the
+side to move at that node, so a higher value is always better.
+
+- Ucb1: the classic value + c * sqrt(ln N / n). What the engine always did
+- Ucb1Tuned: the same, with the exploration term capped by an upper bound
+  on the variance of the child's results
+- Puct: AlphaZero-style, with an exploration term weighted by a prior over
+  the children. The priors come from Game::evaluate, see Priors in mcts.rs
+- Rave: Ucb1 on a blend of the value and the AMAF (all moves as first)
+  value, which counts the results of every simulation where the move was
+  played later on by the same side. Moves are told apart by
+  Game::move_index, which is the same in any position
+
+Priors in mcts.rs are applied on top of whichever policy is chosen.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::format::ParseError;
+
+// the node choosing a child
+#[derive(Copy, Clone, Debug)]
+pub struct ParentInfo {
+    pub n: f32,
+    // natural log of n
+    pub ln_n: f32,
+}
+
+// one of its children. Unvisited children have n 0 and value 0.5
+#[derive(Copy, Clone, Debug)]
+pub struct ChildInfo {
+    pub n: f32,
+    // mean result, 1 being a win for the side choosing
+    pub value: f32,
+    // of the results
+    pub variance: f32,
+    // adds up to 1 over the children. Uniform unless priors are computed,
+    // see uses_priors
+    pub prior: f32,
+    // simulations through the parent that played this move later, and their
+    // mean result. Only kept when uses_amaf says so
+    pub amaf_n: f32,
+    pub amaf_value: f32,
+}
+
+pub trait SelectionPolicy: Clone + Send + 'static {
+    // higher is better
+    fn score(&self, parent: &ParentInfo, child: &ChildInfo) -> f32;
+
+    // whether the worker has to keep ChildInfo::variance. Without it, it's 0
+    fn uses_variance(&self) -> bool {
+        false
+    }
+
+    // whether the worker has to keep AMAF statistics, which costs a bit of
+    // time per rollout
+    fn uses_amaf(&self) -> bool {
+        false
+    }
+
+    // whether the worker has to evaluate every new node for ChildInfo::prior
+    fn uses_priors(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ucb1 {
+    pub c: f32,
+}
+
+impl SelectionPolicy for Ucb1 {
+    #[inline]
+    fn score(&self, parent: &ParentInfo, child: &ChildInfo) -> f32 {
+        child.value + self.c * (parent.ln_n / child.n).sqrt()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ucb1Tuned {
+    pub c: f32,
+}
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, parent: &ParentInfo, child: &ChildInfo) -> f32 {
+        // results are in [0, 1], so their variance is at most 1/4
+        let variance_bound = child.variance + (2.0 * parent.ln_n / child.n).sqrt();
+        return child.value + self.c * (parent.ln_n / child.n * f32::min(0.25, variance_bound)).sqrt();
+    }
+
+    fn uses_variance(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Puct {
+    pub c: f32,
+}
+
+impl SelectionPolicy for Puct {
+    fn score(&self, parent: &ParentInfo, child: &ChildInfo) -> f32 {
+        child.value + self.c * child.prior * parent.n.sqrt() / (1.0 + child.n)
+    }
+
+    fn uses_priors(&self) -> bool {
+        true
+    }
+}
+
+// k is the number of visits at which the AMAF value and the real one
+// weigh the same
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rave {
+    pub c: f32,
+    pub k: f32,
+}
+
+impl SelectionPolicy for Rave {
+    fn score(&self, parent: &ParentInfo, child: &ChildInfo) -> f32 {
+        let beta = (self.k / (3.0 * child.n + self.k)).sqrt();
+        let amaf_value = if child.amaf_n > 0.0 { child.amaf_value } else { child.value };
+        let value = (1.0 - beta) * child.value + beta * amaf_value;
+        return value + self.c * (parent.ln_n / child.n).sqrt();
+    }
+
+    fn uses_amaf(&self) -> bool {
+        true
+    }
+}
+
+// the policies by name, for options and command lines. Each takes the
+// exploration constant c, and Rave's k is RAVE_K. MCTSWorker takes the
+// policy as a type, so pick one with a match
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectionKind {
+    #[default]
+    Ucb1,
+    Ucb1Tuned,
+    Puct,
+    Rave,
+}
+
+pub const RAVE_K: f32 = 1000.0;
+
+impl fmt::Display for SelectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelectionKind::Ucb1 => write!(f, "ucb1"),
+            SelectionKind::Ucb1Tuned => write!(f, "ucb1-tuned"),
+            SelectionKind::Puct => write!(f, "puct"),
+            SelectionKind::Rave => write!(f, "rave"),
+        }
+    }
+}
+
+impl FromStr for SelectionKind {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<SelectionKind, ParseError> {
+        match repr.trim() {
+            "ucb1" => Ok(SelectionKind::Ucb1),
+            "ucb1-tuned" => Ok(SelectionKind::Ucb1Tuned),
+            "puct" => Ok(SelectionKind::Puct),
+            "rave" => Ok(SelectionKind::Rave),
+            other => Err(ParseError::expected(0, "'ucb1', 'ucb1-tuned', 'puct' or 'rave'", other)),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::engine::config::*;
+
+/*
+Shared, lock-free transposition table. Every entry is two u64 words: the
+packed data and (key ^ data). A reader only accepts an entry if the two words
+xor back to its key, so an entry that is torn by a concurrent write from
+another worker is simply treated as a miss (see Hyatt's "lockless
+transposition tables").
+
+Packed data layout (from the lowest bit):
+  0..32   score (f32 bits)
+  32..48  depth
+  48..50  bound (+1 so that an empty entry, which is all zeros, is never valid)
+  50..64  best move: its Game::move_index + 1, or 0 if there is none
+*/
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Bound {
+    Exact = 0,
+    // score is at least this much (failed high)
+    Lower = 1,
+    // score is at most this much (failed low)
+    Upper = 2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TTEntry {
+    pub depth: u16,
+    pub bound: Bound,
+    pub score: Score,
+    // see Game::move_index
+    pub best_move: Option<usize>,
+}
+
+impl TTEntry {
+    fn pack(&self) -> u64 {
+        let best_move = self.best_move.map_or(0, |index| index as u64 + 1);
+        debug_assert!(best_move < 1 << 14);
+        (self.score.to_bits() as u64)
+            | (self.depth as u64) << 32
+            | (self.bound as u64 + 1) << 48
+            | best_move << 50
+    }
+
+    fn unpack(data: u64) -> TTEntry {
+        let bound = match (data >> 48) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        TTEntry {
+            depth: (data >> 32) as u16,
+            bound: bound,
+            score: Score::from_bits(data as u32),
+            best_move: ((data >> 50) as usize).checked_sub(1),
+        }
+    }
+}
+
+pub struct TranspositionTable {
+    // pairs of (key ^ data, data)
+    entries: Vec<AtomicU64>,
+    mask: usize,
+}
+
+impl TranspositionTable {
+    // size is rounded down to a power of two number of entries
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let n_wanted = std::cmp::max(size_mb * 1024 * 1024 / 16, 1);
+        let n_entries = 1usize << (63 - (n_wanted as u64).leading_zeros());
+        let mut entries = Vec::with_capacity(n_entries * 2);
+        for _ in 0..n_entries * 2 {
+            entries.push(AtomicU64::new(0));
+        }
+        TranspositionTable {
+            entries: entries,
+            mask: n_entries - 1,
+        }
+    }
+
+    pub fn n_entries(&self) -> usize {
+        self.mask + 1
+    }
+
+    pub fn clear(&self) {
+        for word in &self.entries {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    #[inline(always)]
+    fn slot(&self, key: u64) -> usize {
+        (key as usize & self.mask) * 2
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TTEntry> {
+        let slot = self.slot(key);
+        let check = self.entries[slot].load(Ordering::Relaxed);
+        let data = self.entries[slot + 1].load(Ordering::Relaxed);
+        if data == 0 || check ^ data != key {
+            return None;
+        }
+        return Some(TTEntry::unpack(data));
+    }
+
+    // depth-preferred replacement: an entry for the same position is only
+    // overwritten by a search that is at least as deep
+    pub fn store(&self, key: u64, entry: TTEntry) {
+        let slot = self.slot(key);
+        if let Some(old) = self.probe(key) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let data = entry.pack();
+        self.entries[slot].store(key ^ data, Ordering::Relaxed);
+        self.entries[slot + 1].store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_probe() {
+        let tt = TranspositionTable::new(1);
+        assert!(tt.probe(12345).is_none());
+        let entry = TTEntry {
+            depth: 7,
+            bound: Bound::Lower,
+            score: -3.5,
+            best_move: Some(728),
+        };
+        tt.store(12345, entry);
+        let found = tt.probe(12345).unwrap();
+        assert_eq!(found.depth, 7);
+        assert_eq!(found.bound, Bound::Lower);
+        assert_eq!(found.score, -3.5);
+        assert_eq!(found.best_move, Some(728));
+
+        // same slot, different key
+        assert!(tt.probe(12345 + tt.n_entries() as u64).is_none());
+
+        // shallower search does not replace a deeper one
+        tt.store(12345, TTEntry { depth: 2, ..entry });
+        assert_eq!(tt.probe(12345).unwrap().depth, 7);
+
+        tt.clear();
+        assert!(tt.probe(12345).is_none());
+    }
+}
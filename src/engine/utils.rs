@@ -9,6 +9,8 @@ static mut NATURAL_LOG_TABLE: [f32; N_NATURAL_LOGS] = [0.0; N_NATURAL_LOGS];
 
 pub struct NonBlockingStdin {
     receiver: mpsc::Receiver<String>,
+    // set once stdin hits EOF (or fails) and every line has been handed out
+    closed: bool,
 }
 
 // NOTE (Gary) this should only be created once at the top of main and later
@@ -19,12 +21,20 @@ impl NonBlockingStdin {
         let (tx, rx) = mpsc::channel();
         thread::spawn(move || loop {
             let mut buf = String::new();
-            io::stdin().read_line(&mut buf).unwrap();
-            tx.send(buf).unwrap();  // convert String to &str
+            match io::stdin().read_line(&mut buf) {
+                // EOF. Dropping tx lets try_nextline know
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if tx.send(buf).is_err() {
+                        break;
+                    }
+                },
+            }
         });
 
         Self {
             receiver: rx,
+            closed: false,
         }
     }
 
@@ -32,9 +42,17 @@ impl NonBlockingStdin {
         match self.receiver.try_recv() {
             Ok(val) => Some(val),
             Err(mpsc::TryRecvError::Empty) => None,
-            Err(mpsc::TryRecvError::Disconnected) => panic!("stdin thread disconnected"),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.closed = true;
+                None
+            },
         }
     }
+
+    // true once stdin is closed and there are no more lines to read
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
 }
 
 // generate a random number with n random bits set in the lower 81 bits exactly.
@@ -72,11 +90,20 @@ pub(crate) fn side_multiplier(side: Side) -> Score {
     (1 - 2 * (side as i32)) as Score
 }
 
-// on codingame, even when the board is filled, whoever has more blocks
-// captured wins. This returns 1 if X has more captured, -1 if O does,
-// and 0 if dead drawn
+// 1 if X won, -1 if O did and 0 for a draw (or an ongoing game). Which
+// side a full board goes to depends on the position's ruleset
+#[inline(always)]
+pub(crate) fn result_sign(result: GameResult) -> f32 {
+    match result {
+        GameResult::XWon => 1.0,
+        GameResult::OWon => -1.0,
+        _ => 0.0,
+    }
+}
+
+// a score just above the given one, used as the upper end of a null window.
+// A fixed epsilon doesn't work because of f32 precision near SCORE_WIN
 #[inline(always)]
-pub(crate) fn codingame_drawn(pos: &Position) -> f32 {
-    let diff = (pos.bitboards[0].n_captured() as i16) - (pos.bitboards[1].n_captured() as i16);
-    return ((diff != 0) as i32 as f32) * (diff as f32).signum();
+pub(crate) fn score_above(score: Score) -> Score {
+    score + f32::max(1e-3, score.abs() * 4.0 * f32::EPSILON)
 }
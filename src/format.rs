@@ -1,18 +1,192 @@
 /* For importing/exporting positions based on formats */
 
 use crate::moves::*;
-use std::i64;
+use crate::rules::*;
+use std::fmt;
+use std::str::FromStr;
 
-// convert from row-major indexing to bitboard indexing
-macro_rules! to_bb_index {
-    ($row:expr, $col:expr) => {{
-        let row: usize = $row;
-        let col: usize = $col;
-        let bi = ((row / 3) * 3 + (col / 3));
-        let small_row = row % 3;
-        let small_col = col % 3;
-        (bi * 9 + (small_row * 3 + small_col)) as Idx
-    }};
+// why a string could not be turned into a Position
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // byte offset into the input where parsing went wrong
+    pub offset: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    // the input doesn't follow the format
+    Expected { expected: String, found: String },
+    // the input follows the format, but describes a position that can't
+    // come up in a game. offset is 0 for these
+    Invalid(PositionError),
+}
+
+impl ParseError {
+    pub(crate) fn expected(offset: usize, expected: &str, found: &str) -> ParseError {
+        ParseError {
+            offset: offset,
+            kind: ParseErrorKind::Expected {
+                expected: expected.to_string(),
+                found: found.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::Expected { expected, found } if found.is_empty() => {
+                write!(f, "at {}: expected {}, found end of input", self.offset, expected)
+            },
+            ParseErrorKind::Expected { expected, found } => {
+                write!(f, "at {}: expected {}, found '{}'", self.offset, expected, found)
+            },
+            ParseErrorKind::Invalid(err) => write!(f, "invalid position: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// what Position::validate found wrong
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PositionError {
+    // bits set outside of the board
+    OutOfRange,
+    // both sides have a piece on the same cell of an open block
+    Overlap { block: u8 },
+    // a side has three in a row in a block that isn't marked as its capture,
+    // or the other way around
+    BadCapture { block: u8, side: Side },
+    // the other side still made three in a row after the block was captured
+    CapturedByBoth { block: u8 },
+    // there is no way the number of X and O pieces works out with the side
+    // to move
+    PieceCount,
+    // the game was won by the side to move, or by both sides
+    BadWinner,
+    // hopeless_occ doesn't match the pieces on the board
+    Hopeless { side: Side },
+    // the previous move can't have sent the side to move to last_block
+    LastBlock(u8),
+    Hash,
+    // closed and open won blocks store captures differently, so the rule
+    // can't change once a block is captured (see Position::set_ruleset)
+    WonBlocksChange,
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::OutOfRange => write!(f, "bits set outside of the board"),
+            PositionError::Overlap { block } => write!(f, "X and O on the same cell in block {}", block),
+            PositionError::BadCapture { block, side } => write!(f, "capture of block {} by {:?} is inconsistent", block, side),
+            PositionError::CapturedByBoth { block } => write!(f, "block {} captured by both sides", block),
+            PositionError::PieceCount => write!(f, "number of X and O pieces doesn't match the side to move"),
+            PositionError::BadWinner => write!(f, "the game can't have been won by the side to move"),
+            PositionError::Hopeless { side } => write!(f, "hopeless blocks of {:?} don't match the board", side),
+            PositionError::LastBlock(block) => write!(f, "last block {} can't have been sent to", block),
+            PositionError::Hash => write!(f, "hash doesn't match the board"),
+            PositionError::WonBlocksChange => write!(f, "won blocks can't change between closed and open after a capture"),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+// BGN is the canonical text form of a Position
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_bgn())
+    }
+}
+
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Position({})", self.to_bgn())
+    }
+}
+
+impl FromStr for Position {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Position, ParseError> {
+        Position::from_bgn(repr)
+    }
+}
+
+// as a BGN string
+#[cfg(feature = "serde")]
+impl serde::Serialize for Position {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_bgn())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Position {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Position, D::Error> {
+        let repr = String::deserialize(deserializer)?;
+        Position::from_bgn(&repr).map_err(serde::de::Error::custom)
+    }
+}
+
+// byte offset of inner, which must be a slice of outer
+pub(crate) fn offset_in(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+// a block with three in a row must either be filled in (the way Bitboard
+// stores captures) or have a piece whose removal undoes the capture, i.e.
+// the move that captured it. Anything else was played after the capture
+fn capture_reachable(occ: B33) -> bool {
+    if occ == BLOCK_OCC || !get_block_won(occ) {
+        return true;
+    }
+    return (0..9).any(|cell| occ & (1 << cell) != 0 && !get_block_won(occ & !(1 << cell)));
+}
+
+// one block of a side's BGN board
+#[derive(Copy, Clone, Default)]
+pub(crate) struct BgnBlock {
+    pub(crate) occ: B33,
+    // followed by '!', see Position::from_bgn
+    pub(crate) owner_mark: bool,
+    pub(crate) offset: usize,
+}
+
+// a side's BGN board: n_blocks blocks in hex separated by "/", 9 for
+// Position. offset is where repr starts in the whole input
+pub(crate) fn parse_bgn_board(repr: &str, offset: usize, n_blocks: usize) -> Result<Vec<BgnBlock>, ParseError> {
+    let mut ret = vec![BgnBlock::default(); n_blocks];
+    let mut tokens = repr.split("/");
+    for block in ret.iter_mut() {
+        let tok = match tokens.next() {
+            Some(tok) => tok,
+            None => {
+                let expected = format!("{} blocks separated by '/'", n_blocks);
+                return Err(ParseError::expected(offset + repr.len(), &expected, ""));
+            },
+        };
+        let tok_offset = offset + offset_in(repr, tok);
+        let (hex, owner_mark) = match tok.strip_suffix('!') {
+            Some(hex) => (hex, true),
+            None => (tok, false),
+        };
+        block.occ = match B33::from_str_radix(hex, 16) {
+            Ok(occ) if occ & !BLOCK_OCC == 0 => occ,
+            _ => return Err(ParseError::expected(tok_offset, "a block in hex, up to 1ff", tok)),
+        };
+        block.owner_mark = owner_mark;
+        block.offset = tok_offset;
+    }
+    if let Some(tok) = tokens.next() {
+        let expected = format!("{} blocks", n_blocks);
+        return Err(ParseError::expected(offset + offset_in(repr, tok) - 1, &expected, "another block"));
+    }
+    return Ok(ret);
 }
 
 impl Position {
@@ -33,70 +207,77 @@ impl Position {
      If auto_side is true, automatically determine which side to move based on the numbers of pieces
      played (and the param to_move will not be used). Otherwise, use to_move as the side
     */
-    pub fn from_compact_board(repr: &str, to_move: Side, auto_side: bool) -> Position {
+    pub fn from_compact_board(repr: &str, to_move: Side, auto_side: bool) -> Result<Position, ParseError> {
+        // offsets are into the trimmed board, with \n line endings
         let repr = repr.trim();
         let repr = repr.replace("\r\n", "\n");
-        assert!(repr.len() == 133);
+        if repr.len() != 133 {
+            let found = format!("{} characters", repr.len());
+            return Err(ParseError::expected(0, "a board of 133 characters", &found));
+        }
         let mut pos = Position::new();
-        let mut n_x = 0;
-        let mut n_o = 0;
-        for (i, c) in repr.chars().enumerate() {
+        let mut n_x: i32 = 0;
+        let mut n_o: i32 = 0;
+        // each side's pieces, before filling in captured blocks
+        let mut occs = [[0 as B33; 9]; 2];
+        for (i, c) in repr.char_indices() {
             if i == 132 {
                 // 11 * 12 + 1 (space) + 1 (block index) - 1
-                if c == '-' {
-                    pos.last_block = 9;
-                } else {
-                    pos.last_block = c as u8 - '0' as u8;
-                }
+                pos.last_block = match c {
+                    '-' => 9,
+                    '0'..='8' => c as u8 - '0' as u8,
+                    _ => return Err(ParseError::expected(i, "a block index or '-'", &c.to_string())),
+                };
                 break;
             }
             if i % 4 == 3 || (i / 12) % 4 == 3 || i == 131 || i % 12 == 11 {
                 continue;
             }
-            if c == 'X' || c == 'O' {
-                let col = match i % 12 {
-                    0..=2 => i % 12,
-                    4..=6 => i % 12 - 1,
-                    8..=10 => i % 12 - 2,
-                    _ => panic!("Bad index"),
-                };
-                let row = match i / 12 {
-                    0..=2 => i / 12,
-                    4..=6 => i / 12 - 1,
-                    8..=10 => i / 12 - 2,
-                    _ => panic!("Bad index"),
-                };
-                let side = match c {
-                    'X' => {
-                        n_x += 1;
-                        Side::X
-                    }
-                    'O' => {
-                        n_o += 1;
-                        Side::O
-                    }
-                    _ => panic!("Impossible"),
-                };
-
-                // place piece
-                let own_bb = &mut pos.bitboards[side as usize];
-                let bi = own_bb.set(to_bb_index!(row, col));
-                let block_occ = own_bb.get_block(bi);
+            let side = match c {
+                'X' => {
+                    n_x += 1;
+                    Side::X
+                },
+                'O' => {
+                    n_o += 1;
+                    Side::O
+                },
+                '.' | '-' | '_' => continue,
+                _ => return Err(ParseError::expected(i, "'X', 'O' or an empty cell ('.', '-' or '_')", &c.to_string())),
+            };
+            // the separators are skipped above, so i % 12 and i / 12 are
+            // in 0..=2, 4..=6 or 8..=10
+            let col = i % 12 - (i % 12) / 4;
+            let row = i / 12 - (i / 12) / 4;
+            let index = Square::from_row_col(row as u8, col as u8).unwrap().idx();
+            occs[side as usize][index as usize / 9] |= 1 << (index % 9);
+        }
 
-                // update hopeless occ for the other player
-                pos.hopeless_occ[side.other() as usize] |= (get_block_hopeless(block_occ) as B33) << bi;
+        for side in Side::iterator() {
+            for bi in 0..9 {
+                let occ = occs[*side as usize][bi as usize];
+                if !capture_reachable(occ) {
+                    // the block's top left cell: 4 lines of 12 characters
+                    // per row of blocks, 4 characters per column
+                    let offset = (bi as usize / 3) * 48 + (bi as usize % 3) * 4;
+                    return Err(ParseError::expected(offset, "no pieces after a block is captured", &format!("block {}", bi)));
+                }
+                pos.bitboards[*side as usize].set_block(bi, occ);
             }
         }
+        pos.hopeless_occ = [pos.compute_hopeless(Side::X), pos.compute_hopeless(Side::O)];
+
         if auto_side {
             pos.to_move = match n_x - n_o {
                 0 => Side::X,
                 1 => Side::O,
-                _ => panic!("Number of X and O not possible"),
+                _ => return Err(ParseError::expected(0, "as many X as O, or one more", &format!("{} X and {} O", n_x, n_o))),
             };
         } else {
             pos.to_move = to_move;
         }
-        return pos;
+        pos.hash = pos.compute_hash();
+        return pos.validated();
     }
 
     pub fn to_pretty_board(&self) -> String {
@@ -112,7 +293,7 @@ impl Position {
                 let out_row = row + row_offset;
                 let out_col = 2 * (col + col_offset) + 1;
                 let out_ind = out_row * 24 + out_col;
-                let index = to_bb_index!(row, col);
+                let index = Square::from_row_col(row as u8, col as u8).unwrap().idx();
                 if self.bitboards[Side::X as usize].get(index) {
                     repr[out_ind as usize] = 'X';
                 } else if self.bitboards[Side::O as usize].get(index) {
@@ -152,86 +333,289 @@ impl Position {
         return repr.iter().collect::<String>();
     }
 
-    pub fn from_bgn(repr: &str) -> Position {
+    // '2 <X board> <O board> <last block> <side to move> [<ruleset>]', where
+    // the ruleset is left out for the default one (see rules.rs). A board is
+    // the side's pieces in each block, in hex and separated by '/'. Closed
+    // won blocks are filled in with the capturer's pieces. Open ones are
+    // not, and if both sides have three in a row in one, the owner's block
+    // gets a '!' after it
+    pub fn from_bgn(repr: &str) -> Result<Position, ParseError> {
         let mut pos = Position::new();
         let mut tokens = repr.split_whitespace();
-        let level = tokens.next();
-
-        // only support level 2 ultimate tic-tac-toe
-        assert_eq!(level, Some("2"));
-
-        let x_board = tokens.next().expect("too few tokens!");
-        let o_board = tokens.next().expect("too few tokens!");
+        let mut next_token = |expected: &str| -> Result<&str, ParseError> {
+            tokens.next().ok_or(ParseError::expected(repr.len(), expected, ""))
+        };
 
-        let focus_block = tokens.next().expect("too few tokens: need focus block");
-        assert_eq!(focus_block.len(), 1);
+        // only level 2 ultimate tic-tac-toe, see board.rs for the others
+        let level = next_token("level")?;
+        if level != "2" {
+            return Err(ParseError::expected(offset_in(repr, level), "level 2", level));
+        }
 
-        let to_move = tokens.next().expect("too few tokens: need side to move");
-        assert_eq!(to_move.len(), 1);
+        let x_board = next_token("X board")?;
+        let x_blocks = parse_bgn_board(x_board, offset_in(repr, x_board), 9)?;
+        let o_board = next_token("O board")?;
+        let o_blocks = parse_bgn_board(o_board, offset_in(repr, o_board), 9)?;
 
-        let x_count = pos.init_bgn_bb(Side::X, x_board);
-        let o_count = pos.init_bgn_bb(Side::O, o_board);
-        if x_count == o_count {
-            pos.to_move = Side::X;
-        } else if x_count - o_count == 1 {
-            pos.to_move = Side::O;
-        } else {
-            panic!("incorrect number of X/O pieces!");
-        }
+        let focus_block = next_token("focus block")?;
+        pos.last_block = match focus_block {
+            "-" => 9,
+            _ => match focus_block.parse::<u8>() {
+                Ok(block) if block <= 9 => block,
+                _ => return Err(ParseError::expected(offset_in(repr, focus_block), "a block index or '-'", focus_block)),
+            },
+        };
 
-        pos.last_block = focus_block.parse().unwrap();
+        let to_move = next_token("side to move")?;
         pos.to_move = match to_move {
             "X" => Side::X,
             "O" => Side::O,
-            other => panic!("to_move must be 'X' or 'O', but got '{}' instead", other),
+            other => return Err(ParseError::expected(offset_in(repr, to_move), "'X' or 'O'", other)),
         };
-        return pos;
+
+        if let Some(tok) = tokens.next() {
+            pos.ruleset = match tok.parse::<Ruleset>() {
+                Ok(ruleset) => ruleset,
+                Err(_) => return Err(ParseError::expected(offset_in(repr, tok), "a ruleset", tok)),
+            };
+        }
+        if let Some(tok) = tokens.next() {
+            return Err(ParseError::expected(offset_in(repr, tok), "end of input", tok));
+        }
+        // how the boards are read depends on the ruleset
+        pos.set_bgn_blocks(&[x_blocks, o_blocks])?;
+        pos.hopeless_occ = [pos.compute_hopeless(Side::X), pos.compute_hopeless(Side::O)];
+        pos.hash = pos.compute_hash();
+        return pos.validated();
     }
 
     pub fn to_bgn(&self) -> String {
         let x_board = self.to_side_bgn(Side::X);
         let o_board = self.to_side_bgn(Side::O);
         let to_move = match self.to_move { Side::X => "X", Side::O => "O" };
-        return format!("2 {} {} {} {}", x_board, o_board, self.last_block, to_move);
+        let mut ret = format!("2 {} {} {} {}", x_board, o_board, self.last_block, to_move);
+        if self.ruleset != Ruleset::default() {
+            ret.push_str(&format!(" {}", self.ruleset));
+        }
+        return ret;
     }
 
     fn to_side_bgn(&self, side: Side) -> String {
         let bitboard = self.bitboards[side as usize];
+        let other_bb = self.bitboards[side.other() as usize];
         let mut str_list = Vec::new();
         for bi in 0..9 {
             let occ = bitboard.get_block(bi);
-            str_list.push(format!("{:x}", occ));
+            let owned = bitboard.captured_occ() & (1 << bi) != 0;
+            if owned && get_block_won(other_bb.get_block(bi)) && self.ruleset.won_blocks.is_open() {
+                str_list.push(format!("{:x}!", occ));
+            } else {
+                str_list.push(format!("{:x}", occ));
+            }
         }
         return str_list.join("/");
     }
-    
-    // initialize bitboard of side using repr, a string of blocks
-    // represented by hex, separated by "/"
-    fn init_bgn_bb(&mut self, side: Side, repr: &str) -> u32 {
-        let bitboard = &mut self.bitboards[side as usize];
-        let mut tokens = repr.split("/");
-        
-        let mut count: u32 = 0;
+
+    // the bitboards from each side's BGN blocks, under self.ruleset
+    fn set_bgn_blocks(&mut self, blocks: &[Vec<BgnBlock>; 2]) -> Result<(), ParseError> {
         for bi in 0..9 {
-            let tok = tokens.next().expect("too few blocks given. 9 expected");
-            let occ = i64::from_str_radix(tok.trim(), 16).expect("could not parse hex string") as B33;
-            count += occ.count_ones();
-            bitboard.set_block(bi, occ);
-            // update hopeless occ
-            self.hopeless_occ[self.to_move as usize] |= (get_block_hopeless(occ) as B33) << bi;
+            let pair = [blocks[0][bi as usize], blocks[1][bi as usize]];
+            if !self.ruleset.won_blocks.is_open() {
+                for side in Side::iterator() {
+                    let block = pair[*side as usize];
+                    if block.owner_mark {
+                        return Err(ParseError::expected(block.offset, "no '!' unless won blocks are open", &format!("{:x}!", block.occ)));
+                    }
+                    if !capture_reachable(block.occ) {
+                        return Err(ParseError::expected(block.offset, "no pieces after a block is captured", &format!("{:x}", block.occ)));
+                    }
+                    self.bitboards[*side as usize].set_block(bi, block.occ);
+                }
+                continue;
+            }
+
+            // the owner is the side with three in a row, or the one marked
+            // with '!' if both have one
+            let won = [get_block_won(pair[0].occ), get_block_won(pair[1].occ)];
+            for block in pair.iter() {
+                if block.owner_mark && !(won[0] && won[1]) {
+                    let found = format!("{:x}!", block.occ);
+                    return Err(ParseError::expected(block.offset, "'!' only where both sides have three in a row", &found));
+                }
+            }
+            let owner = match (won[0], won[1], pair[0].owner_mark, pair[1].owner_mark) {
+                (true, true, true, false) | (true, false, _, _) => Some(Side::X),
+                (true, true, false, true) | (false, true, _, _) => Some(Side::O),
+                (true, true, _, _) => {
+                    let found = format!("{:x}", pair[0].occ);
+                    return Err(ParseError::expected(pair[0].offset, "a '!' on exactly one side's block", &found));
+                },
+                (false, false, _, _) => None,
+            };
+            for side in Side::iterator() {
+                let owned = owner == Some(*side);
+                self.bitboards[*side as usize].set_block_open(bi, pair[*side as usize].occ, owned);
+            }
         }
-        
-        return count;
+        return Ok(());
     }
-
+    
     // comma separated list of moves
-    pub fn from_move_list(repr: &str) -> Position {
+    pub fn from_move_list(repr: &str) -> Result<Position, ParseError> {
         let mut pos = Position::new();
+        if repr.trim().is_empty() {
+            return Ok(pos);
+        }
         let tokens = repr.split(",");
         for tok in tokens {
             let tok = tok.trim();
-            pos.make_move(tok.parse::<Idx>().unwrap());
+            let offset = offset_in(repr, tok);
+            if pos.is_over() {
+                return Err(ParseError::expected(offset, "end of the game", tok));
+            }
+            match tok.parse::<Idx>() {
+                Ok(mov) if mov < 81 && pos.legal_moves().contains(mov) => {
+                    pos.make_move(mov);
+                },
+                _ => return Err(ParseError::expected(offset, "a legal move", tok)),
+            }
+        }
+        return Ok(pos);
+    }
+
+    fn validated(self) -> Result<Position, ParseError> {
+        match self.validate() {
+            Ok(()) => Ok(self),
+            Err(err) => Err(ParseError {
+                offset: 0,
+                kind: ParseErrorKind::Invalid(err),
+            }),
+        }
+    }
+
+    // open won blocks aren't filled in: the owner is whoever got three in
+    // a row first, and the block may fill up after that
+    fn validate_open_block(&self, bi: u8, counts: &mut [(i32, i32); 2]) -> Result<(), PositionError> {
+        let bbs = [self.bitboards[0], self.bitboards[1]];
+        let occs = [bbs[0].get_block(bi), bbs[1].get_block(bi)];
+        let captured = [bbs[0].captured_occ() & (1 << bi) != 0, bbs[1].captured_occ() & (1 << bi) != 0];
+        if captured[0] && captured[1] {
+            return Err(PositionError::CapturedByBoth { block: bi });
+        }
+        if occs[0] & occs[1] != 0 {
+            return Err(PositionError::Overlap { block: bi });
+        }
+        for side in Side::iterator() {
+            let s = *side as usize;
+            let won = get_block_won(occs[s]);
+            // a capture needs three in a row, and three in a row without
+            // the capture means the other side got there first
+            if captured[s] != won && !(won && captured[1 - s]) {
+                return Err(PositionError::BadCapture { block: bi, side: *side });
+            }
+            let n = occs[s].count_ones() as i32;
+            counts[s].0 += n;
+            counts[s].1 += n;
+        }
+        return Ok(());
+    }
+
+    /*
+    Checks that the position could have come up in a game. Positions made
+    with make_move always pass. A last_block pointing at a full block is
+    only rejected when make_move couldn't have produced it, i.e. when the
+    side that just moved has no piece on that cell of any block: a move
+    sending the opponent to a full block is legal, and gives them a free
+    move.
+    */
+    pub fn validate(&self) -> Result<(), PositionError> {
+        let x_bb = self.bitboards[Side::X as usize];
+        let o_bb = self.bitboards[Side::O as usize];
+        let full_bits = (1u128 << (81 + 9)) - 1;
+        if (x_bb.0 | o_bb.0) & !full_bits != 0 || self.last_block > 9 {
+            return Err(PositionError::OutOfRange);
+        }
+
+        // possible number of pieces for each side, as (min, max). Captured
+        // blocks are filled in, so the capturer's count there is a range
+        let mut counts = [(0i32, 0i32); 2];
+        for bi in 0..9 {
+            if self.ruleset.won_blocks.is_open() {
+                self.validate_open_block(bi, &mut counts)?;
+                continue;
+            }
+            let occs = [x_bb.get_block(bi), o_bb.get_block(bi)];
+            let captured = [x_bb.captured_occ() & (1 << bi) != 0, o_bb.captured_occ() & (1 << bi) != 0];
+            for side in Side::iterator() {
+                let s = *side as usize;
+                // captured exactly when filled in, and three in a row
+                // always means captured
+                if captured[s] != (occs[s] == BLOCK_OCC) || (!captured[s] && get_block_won(occs[s])) {
+                    return Err(PositionError::BadCapture { block: bi, side: *side });
+                }
+            }
+            match (captured[0], captured[1]) {
+                (true, true) => return Err(PositionError::CapturedByBoth { block: bi }),
+                (false, false) => {
+                    if occs[0] & occs[1] != 0 {
+                        return Err(PositionError::Overlap { block: bi });
+                    }
+                    for s in 0..2 {
+                        let n = occs[s].count_ones() as i32;
+                        counts[s].0 += n;
+                        counts[s].1 += n;
+                    }
+                },
+                _ => {
+                    let capturer = if captured[0] { 0 } else { 1 };
+                    let other = 1 - capturer;
+                    let n_other = occs[other].count_ones() as i32;
+                    // the capturer has three in a row of its own
+                    if n_other > 6 {
+                        return Err(PositionError::CapturedByBoth { block: bi });
+                    }
+                    counts[other].0 += n_other;
+                    counts[other].1 += n_other;
+                    counts[capturer].0 += 3;
+                    counts[capturer].1 += 9 - n_other;
+                },
+            }
+        }
+        // X moves first, so X has as many pieces as O when X is to move,
+        // and one more when O is
+        let diff = match self.to_move {
+            Side::X => 0,
+            Side::O => 1,
+        };
+        let (x_count, o_count) = (counts[0], counts[1]);
+        if diff < x_count.0 - o_count.1 || diff > x_count.1 - o_count.0 {
+            return Err(PositionError::PieceCount);
+        }
+
+        // only the side that just moved can have won, unless a block it
+        // tied counts for the other side as well
+        if get_block_won(self.bitboards[self.to_move as usize].captured_occ()) {
+            return Err(PositionError::BadWinner);
+        }
+
+        for side in Side::iterator() {
+            if self.compute_hopeless(*side) != self.hopeless_occ[*side as usize] {
+                return Err(PositionError::Hopeless { side: *side });
+            }
+        }
+
+        // the previous move was played on cell last_block of some block, by
+        // the side not to move. Filled in captures count, since the
+        // capturing move could have been anywhere in the block
+        let last_mover = self.bitboards[self.to_move.other() as usize];
+        let no_pieces = (x_bb.0 | o_bb.0) == 0;
+        if self.last_block < 9 && (no_pieces || !(0..9).any(|bi| last_mover.get(bi * 9 + self.last_block))) {
+            return Err(PositionError::LastBlock(self.last_block));
+        }
+
+        if self.hash != self.compute_hash() {
+            return Err(PositionError::Hash);
         }
-        return pos;
+        return Ok(());
     }
 }
@@ -0,0 +1,274 @@
+/*
+What it takes to play a game: enough for perft, and for both search engines
+to run on something other than Position. Position is the main
+implementation; board.rs has ultimate boards of other levels, including
+plain tic-tac-toe, where the perfect-play answers are known, which makes it
+a good check on the engines themselves.
+
+Besides the rules, the engines need a few things that have defaults:
+captures for move ordering and quiescence search, and a static evaluation
+for alpha-beta. Without them alpha-beta is a plain negamax on results.
+The MCTS rollout policies and priors use the same hooks, plus a couple of
+cheap per-move ones; without them they play like the uniform policy.
+*/
+
+use std::fmt;
+
+use crate::board::*;
+use crate::engine::config::Score;
+use crate::engine::eval::{eval, eval_move};
+use crate::moves::*;
+
+// a set of moves, as returned by Game::legal_moves. Cheap set operations are
+// what lets move ordering keep up with the search
+pub trait MoveSet<M>: Copy + Iterator<Item = M> {
+    fn empty() -> Self;
+    fn size(&self) -> usize;
+    fn contains(&self, mov: M) -> bool;
+    fn remove(&mut self, mov: M);
+    fn intersect(&self, other: Self) -> Self;
+    fn subtract(&self, other: Self) -> Self;
+    // the nth move in iteration order
+    fn nth_move(&self, n: usize) -> M;
+}
+
+pub trait Game: Clone {
+    type Move: Copy + Eq + Default + fmt::Debug;
+    type Moves: MoveSet<Self::Move>;
+
+    // NOTE only called when the game isn't over
+    fn legal_moves(&self) -> Self::Moves;
+    fn make_move(&mut self, mov: Self::Move);
+    // GameResult::Ongoing until the game is over
+    fn get_result(&self) -> GameResult;
+    fn side_to_move(&self) -> Side;
+    // Zobrist-like hash, equal for equal positions
+    fn hash(&self) -> u64;
+
+    fn is_over(&self) -> bool {
+        self.get_result() != GameResult::Ongoing
+    }
+
+    // every move as a number below n_move_indices, the same in any position
+    // of the game. The engines index their tables with it
+    fn move_index(mov: Self::Move) -> usize;
+    fn index_move(index: usize) -> Self::Move;
+    fn n_move_indices(&self) -> usize;
+
+    // moves that would win a block for side, whether or not it's their turn
+    fn captures(&self, _side: Side) -> Self::Moves {
+        Self::Moves::empty()
+    }
+
+    // heuristic score for the side to move, called by alpha-beta on
+    // positions that aren't over
+    fn evaluate(&self) -> Score {
+        0.0
+    }
+
+    // whether mov lets the opponent play anywhere next
+    fn sends_to_free_move(&self, _mov: Self::Move) -> bool {
+        false
+    }
+
+    // what mov gains for the side to move, a much cheaper guess than
+    // comparing evaluate before and after
+    fn eval_move(&self, _mov: Self::Move) -> Score {
+        0.0
+    }
+}
+
+impl MoveSet<Idx> for Moves {
+    #[inline(always)]
+    fn empty() -> Moves {
+        Moves::new()
+    }
+
+    #[inline(always)]
+    fn size(&self) -> usize {
+        Moves::size(self)
+    }
+
+    #[inline(always)]
+    fn contains(&self, mov: Idx) -> bool {
+        Moves::contains(self, mov)
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, mov: Idx) {
+        Moves::remove(self, mov);
+    }
+
+    #[inline(always)]
+    fn intersect(&self, other: Moves) -> Moves {
+        Moves::intersect(self, other)
+    }
+
+    #[inline(always)]
+    fn subtract(&self, other: Moves) -> Moves {
+        Moves::subtract(self, other)
+    }
+
+    #[inline(always)]
+    fn nth_move(&self, n: usize) -> Idx {
+        Moves::nth_move(self, n as u8)
+    }
+}
+
+impl MoveSet<Cell> for Cells {
+    fn empty() -> Cells {
+        Cells::new()
+    }
+
+    fn size(&self) -> usize {
+        Cells::size(self)
+    }
+
+    fn contains(&self, mov: Cell) -> bool {
+        Cells::contains(self, mov)
+    }
+
+    fn remove(&mut self, mov: Cell) {
+        Cells::remove(self, mov);
+    }
+
+    fn intersect(&self, other: Cells) -> Cells {
+        Cells::intersect(self, other)
+    }
+
+    fn subtract(&self, other: Cells) -> Cells {
+        Cells::subtract(self, other)
+    }
+
+    fn nth_move(&self, n: usize) -> Cell {
+        Cells::nth_move(self, n)
+    }
+}
+
+impl Game for Position {
+    type Move = Idx;
+    type Moves = Moves;
+
+    #[inline]
+    fn legal_moves(&self) -> Moves {
+        Position::legal_moves(self)
+    }
+
+    #[inline]
+    fn make_move(&mut self, mov: Idx) {
+        Position::make_move(self, mov);
+    }
+
+    #[inline]
+    fn get_result(&self) -> GameResult {
+        Position::get_result(self)
+    }
+
+    #[inline]
+    fn side_to_move(&self) -> Side {
+        Position::side_to_move(self)
+    }
+
+    #[inline]
+    fn hash(&self) -> u64 {
+        Position::hash(self)
+    }
+
+    #[inline]
+    fn is_over(&self) -> bool {
+        Position::is_over(self)
+    }
+
+    #[inline(always)]
+    fn move_index(mov: Idx) -> usize {
+        mov as usize
+    }
+
+    #[inline(always)]
+    fn index_move(index: usize) -> Idx {
+        index as Idx
+    }
+
+    #[inline]
+    fn n_move_indices(&self) -> usize {
+        81
+    }
+
+    #[inline(always)]
+    fn captures(&self, side: Side) -> Moves {
+        self.get_1occ(side)
+    }
+
+    #[inline]
+    fn evaluate(&self) -> Score {
+        eval(self)
+    }
+
+    #[inline]
+    fn sends_to_free_move(&self, mov: Idx) -> bool {
+        let mut next = *self;
+        next.make_move(mov);
+        !next.is_over() && next.forced_block().is_none()
+    }
+
+    #[inline]
+    fn eval_move(&self, mov: Idx) -> Score {
+        eval_move(self, mov)
+    }
+}
+
+impl Game for Board {
+    type Move = Cell;
+    type Moves = Cells;
+
+    fn legal_moves(&self) -> Cells {
+        Board::legal_moves(self)
+    }
+
+    fn make_move(&mut self, mov: Cell) {
+        Board::make_move(self, mov);
+    }
+
+    fn get_result(&self) -> GameResult {
+        Board::get_result(self)
+    }
+
+    fn side_to_move(&self) -> Side {
+        Board::side_to_move(self)
+    }
+
+    fn hash(&self) -> u64 {
+        Board::hash(self)
+    }
+
+    fn move_index(mov: Cell) -> usize {
+        mov as usize
+    }
+
+    fn index_move(index: usize) -> Cell {
+        index as Cell
+    }
+
+    fn n_move_indices(&self) -> usize {
+        self.n_cells()
+    }
+}
+
+// moves::perft for any game: the number of legal moves at depth, not
+// counting finished games
+pub fn perft_game<G: Game>(depth: u16, game: &G) -> u64 {
+    if game.is_over() {
+        return 0;
+    }
+    let moves = game.legal_moves();
+    if depth == 0 {
+        return moves.count() as u64;
+    }
+    let mut count: u64 = 0;
+    for mov in moves {
+        let mut temp = game.clone();
+        temp.make_move(mov);
+        count += perft_game(depth - 1, &temp);
+    }
+    return count;
+}
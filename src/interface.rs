@@ -0,0 +1,268 @@
+use std::fs;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+
+extern crate uttt;
+
+use uttt::engine::*;
+use uttt::engine::mcts::*;
+use uttt::moves::*;
+use uttt::record::*;
+use uttt::rules::*;
+use uttt::uti::EngineKind;
+
+/*
+Play against the engine in the terminal. Moves are entered as '<row> <col>'
+(0-8, like on Codingame) or in any notation Square accepts, like 'c3/b2' or
+'<block>.<cell>' (see moves.rs). Type 'help' for the other commands.
+*/
+
+const HELP: &str = "\
+commands:
+  <row> <col>        play a move, e.g. '4 4' for the center
+  <block>/<cell>     play a move, e.g. 'b2/b2' for the center; blocks and
+                     cells go a-c from the left and 1-3 from the bottom
+  <block>.<cell>     play a move, e.g. '4.4' for the center
+  undo               take back your last move (and the engine's reply)
+  swap               swap sides; the engine plays the side to move
+  engine <name>      choose the engine, 'alphabeta' or 'mcts'
+  time <ms>          engine thinking time per move
+  save <file>        save the game record
+  new                start a new game
+  rules <ruleset>    start a new game under other rules, e.g. 'standard' or
+                     'codingame+tied-both'
+  help               show this message
+  quit               leave";
+
+const MCTS_C: f32 = 0.85;
+
+// reverse video, used for the block the side to move is sent to
+const HIGHLIGHT_ON: &str = "\x1b[7m";
+const HIGHLIGHT_OFF: &str = "\x1b[0m";
+
+struct Game {
+    history: GameHistory,
+    engine_side: Side,
+    engine: EngineKind,
+    millis: u64,
+    // kept across moves for its transposition table
+    manager: Manager,
+}
+
+fn main() {
+    init_moves();
+    init_engine();
+    let mut game = Game::new();
+    println!("{}", HELP);
+    loop {
+        let pos = game.pos();
+        println!();
+        println!("{}", render(&pos));
+        if pos.is_over() {
+            let msg = match pos.get_result() {
+                GameResult::XWon => "X wins",
+                GameResult::OWon => "O wins",
+                _ => "draw",
+            };
+            println!("game over: {}", msg);
+        } else if pos.side_to_move() == game.engine_side {
+            game.engine_move();
+            continue;
+        } else {
+            let side = match pos.side_to_move() {
+                Side::X => 'X',
+                Side::O => 'O',
+            };
+            match pos.forced_block() {
+                Some(block) => println!("{} to move in block {}", side, block),
+                None => println!("{} to move anywhere", side),
+            }
+        }
+
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let split = line.split_whitespace().collect::<Vec<&str>>();
+        if split.is_empty() {
+            continue;
+        }
+        match split[0] {
+            "undo" => game.undo(),
+            "swap" => game.engine_side = game.engine_side.other(),
+            "engine" => match split.get(1).map(|tok| tok.parse::<EngineKind>()) {
+                Some(Ok(engine)) => game.engine = engine,
+                _ => println!("engine is either 'alphabeta' or 'mcts'"),
+            },
+            "time" => match split.get(1).map(|tok| tok.parse::<u64>()) {
+                Some(Ok(millis)) if millis > 0 => game.millis = millis,
+                _ => println!("time needs a number of milliseconds"),
+            },
+            "save" => match split.get(1) {
+                Some(path) => match game.record() {
+                    Ok(record) => match fs::write(path, record.to_string()) {
+                        Ok(()) => println!("saved to {}", path),
+                        Err(e) => println!("could not save: {}", e),
+                    },
+                    Err(e) => println!("could not save: {}", e),
+                },
+                None => println!("save needs a file name"),
+            },
+            "new" => game.history = GameHistory::new(Position::with_ruleset(pos.ruleset())),
+            "rules" => match split.get(1).map(|tok| tok.parse::<Ruleset>()) {
+                Some(Ok(ruleset)) => {
+                    game.history = GameHistory::new(Position::with_ruleset(ruleset));
+                    println!("new game under {} rules", ruleset);
+                },
+                Some(Err(e)) => println!("invalid ruleset: {}", e),
+                None => println!("rules needs a ruleset, e.g. 'standard'"),
+            },
+            "help" => println!("{}", HELP),
+            "quit" => break,
+            _ => match parse_move(&split) {
+                Some(mov) => {
+                    if pos.is_over() || !pos.legal_moves().contains(mov) {
+                        println!("illegal move");
+                    } else {
+                        game.play(mov);
+                    }
+                },
+                None => println!("unknown command, try 'help'"),
+            },
+        }
+    }
+}
+
+impl Game {
+    fn new() -> Game {
+        Game {
+            history: GameHistory::new(Position::new()),
+            engine_side: Side::O,
+            engine: EngineKind::Mcts,
+            millis: 1000,
+            manager: Manager::from_position(Position::new()),
+        }
+    }
+
+    fn pos(&self) -> Position {
+        *self.history.position()
+    }
+
+    fn play(&mut self, mov: Idx) {
+        self.history.make_move(mov);
+    }
+
+    // takes back moves until it is the human's turn again, which is usually
+    // two plies
+    fn undo(&mut self) {
+        if self.history.undo().is_none() {
+            println!("nothing to undo");
+            return;
+        }
+        while !self.history.is_empty() && self.pos().side_to_move() == self.engine_side {
+            self.history.undo();
+        }
+        // the engine would just replay its move otherwise
+        if self.pos().side_to_move() == self.engine_side {
+            self.engine_side = self.engine_side.other();
+            println!("you now play the other side");
+        }
+    }
+
+    fn engine_move(&mut self) {
+        let pos = self.pos();
+        println!("thinking for {} ms...", self.millis);
+        let (mov, eval) = match self.engine {
+            EngineKind::AlphaBeta => {
+                self.manager.set_position(pos);
+                // only the final result is shown, so progress goes nowhere
+                let (tx, _rx) = mpsc::channel();
+                self.manager.set_progress_channel(tx);
+                let res = self.manager.search(SearchLimits::movetime(self.millis), Arc::new(AtomicBool::new(false)));
+                println!("depth {}, {} nodes", res.depth, res.nodes);
+                (res.best_move, res.eval)
+            },
+            EngineKind::Mcts => {
+                let mut mcts = MCTSWorker::new(pos, MCTS_C, SmallRng::from_entropy());
+                let (res, n_rollouts) = mcts.go(MCTSLimits::movetime(self.millis), Arc::new(AtomicBool::new(false)));
+                println!("{} rollouts", n_rollouts);
+                (res.best_move, res.value)
+            },
+        };
+        let square = Square::from_idx(mov).unwrap();
+        println!("engine plays {} = {} {} (eval {})", square, square.row(), square.col(), eval);
+        self.play(mov);
+    }
+
+    fn record(&self) -> Result<GameRecord, RecordError> {
+        let mut ret = GameRecord::new();
+        ret.set_ruleset(self.pos().ruleset())?;
+        for mov in self.history.moves() {
+            ret.push_move(RecordMove::new(mov))?;
+        }
+        let (engine_name, engine_header) = match self.engine_side {
+            Side::X => (HEADER_X, HEADER_X_ENGINE),
+            Side::O => (HEADER_O, HEADER_O_ENGINE),
+        };
+        let human_name = if self.engine_side == Side::X { HEADER_O } else { HEADER_X };
+        ret.set_header(human_name, "human")?;
+        ret.set_header(engine_name, "engine")?;
+        ret.set_header(engine_header, &self.engine.to_string())?;
+        ret.set_header(HEADER_TIME_CONTROL, &format!("{}ms/move", self.millis))?;
+        return Ok(ret);
+    }
+}
+
+// '<row> <col>' or anything Square accepts
+fn parse_move(split: &[&str]) -> Option<Idx> {
+    let square = match split {
+        [row, col] => Square::from_row_col(row.parse().ok()?, col.parse().ok()?),
+        [square] => square.parse().ok(),
+        _ => None,
+    };
+    square.map(|square| square.idx())
+}
+
+// to_pretty_board with row/col numbers around it, and the block the side to
+// move is sent to highlighted
+fn render(pos: &Position) -> String {
+    let board = pos.to_pretty_board();
+    let highlight = if pos.is_over() { None } else { pos.forced_block() };
+    let mut ret = String::from("   0 1 2   3 4 5   6 7 8\n");
+    let mut row = 0;
+    // the last line of to_pretty_board has the side to move and last block
+    // glued to it, which the caller prints in words instead
+    for (line_i, line) in board.lines().enumerate() {
+        let line = &line[..std::cmp::min(line.len(), 23)];
+        // every fourth line is a separator
+        if line_i % 4 == 3 {
+            ret.push_str("  ");
+            ret.push_str(line);
+            ret.push('\n');
+            continue;
+        }
+        ret.push_str(&format!("{} ", row));
+        for (col_i, c) in line.chars().enumerate() {
+            // cells are at odd columns, skipping the bars
+            let on_cell = col_i % 2 == 1 && c != '|';
+            let block = row / 3 * 3 + (col_i / 8) as u8;
+            if on_cell && highlight == Some(block) {
+                ret.push_str(HIGHLIGHT_ON);
+                ret.push(c);
+                ret.push_str(HIGHLIGHT_OFF);
+            } else {
+                ret.push(c);
+            }
+        }
+        ret.push('\n');
+        row += 1;
+    }
+    return ret;
+}
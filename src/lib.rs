@@ -1,3 +1,8 @@
 pub mod moves;
 pub mod format;
 pub mod engine;
+pub mod uti;
+pub mod record;
+pub mod rules;
+pub mod game;
+pub mod board;
@@ -1,11 +1,24 @@
 use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
 
 extern crate uttt;
 
 use uttt::engine::*;
+use uttt::engine::mcts::*;
+use uttt::engine::rollout::*;
+use uttt::engine::selection::*;
 use uttt::engine::utils::*;
 use uttt::moves::*;
+use uttt::rules::*;
+use uttt::uti::*;
 
+// speaks UTI over stdin/stdout, see uti.rs for the protocol
 fn main() {
     let mut nb_stdin = NonBlockingStdin::new();
     init_moves();
@@ -16,115 +29,265 @@ fn main() {
         let line = nb_stdin.try_nextline();
         match line {
             Some(line) => {
-                eprintln!("NOTE: received command: {}", line);
-                let split = line.split_whitespace().collect::<Vec<&str>>();
-                match split[0] {
-                    "uti" => println!("utiok"),
-                    "id" => println!("myid name=barbar;version=0.0.1"),
-                    "pos" => client.handle_pos(split),
-                    "search" => client.handle_search(split),
-                    _ => eprintln!("unknown command: '{}'", split[0]),
-                };
+                eprintln!("NOTE: received command: {}", line.trim_end());
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match line.parse::<Command>() {
+                    // a search in progress dies with the process
+                    Ok(Command::Quit) => break,
+                    Ok(cmd) => client.handle_command(cmd),
+                    Err(msg) => send(Response::Error(msg)),
+                }
+            },
+            None => {
+                // stdin is gone; report the last search before leaving
+                if nb_stdin.closed() && !client.searching {
+                    break;
+                }
+                // don't hog a core that the search could use
+                thread::sleep(Duration::from_millis(1));
             },
-            None => {}
         };
     }
 }
 
+fn send(res: Response) {
+    eprintln!("NOTE: sending '{}'", res);
+    println!("{}", res);
+}
+
 struct Client {
     pos: Position,
+    // kept across searches for its transposition table
+    manager: Manager,
+    engine: EngineKind,
+    mcts_c: f32,
+    mcts_selection: SelectionKind,
+    mcts_final_move: FinalMoveCriterion,
+    mcts_threads: usize,
+    mcts_rollout: RolloutPolicy,
+    mcts_priors: Priors,
+    // None for no cap, see MCTSWorker::set_memory_cap
+    mcts_memory_mb: Option<usize>,
+    // for every `pos start` from now on
+    ruleset: Ruleset,
     searching: bool,
-    receiver: Option<mpsc::Receiver<SearchResult>>,
+    receiver: Option<mpsc::Receiver<Response>>,
+    // stops the search in progress
+    stop: Arc<AtomicBool>,
 }
 
 impl Client {
     fn new() -> Client {
         Client {
             pos: Position::new(),
+            manager: Manager::from_position(Position::new()),
+            engine: EngineKind::AlphaBeta,
+            mcts_c: 0.85,
+            mcts_selection: SelectionKind::default(),
+            mcts_final_move: FinalMoveCriterion::default(),
+            mcts_threads: 0,
+            mcts_rollout: RolloutPolicy::default(),
+            mcts_priors: Priors::default(),
+            mcts_memory_mb: Some(1024),
+            ruleset: Ruleset::default(),
             searching: false,
             receiver: None,
+            stop: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    // called every loop. Sends whatever needed to UTI 
+    // called every loop. Sends whatever needed to UTI
     fn tic(&mut self) {
         if self.searching {
             // check if search finished
             match self.receiver.as_ref().unwrap().try_recv() {
-                Ok(search_res) => {
+                Ok(res) => {
                     self.searching = false;
-                    eprintln!("NOTE: sending 'info best_move={}; eval={}'", search_res.best_move, search_res.eval);
-                    println!("info best_move={}; eval={}", search_res.best_move, search_res.eval);
+                    send(res);
                 },
                 Err(mpsc::TryRecvError::Empty) => {},
-                Err(mpsc::TryRecvError::Disconnected) => panic!("fatal: search channel disconnected"),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // the search thread died without an answer
+                    self.searching = false;
+                    send(Response::Error("the search failed".to_string()));
+                },
             }
         }
     }
 
-    fn handle_pos(&mut self, split: Vec<&str>) {
-        if split.len() < 2 {
-            eprintln!("error: pos command needs a subcommand");
-            return;
+    fn handle_command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Uti => {
+                for spec in option_specs() {
+                    send(Response::Option(spec));
+                }
+                send(Response::UtiOk);
+            },
+            Command::Id => send(Response::MyId {
+                name: ENGINE_NAME.to_string(),
+                version: ENGINE_VERSION.to_string(),
+            }),
+            Command::IsReady => send(Response::ReadyOk),
+            Command::SetOption(opt) => self.handle_option(opt),
+            Command::Pos(pos_cmd) => self.handle_pos(pos_cmd),
+            Command::Search(search_cmd) => self.handle_search(search_cmd),
+            Command::Stop => self.handle_stop(),
+            Command::Quit => {},
         }
+    }
 
-        match split[1] {
-            "start" => self.pos = Position::new(),
-            "bgn" => {
-                if split.len() < 3 {
-                    eprintln!("error: need bgn string");
-                    return;
-                }
-                self.pos = Position::from_bgn(split[2]);
+    // takes effect from the next search on
+    fn handle_option(&mut self, opt: EngineOption) {
+        match opt {
+            EngineOption::Threads(n) => {
+                self.manager.set_threads(n);
+                self.mcts_threads = n;
+            },
+            EngineOption::HashMb(mb) => self.manager.set_hash_size(mb),
+            EngineOption::Engine(kind) => self.engine = kind,
+            EngineOption::MctsC(c) => self.mcts_c = c,
+            EngineOption::MctsSelection(kind) => self.mcts_selection = kind,
+            EngineOption::MctsFinalMove(criterion) => self.mcts_final_move = criterion,
+            EngineOption::MctsRollout(policy) => self.mcts_rollout = policy,
+            EngineOption::MctsPriorVisits(visits) => self.mcts_priors.visits = visits,
+            EngineOption::MctsPriorBias(bias) => self.mcts_priors.bias = bias,
+            EngineOption::MctsMemoryMb(mb) => self.mcts_memory_mb = if mb == 0 { None } else { Some(mb) },
+            EngineOption::Ruleset(ruleset) => self.ruleset = ruleset,
+        }
+    }
+
+    fn handle_pos(&mut self, cmd: PosCommand) {
+        match cmd {
+            PosCommand::Start => self.pos = Position::with_ruleset(self.ruleset),
+            // a BGN has its own ruleset
+            PosCommand::Bgn(bgn) => match Position::from_bgn(&bgn) {
+                Ok(pos) => self.pos = pos,
+                Err(err) => send(Response::Error(err.to_string())),
             },
-            "moves" => {
-                for i in 2..split.len() {
-                    self.pos.make_move(split[i].parse::<Idx>().unwrap());
+            PosCommand::Moves(moves) => {
+                // all or nothing, so an illegal move leaves the position as it was
+                let mut pos = self.pos;
+                for mov in moves {
+                    if !pos.legal_moves().contains(mov) {
+                        send(Response::Error(format!("illegal move {}", mov)));
+                        return;
+                    }
+                    pos.make_move(mov);
                 }
+                self.pos = pos;
             },
-            _ => {},
         }
     }
 
-    fn handle_search(&mut self, split: Vec<&str>) {
+    fn handle_search(&mut self, cmd: SearchCommand) {
         if self.searching {
-            eprintln!("error: search in progress");
+            send(Response::Error("search in progress".to_string()));
             return;
         }
-        if split.len() < 2 {
-            eprintln!("error: search needs a subcommand");
+        if self.pos.is_over() {
+            send(Response::Error("the game is already over".to_string()));
             return;
         }
+        self.manager.set_position(self.pos);
 
-        //let remaining = &split[2..].join("");
-        match split[1] {
-            "free" =>  {
-                // TODO call parse_keyvalue and get xtime otime etc.
-                if split.len() < 4 {
-                    eprintln!("error: too few arguments for 'search free'");
-                    return;
-                }
-                let xtime: u64 = split[2].parse().expect("'search free' <xtime> <otime>");
-                let otime: u64 = split[2].parse().expect("'search free' <xtime> <otime>");
-
-                let mut manager = Manager::from_position(self.pos);
-                manager.search_free(xtime, otime);
-
-                self.searching = true;
-            },
-            "depth" => {
-                panic!("not implemented");
-            },
-            "time" => {
-                panic!("not implemented");
-            },
-            "nodes" => {
-                panic!("not implemented");
+        match self.engine {
+            EngineKind::AlphaBeta => {
+                let limits = match cmd {
+                    SearchCommand::Free(clock) => self.manager.free_limits(clock),
+                    SearchCommand::Depth(depth) => SearchLimits::depth(depth),
+                    SearchCommand::Time(millis) => SearchLimits::movetime(millis),
+                    SearchCommand::Nodes(nodes) => SearchLimits::nodes(nodes),
+                    // runs until a 'stop' command
+                    SearchCommand::Forever => SearchLimits::infinite(),
+                };
+                self.start_search(limits);
             },
-            "forever" => {
-                panic!("not implemented");
+            EngineKind::Mcts => {
+                let limits = match cmd {
+                    SearchCommand::Free(clock) => MCTSLimits::movetime(self.manager.free_limits(clock).movetime_millis.unwrap()),
+                    SearchCommand::Time(millis) => MCTSLimits::movetime(millis),
+                    SearchCommand::Nodes(rollouts) => MCTSLimits::rollouts(std::cmp::min(rollouts, u32::MAX as u64) as u32),
+                    SearchCommand::Forever => MCTSLimits::infinite(),
+                    SearchCommand::Depth(_) => {
+                        send(Response::Error("the mcts engine has no depth to search to".to_string()));
+                        return;
+                    },
+                };
+                self.start_mcts(limits);
             },
-            _ => eprintln!("error: unknown search subcommand"),
         }
     }
+
+    // searches on a background thread. The result is picked up by tic()
+    fn start_search(&mut self, limits: SearchLimits) {
+        let (tx, rx) = mpsc::channel();
+        self.stop = Arc::new(AtomicBool::new(false));
+        let localstop = Arc::clone(&self.stop);
+        let manager = self.manager.clone();
+        thread::spawn(move || {
+            let res = manager.search(limits, localstop);
+            let info = SearchInfo {
+                best_move: res.best_move,
+                eval: res.eval,
+                depth: Some(res.depth),
+                nodes: Some(res.nodes),
+                pv: res.pv,
+            };
+            // the client might have gone away in the meantime
+            let _ = tx.send(Response::Info(info));
+        });
+        self.receiver = Some(rx);
+        self.searching = true;
+    }
+
+    // like start_search
+    fn start_mcts(&mut self, limits: MCTSLimits) {
+        let c = self.mcts_c;
+        match self.mcts_selection {
+            SelectionKind::Ucb1 => self.start_mcts_with(Ucb1 { c: c }, limits),
+            SelectionKind::Ucb1Tuned => self.start_mcts_with(Ucb1Tuned { c: c }, limits),
+            SelectionKind::Puct => self.start_mcts_with(Puct { c: c }, limits),
+            SelectionKind::Rave => self.start_mcts_with(Rave { c: c, k: RAVE_K }, limits),
+        }
+    }
+
+    fn start_mcts_with<S: SelectionPolicy>(&mut self, selection: S, limits: MCTSLimits) {
+        let (tx, rx) = mpsc::channel();
+        self.stop = Arc::new(AtomicBool::new(false));
+        let localstop = Arc::clone(&self.stop);
+        let pos = self.pos;
+        let threads = self.mcts_threads;
+        let (policy, priors, final_move) = (self.mcts_rollout, self.mcts_priors, self.mcts_final_move);
+        let memory_cap_mb = self.mcts_memory_mb;
+        thread::spawn(move || {
+            let mut mcts = MCTSWorker::with_selection(pos, selection, SmallRng::from_entropy());
+            mcts.set_threads(threads);
+            mcts.set_rollout_policy(policy);
+            mcts.set_priors(priors);
+            mcts.set_final_move(final_move);
+            mcts.set_memory_cap(memory_cap_mb);
+            let (res, n_rollouts) = mcts.go(limits, localstop);
+            eprintln!("NOTE: root moves: {}", mcts.report());
+            let info = SearchInfo {
+                best_move: res.best_move,
+                eval: res.value,
+                depth: None,
+                nodes: Some(n_rollouts as u64),
+                pv: mcts.pv().iter().map(|node| node.best_move).collect(),
+            };
+            let _ = tx.send(Response::Info(info));
+        });
+        self.receiver = Some(rx);
+        self.searching = true;
+    }
+
+    fn handle_stop(&mut self) {
+        if !self.searching {
+            eprintln!("error: no search in progress");
+            return;
+        }
+        self.stop.store(true, Ordering::Relaxed);
+    }
 }
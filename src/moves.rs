@@ -1,6 +1,12 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::slice::Iter;
+use std::str::FromStr;
 use core::arch::x86_64::*;
 
+use crate::format::{ParseError, PositionError};
+use crate::rules::*;
+
 /*
 Define block to be each 3x3 block of cells.
 Idxing is done block-by-block, row-major from
@@ -24,7 +30,7 @@ macro_rules! to_local_index {
     }};
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct BlockState(u8);
 
 impl BlockState {
@@ -41,6 +47,7 @@ impl BlockState {
         BlockState(min_needed | n_routes << 3)
     }
 
+    #[inline]
     pub(crate) fn min_needed(&self) -> u8 {
         self.0 & 7
     }
@@ -50,6 +57,15 @@ impl BlockState {
     }
 }
 
+impl fmt::Debug for BlockState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockState")
+            .field("min_needed", &self.min_needed())
+            .field("n_routes", &self.n_routes())
+            .finish()
+    }
+}
+
 // enumeration of the rows, diagonals & cols
 static WIN_OCC_LIST: [B33; 8] = [
     0b111,
@@ -67,6 +83,18 @@ pub(crate) const N_BLOCK33: usize = 262144;
 static mut BLOCK_STATE_TABLE: [BlockState; N_BLOCK33] = [BlockState(0); N_BLOCK33];
 static mut INITIALIZED: bool = false;  // for sanity checks
 
+// Zobrist keys. Each side gets a key per bit of its Bitboard, i.e. the 81
+// cells plus the 9 block-capture bits. The side key is toggled when O is to
+// move and the last_block key for ANY_BLOCK is left at 0, so that the
+// starting position always hashes to 0 (Position::new() may be called before
+// init_moves()).
+const N_ZOBRIST_BITS: usize = 90;
+static mut ZOBRIST_BITS: [[u64; N_ZOBRIST_BITS]; 2] = [[0; N_ZOBRIST_BITS]; 2];
+static mut ZOBRIST_SIDE: u64 = 0;
+static mut ZOBRIST_LAST_BLOCK: [u64; 10] = [0; 10];
+// the default ruleset (code 0) has no key, so that it doesn't change hashes
+static mut ZOBRIST_RULESET: [u64; N_RULESET_CODES] = [0; N_RULESET_CODES];
+
 pub fn init_moves() {
     for idx in 0..N_BLOCK33 {
         // by convention, my_occ is the lower 9 bits, etc.
@@ -87,11 +115,62 @@ pub fn init_moves() {
             BLOCK_STATE_TABLE[idx] = BlockState::new(min_count, counts[min_count as usize]);
         }
     }
+    init_zobrist();
     unsafe {
         INITIALIZED = true;
     }
 }
 
+// xorshift64* with a fixed seed, so that hashes are the same across runs
+fn init_zobrist() {
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut next = || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545f4914f6cdd1d)
+    };
+    unsafe {
+        for side in 0..2 {
+            for bit in 0..N_ZOBRIST_BITS {
+                ZOBRIST_BITS[side][bit] = next();
+            }
+        }
+        ZOBRIST_SIDE = next();
+        for block_i in 0..ANY_BLOCK as usize {
+            ZOBRIST_LAST_BLOCK[block_i] = next();
+        }
+        for code in 1..N_RULESET_CODES {
+            ZOBRIST_RULESET[code] = next();
+        }
+    }
+}
+
+// xor of the keys of every bit set in bits (a Bitboard of side)
+#[inline(always)]
+fn zobrist_bits(side: Side, bits: u128) -> u64 {
+    let mut hash = 0;
+    for bit in Moves(bits) {
+        unsafe {
+            hash ^= ZOBRIST_BITS[side as usize][bit as usize];
+        }
+    }
+    return hash;
+}
+
+#[inline(always)]
+fn zobrist_last_block(last_block: u8) -> u64 {
+    unsafe {
+        ZOBRIST_LAST_BLOCK[last_block as usize]
+    }
+}
+
+fn zobrist_ruleset(ruleset: Ruleset) -> u64 {
+    unsafe {
+        ZOBRIST_RULESET[ruleset.code()]
+    }
+}
+
 #[inline(always)]
 pub fn get_block_state(my_occ: B33, their_occ: B33) -> BlockState {
     debug_assert!(my_occ | (their_occ << 9) == my_occ + (their_occ << 9));
@@ -116,7 +195,8 @@ pub fn get_block_state_by_idx(idx: usize) -> BlockState {
 //     }};
 // }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GameResult {
     XWon = 0,
     OWon = 1,
@@ -124,12 +204,70 @@ pub enum GameResult {
     Ongoing = 3,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+// written like PGN results: 1-0, 0-1, 1/2-1/2 and * for an ongoing game
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let repr = match self {
+            GameResult::XWon => "1-0",
+            GameResult::OWon => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Ongoing => "*",
+        };
+        write!(f, "{}", repr)
+    }
+}
+
+impl GameResult {
+    pub fn won_by(side: Side) -> GameResult {
+        match side {
+            Side::X => GameResult::XWon,
+            Side::O => GameResult::OWon,
+        }
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<GameResult, ParseError> {
+        match repr.trim() {
+            "1-0" => Ok(GameResult::XWon),
+            "0-1" => Ok(GameResult::OWon),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Ongoing),
+            other => Err(ParseError::expected(0, "1-0, 0-1, 1/2-1/2 or *", other)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     X = 0,
     O = 1,
 }
 
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::X => write!(f, "X"),
+            Side::O => write!(f, "O"),
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Side, ParseError> {
+        match repr.trim() {
+            "X" | "x" => Ok(Side::X),
+            "O" | "o" => Ok(Side::O),
+            other => Err(ParseError::expected(0, "'X' or 'O'", other)),
+        }
+    }
+}
+
 impl Side {
     pub fn other(&self) -> Side {
         match self {
@@ -144,6 +282,130 @@ impl Side {
     }
 }
 
+/*
+A cell of the board, in a form that can be shown to people. It converts
+losslessly between the Idx used everywhere else, Codingame's row/col (0-8
+each, from the top left) and block/cell (0-8 each, row by row from the top
+left, so idx = block * 9 + cell).
+
+The notation is algebraic-like: "c3/b2" is cell b2 of block c3. Both halves
+name a spot in a 3x3 grid like a chess board, with columns a-c from the left
+and rows 1-3 from the bottom, so a3 is the top left and b2 the center.
+FromStr also accepts "<block>.<cell>" and "<row>,<col>".
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+pub struct Square(Idx);
+
+impl Square {
+    pub fn from_idx(idx: Idx) -> Option<Square> {
+        if idx < BOARD_SIZE {
+            Some(Square(idx))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_block_cell(block: u8, cell: u8) -> Option<Square> {
+        if block < 9 && cell < 9 {
+            Some(Square(block * 9 + cell))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_row_col(row: u8, col: u8) -> Option<Square> {
+        if row < 9 && col < 9 {
+            Some(Square((row / 3 * 3 + col / 3) * 9 + row % 3 * 3 + col % 3))
+        } else {
+            None
+        }
+    }
+
+    pub fn idx(self) -> Idx {
+        self.0
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 / 9
+    }
+
+    pub fn cell(self) -> u8 {
+        self.0 % 9
+    }
+
+    pub fn row(self) -> u8 {
+        self.block() / 3 * 3 + self.cell() / 3
+    }
+
+    pub fn col(self) -> u8 {
+        self.block() % 3 * 3 + self.cell() % 3
+    }
+
+    pub fn iter() -> impl Iterator<Item = Square> {
+        (0..BOARD_SIZE).map(Square)
+    }
+}
+
+impl From<Square> for Idx {
+    fn from(square: Square) -> Idx {
+        square.0
+    }
+}
+
+impl std::convert::TryFrom<Idx> for Square {
+    type Error = String;
+
+    fn try_from(idx: Idx) -> Result<Square, String> {
+        Square::from_idx(idx).ok_or_else(|| format!("cell index {} out of range", idx))
+    }
+}
+
+// one half of the notation, e.g. a3 for 0
+fn write_spot(spot: u8) -> String {
+    format!("{}{}", (b'a' + spot % 3) as char, 3 - spot / 3)
+}
+
+fn parse_spot(col: u8, row: u8) -> Option<u8> {
+    match (col, row) {
+        (b'a'..=b'c', b'1'..=b'3') => Some((b'3' - row) * 3 + (col - b'a')),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", write_spot(self.block()), write_spot(self.cell()))
+    }
+}
+
+impl fmt::Debug for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Square({})", self)
+    }
+}
+
+impl FromStr for Square {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Square, ParseError> {
+        parse_square(repr.trim().to_ascii_lowercase().as_bytes())
+            .ok_or_else(|| ParseError::expected(0, "a square like c3/b2, <block>.<cell> or <row>,<col>", repr.trim()))
+    }
+}
+
+fn parse_square(repr: &[u8]) -> Option<Square> {
+    match *repr {
+        [block_col, block_row, b'/', cell_col, cell_row] => {
+            Square::from_block_cell(parse_spot(block_col, block_row)?, parse_spot(cell_col, cell_row)?)
+        },
+        [first @ b'0'..=b'8', b'.', second @ b'0'..=b'8'] => Square::from_block_cell(first - b'0', second - b'0'),
+        [first @ b'0'..=b'8', b',', second @ b'0'..=b'8'] => Square::from_row_col(first - b'0', second - b'0'),
+        _ => None,
+    }
+}
+
 pub(crate) static BLOCK_OCC: B33 = 0b111111111;
 pub(crate) static BLOCK_OCC_I128: i128 = BLOCK_OCC as i128;
 pub(crate) static BOARD_OCC: u128 = 0x1ffffffffffffffffffffu128;
@@ -162,13 +424,24 @@ pub(crate) fn get_block_hopeless(occ: B33) -> bool {
     get_block_state(0, occ).min_needed() == 4
 }
 
+// whether the other side can't win block bi anymore, after the side with
+// own_occ there moved. own_captured and their_captured are the captured
+// blocks of both sides. Blocks captured by the mover are lost for the other
+// side, while the ones it captured itself are not hopeless but won
+#[inline(always)]
+pub(crate) fn block_hopeless(own_occ: B33, own_captured: B33, their_captured: B33, bi: u8) -> bool {
+    let captured_by_own = own_captured & (1 << bi) != 0;
+    let captured_by_them = their_captured & (1 << bi) != 0;
+    !captured_by_them && (captured_by_own || get_block_hopeless(own_occ))
+}
+
 // returns filled block occ if filled is true; 0 otherwise
 #[inline(always)]
 fn bool_to_block(filled: bool) -> u128 {
     ((0i128 - (filled as i128)) & BLOCK_OCC_I128) as u128
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Moves(u128);
 
 impl Moves {
@@ -261,8 +534,70 @@ impl Iterator for Moves {
     }
 }
 
-#[derive(Copy, Clone)]
-pub(crate) struct Bitboard(u128);
+impl fmt::Debug for Moves {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(*self).finish()
+    }
+}
+
+// comma separated, in the format of Position::from_move_list
+impl fmt::Display for Moves {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let moves = self.map(|mov| mov.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", moves.join(", "))
+    }
+}
+
+impl FromStr for Moves {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Moves, ParseError> {
+        let mut ret = Moves::new();
+        if repr.trim().is_empty() {
+            return Ok(ret);
+        }
+        let mut offset = 0;
+        for tok in repr.split(",") {
+            match tok.trim().parse::<Idx>() {
+                Ok(mov) if mov < BOARD_SIZE => ret.add(mov),
+                _ => return Err(ParseError::expected(offset, "a cell index below 81", tok.trim())),
+            }
+            offset += tok.len() + 1;
+        }
+        return Ok(ret);
+    }
+}
+
+// a list of cell indices
+#[cfg(feature = "serde")]
+impl serde::Serialize for Moves {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(*self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Moves {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Moves, D::Error> {
+        let mut ret = Moves::new();
+        for mov in Vec::<Idx>::deserialize(deserializer)? {
+            if mov >= BOARD_SIZE {
+                return Err(serde::de::Error::custom(format!("cell index {} out of range", mov)));
+            }
+            ret.add(mov);
+        }
+        return Ok(ret);
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct Bitboard(pub(crate) u128);
+
+impl fmt::Debug for Bitboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bitboard({:#x})", self.0)
+    }
+}
 
 impl Bitboard {
     fn new() -> Bitboard {
@@ -270,6 +605,7 @@ impl Bitboard {
     }
 
     // returns block index
+    #[inline]
     pub(crate) fn set(&mut self, index: Idx) -> u8 {
         debug_assert!(index < BOARD_SIZE);
         //debug_assert_eq!(self.0 & (1u128 << index), 0);
@@ -294,6 +630,19 @@ impl Bitboard {
         self.0 & ((1 as u128) << index) != 0
     }
 
+    // set for open won blocks (see WonBlocks): the block is not filled in
+    // when captured, and a block captured by the other side (captured is
+    // true) stays theirs. Returns block index
+    #[inline]
+    pub(crate) fn set_open(&mut self, index: Idx, captured: bool) -> u8 {
+        debug_assert!(index < BOARD_SIZE);
+        self.0 |= 1u128 << index;
+        let block_i = index / 9;
+        let won = get_block_won(self.get_block(block_i)) && !captured;
+        self.0 |= (won as u128) << (BOARD_SIZE + block_i);
+        return block_i;
+    }
+
     // NOTE block must be empty before this
     pub fn set_block(&mut self, block_i: u8, occ: B33) {
         debug_assert!(block_i < 9);
@@ -304,7 +653,25 @@ impl Bitboard {
         self.0 |= bool_to_block(won) << block_i * 9;
     }
 
+    // NOTE block must be empty before this. Like set_open, the block isn't
+    // filled in when owned
+    pub(crate) fn set_block_open(&mut self, block_i: u8, occ: B33, owned: bool) {
+        debug_assert!(block_i < 9);
+        debug_assert_eq!(occ & !BLOCK_OCC, 0);
+        self.0 |= (occ as u128) << (block_i * 9);
+        self.0 |= (owned as u128) << (BOARD_SIZE + block_i);
+    }
+
+    // puts back a block that may have been captured (and filled) since,
+    // along with the capture bits
+    pub(crate) fn restore_block(&mut self, block_i: u8, occ: B33, captured_occ: B33) {
+        debug_assert!(block_i < 9);
+        self.0 &= !((BLOCK_OCC as u128) << (block_i * 9)) & !((BLOCK_OCC as u128) << BOARD_SIZE);
+        self.0 |= (occ as u128) << (block_i * 9) | (captured_occ as u128) << BOARD_SIZE;
+    }
+
     // return aligned occupancy for one block
+    #[inline]
     pub fn get_block(&self, block_i: u8) -> B33 {
         debug_assert!(block_i < 9);
         ((self.0 >> (block_i * 9)) as B33) & BLOCK_OCC
@@ -321,34 +688,93 @@ impl Bitboard {
     }
 }
 
-#[derive(Copy, Clone)]
+// equal positions also have equal hashes, so Hash just uses that one (see
+// format.rs for Display and FromStr)
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub struct Position {
     pub(crate) bitboards: [Bitboard; 2],
     pub(crate) to_move: Side,
     // occupancy of blocks that cannot be won
     pub(crate) hopeless_occ: [B33; 2],
     pub(crate) last_block: u8,
+    // Zobrist hash, maintained incrementally by make_move
+    pub(crate) hash: u64,
+    pub(crate) ruleset: Ruleset,
 }
 
 const ANY_BLOCK: u8 = 9;
 
+impl Hash for Position {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+// what make_move overwrites, so that unmake_move can put it back. A captured
+// block gets filled in, so the cells actually played there are kept here
+#[derive(Copy, Clone, Debug)]
+pub struct Undo {
+    mov: Idx,
+    last_block: u8,
+    // the mover's occupancy of the block played in, and its captured
+    // blocks, before the move
+    block_occ: B33,
+    captured_occ: B33,
+    // the opponent's hopeless_occ, before the move
+    hopeless_occ: B33,
+    hash: u64,
+}
+
+impl Undo {
+    #[inline(always)]
+    pub fn mov(&self) -> Idx {
+        self.mov
+    }
+}
+
 impl Position {
     pub fn new() -> Position {
+        Position::with_ruleset(Ruleset::default())
+    }
+
+    // the starting position of a game played under ruleset
+    pub fn with_ruleset(ruleset: Ruleset) -> Position {
         Position {
             bitboards: [Bitboard::new(), Bitboard::new()],
             to_move: Side::X,
             hopeless_occ: [0; 2],
             last_block: ANY_BLOCK,
+            hash: zobrist_ruleset(ruleset),
+            ruleset: ruleset,
         }
     }
 
+    #[inline(always)]
+    pub fn ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
+    // plays the rest of the game under other rules. The result may change
+    // with them, e.g. a full board isn't a draw anymore. Closed won blocks
+    // are filled in when captured and open ones aren't, so going from one
+    // to the other is only allowed before any block is captured
+    pub fn set_ruleset(&mut self, ruleset: Ruleset) -> Result<(), PositionError> {
+        let captured = self.bitboards[0].n_captured() + self.bitboards[1].n_captured() > 0;
+        if captured && self.ruleset.won_blocks.is_open() != ruleset.won_blocks.is_open() {
+            return Err(PositionError::WonBlocksChange);
+        }
+        self.hash ^= zobrist_ruleset(self.ruleset) ^ zobrist_ruleset(ruleset);
+        self.ruleset = ruleset;
+        return Ok(());
+    }
+
     // does NOT check for termination, i.e. if the game is won/drawn
+    #[inline]
     pub fn legal_moves(&self) -> Moves {
         debug_assert!(!self.is_over());
         let total_occ = self.bitboards[0].0 | self.bitboards[1].0;
         // check if I can go anywhere on the board
-        let full_board = self.last_block == ANY_BLOCK
-            || (((total_occ >> (self.last_block * 9)) as B33) & BLOCK_OCC == BLOCK_OCC);
+        let full_board = self.last_block == ANY_BLOCK || self.free_move_from(self.last_block);
         // no-branch map full_board true => all one's, or full_board false => local one's
         let local_occ = ((BLOCK_OCC as u128) << (self.last_block * 9)) as u128;
         let mask = (0i128 - full_board as i128) as u128 | local_occ;
@@ -357,37 +783,97 @@ impl Position {
 
     #[inline(always)]
     pub fn get_result(&self) -> GameResult {
-        if self.is_won(Side::X) {
-            return GameResult::XWon;
-        } else if self.is_won(Side::O) {
-            return GameResult::OWon;
+        // the side that just moved first: with tied blocks counting for
+        // both, one move can complete a row for both sides
+        let last_mover = self.to_move.other();
+        if self.is_won(last_mover) {
+            return GameResult::won_by(last_mover);
+        } else if self.is_won(self.to_move) {
+            return GameResult::won_by(self.to_move);
         } else if self.is_drawn() {
-            return GameResult::Draw;
+            return self.full_board_result();
         } else {
             return GameResult::Ongoing;
         }
     }
 
+    // the result of a full board without three blocks in a row for anyone
+    fn full_board_result(&self) -> GameResult {
+        match self.ruleset.full_board {
+            FullBoard::Draw => GameResult::Draw,
+            FullBoard::Majority => {
+                let n_x = self.bitboards[Side::X as usize].n_captured();
+                let n_o = self.bitboards[Side::O as usize].n_captured();
+                match n_x.cmp(&n_o) {
+                    std::cmp::Ordering::Greater => GameResult::XWon,
+                    std::cmp::Ordering::Less => GameResult::OWon,
+                    std::cmp::Ordering::Equal => GameResult::Draw,
+                }
+            },
+        }
+    }
+
     #[inline(always)]
     pub fn side_to_move(&self) -> Side {
         self.to_move
     }
 
+    // the block that the side to move has to play in, or None if it can go
+    // anywhere (first move, or the block it was sent to is full)
+    pub fn forced_block(&self) -> Option<u8> {
+        if self.last_block == ANY_BLOCK {
+            return None;
+        }
+        if self.free_move_from(self.last_block) {
+            return None;
+        }
+        return Some(self.last_block);
+    }
+
+    // whether being sent to block_i grants a free move: it's full, or won
+    // under WonBlocks::Open. Closed won blocks are filled in, so full
+    #[inline(always)]
+    fn free_move_from(&self, block_i: u8) -> bool {
+        let total_occ = self.bitboards[0].0 | self.bitboards[1].0;
+        let full = ((total_occ >> (block_i * 9)) as B33) & BLOCK_OCC == BLOCK_OCC;
+        let won = ((total_occ >> (BOARD_SIZE + block_i)) & 1) != 0;
+        full || (won && self.ruleset.won_blocks == WonBlocks::Open)
+    }
+
+    // three blocks in a row. Under TiedBlocks::CountForBoth this can also be
+    // true for the side to move
     #[inline(always)]
     pub fn is_won(&self, side: Side) -> bool {
-        get_block_won(self.bitboards[side as usize].captured_occ())
+        let mut occ = self.bitboards[side as usize].captured_occ();
+        if self.ruleset.tied_blocks == TiedBlocks::CountForBoth {
+            occ |= self.tied_occ();
+        }
+        get_block_won(occ)
+    }
+
+    // blocks that filled up without being captured
+    pub(crate) fn tied_occ(&self) -> B33 {
+        let total_occ = self.bitboards[0].0 | self.bitboards[1].0;
+        let captured = self.bitboards[0].captured_occ() | self.bitboards[1].captured_occ();
+        let mut full: B33 = 0;
+        for bi in 0..9 {
+            full |= ((((total_occ >> (bi * 9)) as B33) & BLOCK_OCC == BLOCK_OCC) as B33) << bi;
+        }
+        return full & !captured;
     }
 
     // TODO use this for eval
     // This is test for if the game cannot be won anymore. In contrast
     // to is_drawn which only returns true for boards without any more moves.
+    // Tied blocks are taken as dead, whatever the ruleset.
     #[inline(always)]
     pub fn is_hopeless(&self) -> bool {
         get_block_hopeless(self.hopeless_occ[0]) && get_block_hopeless(self.hopeless_occ[1])
     }
 
     // NOTE does not check for win/loss. So only call this after is_won is called
-    // for both sides
+    // for both sides. The board being full is only a draw under some rulesets,
+    // see get_result
     #[inline(always)]
     pub fn is_drawn(&self) -> bool {
         (self.bitboards[0].0 | self.bitboards[1].0) & BOARD_OCC == BOARD_OCC
@@ -398,21 +884,77 @@ impl Position {
         self.is_won(Side::X) || self.is_won(Side::O) || self.is_drawn()
     }
 
-    pub fn make_move(&mut self, index: Idx) {
+    #[inline(always)]
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // computes the Zobrist hash from scratch. make_move keeps self.hash up to
+    // date, so this is only needed when the bitboards are set directly
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist_bits(Side::X, self.bitboards[Side::X as usize].0)
+            ^ zobrist_bits(Side::O, self.bitboards[Side::O as usize].0)
+            ^ zobrist_last_block(self.last_block)
+            ^ zobrist_ruleset(self.ruleset);
+        if self.to_move == Side::O {
+            hash ^= unsafe { ZOBRIST_SIDE };
+        }
+        return hash;
+    }
+
+    // the returned Undo can be handed to unmake_move to take the move back
+    #[inline]
+    pub fn make_move(&mut self, index: Idx) -> Undo {
         debug_assert!(self.legal_moves().contains(index));
+        let side = self.to_move;
+        let undo = Undo {
+            mov: index,
+            last_block: self.last_block,
+            block_occ: self.bitboards[side as usize].get_block(index / 9),
+            captured_occ: self.bitboards[side as usize].captured_occ(),
+            hopeless_occ: self.hopeless_occ[side.other() as usize],
+            hash: self.hash,
+        };
+
         // place piece
-        let own_bb = &mut self.bitboards[self.to_move as usize];
-        let bi = own_bb.set(index);
+        let their_captured = self.bitboards[side.other() as usize].captured_occ();
+        let own_bb = &mut self.bitboards[side as usize];
+        let old_bits = own_bb.0;
+        let bi = if self.ruleset.won_blocks.is_open() {
+            own_bb.set_open(index, their_captured & (1 << (index / 9)) != 0)
+        } else {
+            own_bb.set(index)
+        };
         let block_occ = own_bb.get_block(bi);
+        let own_captured = own_bb.captured_occ();
+
+        // the piece itself, plus the filled block and capture bit if this
+        // move captured a block
+        self.hash ^= zobrist_bits(side, own_bb.0 ^ old_bits);
+        self.hash ^= unsafe { ZOBRIST_SIDE };
+        self.hash ^= zobrist_last_block(self.last_block) ^ zobrist_last_block(to_local_index!(index));
 
         // update to_move
         self.to_move = self.to_move.other();
 
         // update hopeless occ for the other player
-        self.hopeless_occ[self.to_move as usize] |= (get_block_hopeless(block_occ) as B33) << bi;
+        self.hopeless_occ[self.to_move as usize] |= (block_hopeless(block_occ, own_captured, their_captured, bi) as B33) << bi;
 
         // update last_block
         self.last_block = to_local_index!(index);
+        return undo;
+    }
+
+    // undo must come from the last make_move on this position
+    pub fn unmake_move(&mut self, undo: Undo) {
+        self.to_move = self.to_move.other();
+        let side = self.to_move;
+        // also clears the capture bit and the fill, if the move captured
+        self.bitboards[side as usize].restore_block(undo.mov / 9, undo.block_occ, undo.captured_occ);
+        self.hopeless_occ[side.other() as usize] = undo.hopeless_occ;
+        self.last_block = undo.last_block;
+        self.hash = undo.hash;
+        debug_assert!(!self.bitboards[side as usize].get(undo.mov));
     }
 
     #[allow(dead_code)]
@@ -422,10 +964,22 @@ impl Position {
         // bit representations are within range
         debug_assert_eq!(self.bitboards[0].0 >> (BOARD_SIZE + 9), 0);
         debug_assert_eq!(self.bitboards[1].0 >> (BOARD_SIZE + 9), 0);
+        debug_assert_eq!(self.hash, self.compute_hash());
 
         return true;
     }
 
+    // hopeless_occ[side] from scratch, see block_hopeless
+    pub(crate) fn compute_hopeless(&self, side: Side) -> B33 {
+        let other_bb = self.bitboards[side.other() as usize];
+        let own_captured = self.bitboards[side as usize].captured_occ();
+        let mut ret: B33 = 0;
+        for bi in 0..9 {
+            ret |= (block_hopeless(other_bb.get_block(bi), other_bb.captured_occ(), own_captured, bi) as B33) << bi;
+        }
+        return ret;
+    }
+
     // current ply number
     pub fn cur_ply(&self) -> u16 {
         (self.bitboards[0].0 | self.bitboards[1].0).count_ones() as u16
@@ -440,6 +994,8 @@ impl Position {
         // this is used more than once
         let my_occ = self.bitboards[side as usize].0;
         let their_occ = self.bitboards[side.other() as usize].0;
+        // open won blocks can't be captured again
+        let captured = self.bitboards[0].captured_occ() | self.bitboards[1].captured_occ();
         let mut shift = 0;
         // when my_occ is 0 there's def no chance that there's any more captures
         while shift != 81 {
@@ -447,7 +1003,7 @@ impl Position {
             let their_block = (their_occ >> shift) & BLOCK_OCC as u128;
 
             let bstate = get_block_state(my_block as B33, their_block as B33);
-            if bstate.min_needed() == 1 {
+            if bstate.min_needed() == 1 && captured & (1 << (shift / 9)) == 0 {
                 // possible captures. Find them all.
                 let block_moves = Moves(!my_block & BLOCK_OCC as u128);
                 for mov in block_moves {
@@ -463,10 +1019,63 @@ impl Position {
     }
 }
 
+// a Position along with the moves that led to it, so that they can be taken
+// back one by one
+#[derive(Clone, Debug)]
+pub struct GameHistory {
+    start: Position,
+    pos: Position,
+    undos: Vec<Undo>,
+}
+
+impl GameHistory {
+    pub fn new(start: Position) -> GameHistory {
+        GameHistory {
+            start: start,
+            pos: start,
+            undos: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+
+    pub fn start_position(&self) -> &Position {
+        &self.start
+    }
+
+    pub fn make_move(&mut self, mov: Idx) {
+        let undo = self.pos.make_move(mov);
+        self.undos.push(undo);
+    }
+
+    // returns the move that was taken back, or None at the start position
+    pub fn undo(&mut self) -> Option<Idx> {
+        let undo = self.undos.pop()?;
+        self.pos.unmake_move(undo);
+        return Some(undo.mov);
+    }
+
+    // moves played since the start position, in order
+    pub fn moves(&self) -> Vec<Idx> {
+        self.undos.iter().map(|undo| undo.mov).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.undos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undos.is_empty()
+    }
+}
+
 #[allow(dead_code)]
 pub fn perft(depth: u16, pos: &mut Position) -> u64 {
     debug_assert!(pos.assert());
-    if pos.is_won(pos.to_move.other()) || pos.is_drawn() {
+    if pos.is_over() {
         return 0;
     }
     if depth == 0 {
@@ -482,6 +1091,25 @@ pub fn perft(depth: u16, pos: &mut Position) -> u64 {
     return count;
 }
 
+// same as perft, but with make/unmake instead of copy-make
+pub fn perft_unmake(depth: u16, pos: &mut Position) -> u64 {
+    debug_assert!(pos.assert());
+    if pos.is_over() {
+        return 0;
+    }
+    if depth == 0 {
+        return pos.legal_moves().size() as u64;
+    }
+    let mut count: u64 = 0;
+
+    for mov in pos.legal_moves() {
+        let undo = pos.make_move(mov);
+        count += perft_unmake(depth - 1, pos);
+        pos.unmake_move(undo);
+    }
+    return count;
+}
+
 #[allow(dead_code)]
 fn divide(depth: u16, pos: &mut Position) {
     debug_assert!(pos.assert());
@@ -494,11 +1122,9 @@ fn divide(depth: u16, pos: &mut Position) {
         }
     } else {
         for mov in pos.legal_moves() {
-            let mut temp = pos.clone();
-            // let last_block = pos.last_block;
-            temp.make_move(mov);
-            let count = perft(depth - 1, &mut temp);
-            // pos.unmake_move(mov, last_block);
+            let undo = pos.make_move(mov);
+            let count = perft_unmake(depth - 1, pos);
+            pos.unmake_move(undo);
             println!("{}: {}", mov, count);
         }
     }
@@ -538,4 +1164,31 @@ mod tests {
         assert!(get_block_won(0b111000000));
         assert!(!get_block_won(0b000000000));
     }
+
+    #[test]
+    fn test_unmake() {
+        init_moves();
+        // includes several block captures, and blocks becoming hopeless
+        let move_list = [0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21, 31, 40, 37, 13, 38, 23, 49];
+        let mut positions = vec![Position::new()];
+        let mut history = GameHistory::new(Position::new());
+        for mov in move_list.iter() {
+            history.make_move(*mov);
+            positions.push(*history.position());
+        }
+        assert_eq!(history.moves(), move_list.to_vec());
+        while let Some(mov) = history.undo() {
+            positions.pop();
+            let pos = history.position();
+            let expected = positions.last().unwrap();
+            assert_eq!(mov, move_list[history.len()]);
+            assert_eq!(pos.bitboards[0].0, expected.bitboards[0].0);
+            assert_eq!(pos.bitboards[1].0, expected.bitboards[1].0);
+            assert_eq!(pos.hopeless_occ, expected.hopeless_occ);
+            assert_eq!(pos.last_block, expected.last_block);
+            assert!(pos.to_move == expected.to_move);
+            assert_eq!(pos.hash, expected.hash);
+        }
+        assert!(history.is_empty());
+    }
 }
@@ -0,0 +1,553 @@
+/*
+Game records, a PGN-like format for whole games. An example:
+
+  [X "alice"]
+  [O "barbar"]
+  [OEngine "alphabeta"]
+  [TimeControl "60000+1000"]
+  [Date "2020.06.21"]
+  [Result "0-1"]
+
+  1. b2/b2 {the center} b2/a3 2. a3/b2 {[%eval -0.35] forced} b2/c1 ...
+  0-1
+
+Headers are `[Key "value"]` lines, with `\"` and `\\` escaped in the value.
+Moves are written in Square's notation (see moves.rs), and its other
+notations like <block>.<cell> are accepted as well. Move numbers are
+optional when reading. A `{comment}` belongs to the move before it, and
+an `[%eval <score>]` inside it is the eval of that move. The game ends with
+its result: 1-0 (X won), 0-1 (O won), 1/2-1/2 or * (unfinished).
+The moves and result are checked against the rules in the Ruleset header
+(see rules.rs), or the default ones if there is none.
+
+Several games can follow each other in one file, see parse_games and
+GameReader. Every game starts at the starting position, so a record's moves
+are the same as Position::from_move_list(&record.to_move_list()).
+*/
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use crate::engine::config::Score;
+use crate::format::*;
+use crate::moves::*;
+use crate::rules::Ruleset;
+
+// the headers GameRecord knows about. Others are kept as they are
+pub const HEADER_X: &str = "X";
+pub const HEADER_O: &str = "O";
+pub const HEADER_X_ENGINE: &str = "XEngine";
+pub const HEADER_O_ENGINE: &str = "OEngine";
+pub const HEADER_TIME_CONTROL: &str = "TimeControl";
+pub const HEADER_RULESET: &str = "Ruleset";
+pub const HEADER_DATE: &str = "Date";
+pub const HEADER_RESULT: &str = "Result";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordMove {
+    pub mov: Idx,
+    pub comment: Option<String>,
+    pub eval: Option<Score>,
+}
+
+impl RecordMove {
+    pub fn new(mov: Idx) -> RecordMove {
+        RecordMove {
+            mov: mov,
+            comment: None,
+            eval: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    // in the order they are written, without Result (see result)
+    headers: Vec<(String, String)>,
+    // a comment before the first move
+    pub comment: Option<String>,
+    // only added with push_move and taken back with pop_move, so that pos
+    // stays up to date
+    moves: Vec<RecordMove>,
+    pub result: GameResult,
+    // the position after all the moves, so that push_move doesn't have to
+    // replay the game
+    pos: Position,
+}
+
+impl GameRecord {
+    pub fn new() -> GameRecord {
+        GameRecord {
+            headers: Vec::new(),
+            comment: None,
+            moves: Vec::new(),
+            result: GameResult::Ongoing,
+            pos: Position::new(),
+        }
+    }
+
+    // under the default ruleset. The result is set if the game is over
+    pub fn from_moves(moves: &[Idx]) -> Result<GameRecord, RecordError> {
+        let mut ret = GameRecord::new();
+        for mov in moves {
+            ret.push_move(RecordMove::new(*mov))?;
+        }
+        return Ok(ret);
+    }
+
+    pub fn from_move_list(repr: &str) -> Result<GameRecord, ParseError> {
+        // for the error
+        Position::from_move_list(repr)?;
+        let moves = repr.split(",").filter(|tok| !tok.trim().is_empty());
+        let moves = moves.map(|tok| tok.trim().parse().unwrap()).collect::<Vec<_>>();
+        return Ok(GameRecord::from_moves(&moves).expect("the moves are legal, see above"));
+    }
+
+    // in the format of Position::from_move_list
+    pub fn to_move_list(&self) -> String {
+        self.moves.iter().map(|mov| mov.mov.to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    pub fn moves(&self) -> &[RecordMove] {
+        &self.moves
+    }
+
+    // for their comments and evals. See push_move and pop_move for the
+    // moves themselves
+    pub fn moves_mut(&mut self) -> &mut [RecordMove] {
+        &mut self.moves
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        if key == HEADER_RESULT {
+            return None;
+        }
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| &v[..])
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    // Result can't be set this way, since it lives in self.result. A new
+    // Ruleset must parse, and keep all the moves legal
+    pub fn set_header(&mut self, key: &str, value: &str) -> Result<(), RecordError> {
+        if key == HEADER_RESULT {
+            return Err(RecordError::ResultHeader);
+        }
+        if key == HEADER_RULESET {
+            let ruleset = value.parse::<Ruleset>().map_err(RecordError::BadRuleset)?;
+            let pos = self.replay(ruleset)?;
+            // a result from the board may have changed with the rules
+            if self.pos.is_over() || pos.is_over() {
+                self.result = pos.get_result();
+            }
+            self.pos = pos;
+        }
+        match self.headers.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.headers.push((key.to_string(), value.to_string())),
+        }
+        return Ok(());
+    }
+
+    // from the Ruleset header, or the default ruleset without one.
+    // set_header and the parser only take valid ones
+    pub fn ruleset(&self) -> Ruleset {
+        match self.header(HEADER_RULESET) {
+            Some(repr) => repr.parse().expect("the Ruleset header is checked when set"),
+            None => Ruleset::default(),
+        }
+    }
+
+    pub fn set_ruleset(&mut self, ruleset: Ruleset) -> Result<(), RecordError> {
+        return self.set_header(HEADER_RULESET, &ruleset.to_string());
+    }
+
+    // the move must be legal in the current position. The result is set if
+    // it ends the game
+    pub fn push_move(&mut self, mov: RecordMove) -> Result<(), RecordError> {
+        if self.pos.is_over() || !self.pos.legal_moves().contains(mov.mov) {
+            return Err(RecordError::IllegalMove { ply: self.moves.len(), mov: mov.mov });
+        }
+        self.pos.make_move(mov.mov);
+        self.moves.push(mov);
+        if self.pos.is_over() {
+            self.result = self.pos.get_result();
+        }
+        return Ok(());
+    }
+
+    // takes back the last move, if any. A result from the board goes back
+    // to unfinished
+    pub fn pop_move(&mut self) -> Option<RecordMove> {
+        let ret = self.moves.pop()?;
+        if self.pos.is_over() {
+            self.result = GameResult::Ongoing;
+        }
+        self.pos = self.replay(self.ruleset()).expect("the moves before a legal move are legal");
+        return Some(ret);
+    }
+
+    // the position after all the moves
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    // the position after all the moves under ruleset, unless one of them
+    // isn't legal there
+    fn replay(&self, ruleset: Ruleset) -> Result<Position, RecordError> {
+        let mut pos = Position::with_ruleset(ruleset);
+        for (ply, mov) in self.moves.iter().enumerate() {
+            if pos.is_over() || !pos.legal_moves().contains(mov.mov) {
+                return Err(RecordError::IllegalMove { ply: ply, mov: mov.mov });
+            }
+            pos.make_move(mov.mov);
+        }
+        return Ok(pos);
+    }
+}
+
+impl Default for GameRecord {
+    fn default() -> GameRecord {
+        GameRecord::new()
+    }
+}
+
+// '}' would end the comment early
+fn escape_comment(comment: &str) -> String {
+    comment.replace('}', ")")
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in self.headers.iter() {
+            writeln!(f, "[{} \"{}\"]", key, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f, "[{} \"{}\"]", HEADER_RESULT, self.result)?;
+        writeln!(f)?;
+
+        // movetext, wrapped at 80 characters
+        let mut tokens = Vec::new();
+        if let Some(comment) = &self.comment {
+            tokens.push(format!("{{{}}}", escape_comment(comment)));
+        }
+        for (i, mov) in self.moves.iter().enumerate() {
+            if i % 2 == 0 {
+                tokens.push(format!("{}.", i / 2 + 1));
+            }
+            tokens.push(Square::from_idx(mov.mov).unwrap().to_string());
+            let comment = match (mov.eval, &mov.comment) {
+                (Some(eval), Some(comment)) => Some(format!("[%eval {}] {}", eval, escape_comment(comment))),
+                (Some(eval), None) => Some(format!("[%eval {}]", eval)),
+                (None, Some(comment)) => Some(escape_comment(comment)),
+                (None, None) => None,
+            };
+            if let Some(comment) = comment {
+                tokens.push(format!("{{{}}}", comment));
+            }
+        }
+        tokens.push(self.result.to_string());
+
+        let mut line_len = 0;
+        for tok in tokens {
+            if line_len > 0 && line_len + 1 + tok.len() > 80 {
+                writeln!(f)?;
+                line_len = 0;
+            }
+            if line_len > 0 {
+                write!(f, " ")?;
+                line_len += 1;
+            }
+            write!(f, "{}", tok)?;
+            line_len += tok.len();
+        }
+        writeln!(f)
+    }
+}
+
+// reads one game from text, starting at offset. Offsets in errors are into
+// text as a whole
+struct RecordParser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> RecordParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.rest().is_empty()
+    }
+
+    fn error(&self, expected: &str, found: &str) -> ParseError {
+        ParseError::expected(self.offset, expected, found)
+    }
+
+    // [Key "value"]
+    fn parse_header(&mut self) -> Result<(String, String), ParseError> {
+        debug_assert!(self.rest().starts_with('['));
+        self.offset += 1;
+        let rest = self.rest();
+        let key_len = rest.find(|c: char| c.is_whitespace() || c == '"' || c == ']').unwrap_or(rest.len());
+        if key_len == 0 {
+            return Err(self.error("a header name", &rest[..rest.len().min(1)]));
+        }
+        let key = rest[..key_len].to_string();
+        self.offset += key_len;
+        self.skip_whitespace();
+
+        if !self.rest().starts_with('"') {
+            return Err(self.error("'\"'", &self.rest()[..self.rest().len().min(1)]));
+        }
+        self.offset += 1;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        loop {
+            match chars.next() {
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                Some((i, '"')) => {
+                    self.offset += i + 1;
+                    self.skip_whitespace();
+                    if !self.rest().starts_with(']') {
+                        return Err(self.error("']'", &self.rest()[..self.rest().len().min(1)]));
+                    }
+                    self.offset += 1;
+                    return Ok((key, value));
+                },
+                Some((_, '\n')) | None => break,
+                Some((_, c)) => value.push(c),
+            }
+        }
+        return Err(self.error("a closing '\"' on the same line", ""));
+    }
+
+    // splits [%eval <score>] off the rest of the comment
+    fn parse_comment(&mut self) -> Result<(Option<String>, Option<Score>), ParseError> {
+        debug_assert!(self.rest().starts_with('{'));
+        let start = self.offset;
+        let end = match self.rest().find('}') {
+            Some(end) => end,
+            None => return Err(self.error("a closing '}'", "")),
+        };
+        let mut comment = self.rest()[1..end].to_string();
+        self.offset += end + 1;
+
+        let mut eval = None;
+        if let Some(tag_start) = comment.find("[%eval") {
+            let tag_len = match comment[tag_start..].find(']') {
+                Some(len) => len + 1,
+                None => return Err(ParseError::expected(start + 1 + tag_start, "'[%eval <score>]'", &comment[tag_start..])),
+            };
+            let score = comment[tag_start + 6..tag_start + tag_len - 1].trim();
+            match score.parse::<Score>() {
+                Ok(score) => eval = Some(score),
+                Err(_) => return Err(ParseError::expected(start + 1 + tag_start, "an eval score", score)),
+            }
+            comment.replace_range(tag_start..tag_start + tag_len, "");
+        }
+        let comment = comment.trim();
+        let comment = if comment.is_empty() { None } else { Some(comment.to_string()) };
+        return Ok((comment, eval));
+    }
+
+    fn next_token(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == '{' || c == '[').unwrap_or(rest.len());
+        self.offset += len;
+        return &rest[..len];
+    }
+
+    fn parse_game(&mut self) -> Result<GameRecord, ParseError> {
+        let mut ret = GameRecord::new();
+        let mut header_result = None;
+        self.skip_whitespace();
+        while self.rest().starts_with('[') {
+            let header_offset = self.offset;
+            let (key, value) = self.parse_header()?;
+            if key == HEADER_RESULT {
+                match value.parse::<GameResult>() {
+                    Ok(result) => header_result = Some(result),
+                    Err(_) => return Err(ParseError::expected(header_offset, "a result", &value)),
+                }
+            } else {
+                if key == HEADER_RULESET && value.parse::<Ruleset>().is_err() {
+                    return Err(ParseError::expected(header_offset, "a ruleset", &value));
+                }
+                ret.headers.push((key, value));
+            }
+            self.skip_whitespace();
+        }
+
+        ret.pos = Position::with_ruleset(ret.ruleset());
+        let mut result = None;
+        // the movetext ends with the result, or where the next game's
+        // headers start
+        while !self.at_end() && !self.rest().starts_with('[') {
+            if self.rest().starts_with('{') {
+                let (comment, eval) = self.parse_comment()?;
+                match ret.moves.last_mut() {
+                    Some(mov) => {
+                        mov.comment = comment;
+                        mov.eval = eval;
+                    },
+                    None => ret.comment = comment,
+                }
+                continue;
+            }
+            let tok_offset = self.offset;
+            let tok = self.next_token();
+            if let Ok(res) = tok.parse::<GameResult>() {
+                result = Some(res);
+                break;
+            }
+            // move numbers, like 12. or 12...
+            let digits = tok.trim_end_matches('.');
+            if digits.len() < tok.len() && !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            match tok.parse::<Square>().map(|square| square.idx()) {
+                Ok(mov) if !ret.pos.is_over() && ret.pos.legal_moves().contains(mov) => {
+                    ret.pos.make_move(mov);
+                    ret.moves.push(RecordMove::new(mov));
+                },
+                _ => return Err(ParseError::expected(tok_offset, "a legal move, a move number or a result", tok)),
+            }
+        }
+
+        ret.result = match (result, header_result) {
+            (Some(res), Some(header)) if res != header => {
+                return Err(self.error(&format!("result {} like in the header", header), &res.to_string()));
+            },
+            (Some(res), _) | (None, Some(res)) => res,
+            (None, None) => ret.pos.get_result(),
+        };
+        if ret.pos.is_over() && ret.result != ret.pos.get_result() {
+            return Err(self.error(&format!("result {} as on the board", ret.pos.get_result()), &ret.result.to_string()));
+        }
+        return Ok(ret);
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<GameRecord, ParseError> {
+        let mut parser = RecordParser { text: text, offset: 0 };
+        let ret = parser.parse_game()?;
+        if !parser.at_end() {
+            return Err(parser.error("end of input", parser.rest().lines().next().unwrap_or("")));
+        }
+        return Ok(ret);
+    }
+}
+
+// all the games in text, separated by whitespace
+pub fn parse_games(text: &str) -> Result<Vec<GameRecord>, ParseError> {
+    let mut parser = RecordParser { text: text, offset: 0 };
+    let mut ret = Vec::new();
+    while !parser.at_end() {
+        ret.push(parser.parse_game()?);
+    }
+    return Ok(ret);
+}
+
+pub fn write_games(games: &[GameRecord]) -> String {
+    games.iter().map(|game| game.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    // line is where the game starts (from 0); the offset of err is into the
+    // game's text
+    Parse { line: usize, err: ParseError },
+    // Result isn't set as a header, see GameRecord::result
+    ResultHeader,
+    BadRuleset(ParseError),
+    // the move at ply (from 0) isn't legal there, either when it's pushed
+    // or under a new ruleset
+    IllegalMove { ply: usize, mov: Idx },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Io(err) => write!(f, "{}", err),
+            RecordError::Parse { line, err } => write!(f, "game starting on line {}: {}", line + 1, err),
+            RecordError::ResultHeader => write!(f, "the result isn't a header"),
+            RecordError::BadRuleset(err) => write!(f, "invalid ruleset: {}", err),
+            RecordError::IllegalMove { ply, mov } => write!(f, "illegal move {} as move {} of the game", mov, ply + 1),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+// reads the games of a (possibly large) file one at a time
+pub struct GameReader<R: BufRead> {
+    reader: R,
+    // the first header line of the next game, if already read
+    pending: Option<String>,
+    line: usize,
+}
+
+impl<R: BufRead> GameReader<R> {
+    pub fn new(reader: R) -> GameReader<R> {
+        GameReader {
+            reader: reader,
+            pending: None,
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for GameReader<R> {
+    type Item = Result<GameRecord, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = self.pending.take().unwrap_or_default();
+        let mut start_line = self.line - if text.is_empty() { 0 } else { 1 };
+        let mut in_movetext = false;
+        loop {
+            let mut buf = String::new();
+            match self.reader.read_line(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => self.line += 1,
+                Err(err) => return Some(Err(RecordError::Io(err))),
+            }
+            let trimmed = buf.trim();
+            if text.trim().is_empty() && trimmed.is_empty() {
+                // blank lines between games
+                start_line = self.line;
+                text.clear();
+                continue;
+            }
+            if trimmed.starts_with('[') && in_movetext {
+                // the next game's headers
+                self.pending = Some(buf);
+                break;
+            }
+            if !trimmed.is_empty() && !trimmed.starts_with('[') {
+                in_movetext = true;
+            }
+            text.push_str(&buf);
+        }
+        if text.trim().is_empty() {
+            return None;
+        }
+        return Some(text.parse().map_err(|err| RecordError::Parse { line: start_line, err: err }));
+    }
+}
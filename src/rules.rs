@@ -0,0 +1,152 @@
+/*
+The rules a game is played under. Every Position carries its Ruleset, so
+move generation, result detection and the engines all follow it without
+being told separately. The variants:
+
+- full_board: what a full board without three blocks in a row means.
+  Standard is a draw; on Codingame whoever captured more blocks wins (and
+  it's a draw if both captured as many).
+- tied_blocks: whether a block that filled up without a winner is dead, or
+  counts for both sides when looking for three blocks in a row. If one move
+  completes a row for both sides this way, the side that made it wins.
+- won_blocks: whether won blocks are closed, or stay open to play in. Moves
+  in an open won block never change who owns it. Being sent to a full block
+  always grants a free move anywhere on the board, and so does being sent
+  to a won one, except under OpenSendsYouThere where you have to play in it.
+
+Rulesets are written as a base, "codingame" or "standard", followed by
+"+tied-both" if tied blocks count for both, and "+open" or
+"+sends-you-there" for open won blocks, e.g. "standard+tied-both+open".
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::format::ParseError;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FullBoard {
+    Majority = 0,
+    Draw = 1,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TiedBlocks {
+    Dead = 0,
+    CountForBoth = 1,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WonBlocks {
+    Closed = 0,
+    Open = 1,
+    OpenSendsYouThere = 2,
+}
+
+impl WonBlocks {
+    #[inline(always)]
+    pub fn is_open(self) -> bool {
+        self != WonBlocks::Closed
+    }
+}
+
+// the default is Codingame's rules, which the engines were tuned for
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ruleset {
+    pub full_board: FullBoard,
+    pub tied_blocks: TiedBlocks,
+    pub won_blocks: WonBlocks,
+}
+
+// the number of distinct codes, see Ruleset::code
+pub(crate) const N_RULESET_CODES: usize = 12;
+
+impl Ruleset {
+    pub fn codingame() -> Ruleset {
+        Ruleset {
+            full_board: FullBoard::Majority,
+            tied_blocks: TiedBlocks::Dead,
+            won_blocks: WonBlocks::Closed,
+        }
+    }
+
+    pub fn standard() -> Ruleset {
+        Ruleset {
+            full_board: FullBoard::Draw,
+            ..Ruleset::codingame()
+        }
+    }
+
+    pub fn with_tied_blocks(self, tied_blocks: TiedBlocks) -> Ruleset {
+        Ruleset {
+            tied_blocks: tied_blocks,
+            ..self
+        }
+    }
+
+    pub fn with_won_blocks(self, won_blocks: WonBlocks) -> Ruleset {
+        Ruleset {
+            won_blocks: won_blocks,
+            ..self
+        }
+    }
+
+    // a distinct small number for every ruleset, 0 for the default one
+    pub(crate) fn code(&self) -> usize {
+        self.full_board as usize | (self.tied_blocks as usize) << 1 | (self.won_blocks as usize) << 2
+    }
+}
+
+impl Default for Ruleset {
+    fn default() -> Ruleset {
+        Ruleset::codingame()
+    }
+}
+
+impl fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.full_board {
+            FullBoard::Majority => write!(f, "codingame")?,
+            FullBoard::Draw => write!(f, "standard")?,
+        }
+        if self.tied_blocks == TiedBlocks::CountForBoth {
+            write!(f, "+tied-both")?;
+        }
+        match self.won_blocks {
+            WonBlocks::Closed => {},
+            WonBlocks::Open => write!(f, "+open")?,
+            WonBlocks::OpenSendsYouThere => write!(f, "+sends-you-there")?,
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Ruleset {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<Ruleset, ParseError> {
+        let repr = repr.trim();
+        let mut parts = repr.split('+');
+        let base = parts.next().unwrap_or("");
+        let mut ret = match base {
+            "codingame" => Ruleset::codingame(),
+            "standard" => Ruleset::standard(),
+            _ => return Err(ParseError::expected(0, "'codingame' or 'standard'", base)),
+        };
+        let mut offset = base.len() + 1;
+        for modifier in parts {
+            match modifier {
+                "tied-both" => ret.tied_blocks = TiedBlocks::CountForBoth,
+                "open" => ret.won_blocks = WonBlocks::Open,
+                "sends-you-there" => ret.won_blocks = WonBlocks::OpenSendsYouThere,
+                _ => return Err(ParseError::expected(offset, "'tied-both', 'open' or 'sends-you-there'", modifier)),
+            }
+            offset += modifier.len() + 1;
+        }
+        return Ok(ret);
+    }
+}
@@ -7,7 +7,7 @@ use std::time::Instant;
 
 fn from_file(fname: &str, to_move: Side, auto_side: bool) -> Position {
     let s = std::fs::read_to_string(Path::new(fname)).unwrap();
-    return Position::from_compact_board(&s[..], to_move, auto_side);
+    return Position::from_compact_board(&s[..], to_move, auto_side).unwrap();
 }
 
 fn main() {
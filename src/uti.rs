@@ -0,0 +1,629 @@
+/*
+UTI (Universal Tic-tac-toe Interface), the line-based protocol spoken by the
+`main` binary over stdin/stdout. Loosely modelled after UCI.
+
+The engine writes moves as bit indices (see moves.rs). `pos moves` also takes
+any notation Square accepts, like c3/b2 or 4.4. Arguments are either positional or
+key=value pairs separated by whitespace or ';'. Every command gets exactly the
+replies listed below; anything the engine can't make sense of gets an
+`error msg=<message>` reply instead, as do illegal moves and searches that
+can't start (one is already running, or the game is over). On EOF the engine
+exits once the search in progress has been reported.
+
+GUI -> engine:
+  uti                      reply: one `option ...` per option, then `utiok`
+  id                       reply: `myid name=<name>;version=<version>`
+  isready                  reply: `readyok` (also while searching)
+  option <name>=<value>    set an option, see below. No reply
+  pos start                set up the starting position. No reply
+  pos bgn <bgn>            set up a position from BGN (see format.rs). No reply
+  pos moves <m1> <m2> ...  play moves from the current position. No reply
+  search free xtime=<ms> otime=<ms> [xinc=<ms>] [oinc=<ms>]
+                           search on a clock. `search free <xtime> <otime>`
+                           is also accepted
+  search depth <n>         search to a fixed depth
+  search time <ms>         search for a fixed time
+  search nodes <n>         search a fixed number of nodes
+  search forever           search until `stop`
+                           reply to every search, once done:
+                           `info best_move=<m>; eval=<score>[; depth=<n>][; nodes=<n>][; pv=<m1>,<m2>,...]`
+                           The mcts engine takes `search nodes` as rollouts,
+                           has no `search depth`, and reports its rollouts
+                           as nodes and its win rate in [0, 1] as eval
+  stop                     stop the search in progress; its info is still
+                           sent. Ignored if there is none
+  quit                     exit, without waiting for a search in progress
+
+Options:
+  threads   threads for the search. 0 means one per root move for
+            alpha-beta, and one per core for mcts
+  hash      transposition table size in megabytes
+  engine    alphabeta or mcts
+  mcts_c    exploration constant of the MCTS engine
+  mcts_selection
+            how MCTS picks the child to explore: ucb1, ucb1-tuned, puct or
+            rave (see engine/selection.rs). Each uses mcts_c
+  mcts_final_move
+            how MCTS picks the move to play: max-visits, max-value,
+            robust-max or secure-child (see FinalMoveCriterion in
+            engine/mcts.rs)
+  mcts_rollout
+            how MCTS rollouts pick moves: uniform, captures, avoid-free or
+            greedy:<epsilon> (see engine/rollout.rs)
+  mcts_memory
+            megabytes the MCTS trees of all threads are kept under
+            together, by dropping their least visited subtrees when they
+            fill up. 0 lets them grow
+  mcts_prior_visits, mcts_prior_bias
+            how much the static evaluation steers the MCTS tree, 0 for
+            not at all (see Priors in engine/mcts.rs)
+  ruleset   the rules to play by, like codingame or standard+tied-both (see
+            rules.rs). Applies to every `pos start` afterwards; a BGN
+            keeps its own
+*/
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::engine::Clock;
+use crate::engine::mcts::FinalMoveCriterion;
+use crate::engine::rollout::RolloutPolicy;
+use crate::engine::selection::SelectionKind;
+use crate::format::ParseError;
+use crate::moves::*;
+use crate::rules::Ruleset;
+
+pub const ENGINE_NAME: &str = "barbar";
+pub const ENGINE_VERSION: &str = "0.0.1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Uti,
+    Id,
+    IsReady,
+    SetOption(EngineOption),
+    Pos(PosCommand),
+    Search(SearchCommand),
+    Stop,
+    Quit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PosCommand {
+    Start,
+    Bgn(String),
+    Moves(Vec<Idx>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SearchCommand {
+    Free(Clock),
+    Depth(u16),
+    Time(u64),
+    Nodes(u64),
+    Forever,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EngineKind {
+    AlphaBeta,
+    Mcts,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EngineOption {
+    Threads(usize),
+    HashMb(usize),
+    Engine(EngineKind),
+    MctsC(f32),
+    MctsSelection(SelectionKind),
+    MctsFinalMove(FinalMoveCriterion),
+    MctsRollout(RolloutPolicy),
+    MctsPriorVisits(f32),
+    MctsPriorBias(f32),
+    MctsMemoryMb(usize),
+    Ruleset(Ruleset),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionKind {
+    Spin { min: i64, max: i64 },
+    Combo(Vec<String>),
+    Float,
+    String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionSpec {
+    pub name: String,
+    pub kind: OptionKind,
+    pub default: String,
+}
+
+// result of a search, as reported to the GUI
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchInfo {
+    pub best_move: Idx,
+    pub eval: f32,
+    pub depth: Option<u16>,
+    // for MCTS, this is the number of rollouts
+    pub nodes: Option<u64>,
+    pub pv: Vec<Idx>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    UtiOk,
+    MyId { name: String, version: String },
+    ReadyOk,
+    Option(OptionSpec),
+    Info(SearchInfo),
+    Error(String),
+}
+
+// the options announced in reply to `uti`
+pub fn option_specs() -> Vec<OptionSpec> {
+    vec![
+        OptionSpec {
+            name: "threads".to_string(),
+            kind: OptionKind::Spin { min: 0, max: 256 },
+            default: "0".to_string(),
+        },
+        OptionSpec {
+            name: "hash".to_string(),
+            kind: OptionKind::Spin { min: 1, max: 65536 },
+            default: "16".to_string(),
+        },
+        OptionSpec {
+            name: "engine".to_string(),
+            kind: OptionKind::Combo(vec!["alphabeta".to_string(), "mcts".to_string()]),
+            default: "alphabeta".to_string(),
+        },
+        OptionSpec {
+            name: "mcts_c".to_string(),
+            kind: OptionKind::Float,
+            default: "0.85".to_string(),
+        },
+        OptionSpec {
+            name: "mcts_selection".to_string(),
+            kind: OptionKind::Combo(vec![
+                SelectionKind::Ucb1.to_string(),
+                SelectionKind::Ucb1Tuned.to_string(),
+                SelectionKind::Puct.to_string(),
+                SelectionKind::Rave.to_string(),
+            ]),
+            default: SelectionKind::default().to_string(),
+        },
+        OptionSpec {
+            name: "mcts_final_move".to_string(),
+            kind: OptionKind::Combo(vec![
+                FinalMoveCriterion::MaxVisits.to_string(),
+                FinalMoveCriterion::MaxValue.to_string(),
+                FinalMoveCriterion::RobustMax.to_string(),
+                FinalMoveCriterion::SecureChild.to_string(),
+            ]),
+            default: FinalMoveCriterion::default().to_string(),
+        },
+        OptionSpec {
+            name: "mcts_rollout".to_string(),
+            kind: OptionKind::String,
+            default: RolloutPolicy::default().to_string(),
+        },
+        OptionSpec {
+            name: "mcts_prior_visits".to_string(),
+            kind: OptionKind::Float,
+            default: "0".to_string(),
+        },
+        OptionSpec {
+            name: "mcts_prior_bias".to_string(),
+            kind: OptionKind::Float,
+            default: "0".to_string(),
+        },
+        OptionSpec {
+            name: "mcts_memory".to_string(),
+            kind: OptionKind::Spin { min: 0, max: 65536 },
+            default: "1024".to_string(),
+        },
+        OptionSpec {
+            name: "ruleset".to_string(),
+            kind: OptionKind::String,
+            default: Ruleset::default().to_string(),
+        },
+    ]
+}
+
+// parses key=value pairs, separated by whitespace or ';'. Tokens without a
+// '=' are skipped
+pub fn parse_keyvalue(args: &[&str]) -> HashMap<String, String> {
+    let mut ret = HashMap::new();
+    for arg in args {
+        for pair in arg.split(';') {
+            let mut kv = pair.splitn(2, '=');
+            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+                ret.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+    return ret;
+}
+
+fn parse_arg<T: FromStr>(what: &str, val: &str) -> Result<T, String> {
+    val.parse().map_err(|_| format!("could not parse {} '{}'", what, val))
+}
+
+// a cell index, or any notation Square accepts
+fn parse_move(val: &str) -> Result<Idx, String> {
+    if let Ok(mov) = val.parse::<Idx>() {
+        if mov as usize >= 81 {
+            return Err(format!("move {} out of range", mov));
+        }
+        return Ok(mov);
+    }
+    match val.parse::<Square>() {
+        Ok(square) => Ok(square.idx()),
+        Err(_) => Err(format!("invalid move '{}'", val)),
+    }
+}
+
+fn parse_move_list(val: &str) -> Result<Vec<Idx>, String> {
+    val.split(',').filter(|tok| !tok.is_empty()).map(parse_move).collect()
+}
+
+// the arguments of 'search free', either as key-values or '<xtime> <otime>'
+fn parse_clock(args: &[&str]) -> Result<Clock, String> {
+    let kv = parse_keyvalue(args);
+    let (xtime, otime) = if kv.is_empty() {
+        if args.len() < 2 {
+            return Err("'search free' needs xtime and otime".to_string());
+        }
+        (args[0].to_string(), args[1].to_string())
+    } else {
+        match (kv.get("xtime"), kv.get("otime")) {
+            (Some(xtime), Some(otime)) => (xtime.clone(), otime.clone()),
+            _ => return Err("'search free' needs xtime and otime".to_string()),
+        }
+    };
+    let zero = "0".to_string();
+    return Ok(Clock {
+        x_millis: parse_arg("xtime", &xtime)?,
+        o_millis: parse_arg("otime", &otime)?,
+        x_inc_millis: parse_arg("xinc", kv.get("xinc").unwrap_or(&zero))?,
+        o_inc_millis: parse_arg("oinc", kv.get("oinc").unwrap_or(&zero))?,
+    });
+}
+
+fn parse_pos(args: &[&str]) -> Result<PosCommand, String> {
+    match args.first() {
+        Some(&"start") => Ok(PosCommand::Start),
+        Some(&"bgn") => {
+            if args.len() < 2 {
+                return Err("need bgn string".to_string());
+            }
+            Ok(PosCommand::Bgn(args[1..].join(" ")))
+        },
+        Some(&"moves") => {
+            let moves = args[1..].iter().map(|tok| parse_move(tok)).collect::<Result<Vec<_>, _>>()?;
+            Ok(PosCommand::Moves(moves))
+        },
+        Some(other) => Err(format!("unknown pos subcommand '{}'", other)),
+        None => Err("pos command needs a subcommand".to_string()),
+    }
+}
+
+fn parse_search(args: &[&str]) -> Result<SearchCommand, String> {
+    let sub = match args.first() {
+        Some(sub) => *sub,
+        None => return Err("search needs a subcommand".to_string()),
+    };
+    if sub == "free" {
+        return Ok(SearchCommand::Free(parse_clock(&args[1..])?));
+    } else if sub == "forever" {
+        return Ok(SearchCommand::Forever);
+    }
+    let arg = match args.get(1) {
+        Some(arg) => *arg,
+        None => return Err(format!("'search {}' needs an argument", sub)),
+    };
+    match sub {
+        "depth" => {
+            let depth = parse_arg("depth", arg)?;
+            if depth == 0 {
+                return Err("depth must be at least 1".to_string());
+            }
+            Ok(SearchCommand::Depth(depth))
+        },
+        "time" => Ok(SearchCommand::Time(parse_arg("time", arg)?)),
+        "nodes" => Ok(SearchCommand::Nodes(parse_arg("nodes", arg)?)),
+        other => Err(format!("unknown search subcommand '{}'", other)),
+    }
+}
+
+fn parse_option(args: &[&str]) -> Result<EngineOption, String> {
+    let kv = parse_keyvalue(args);
+    if kv.len() != 1 {
+        return Err("option needs exactly one <name>=<value>".to_string());
+    }
+    let (name, value) = kv.iter().next().unwrap();
+    match &name[..] {
+        "threads" => Ok(EngineOption::Threads(parse_arg("threads", value)?)),
+        "hash" => {
+            let mb = parse_arg("hash", value)?;
+            if mb == 0 {
+                return Err("hash must be at least 1".to_string());
+            }
+            Ok(EngineOption::HashMb(mb))
+        },
+        "engine" => match value.parse() {
+            Ok(kind) => Ok(EngineOption::Engine(kind)),
+            Err(_) => Err(format!("unknown engine '{}'", value)),
+        },
+        "mcts_c" => Ok(EngineOption::MctsC(parse_arg("mcts_c", value)?)),
+        "mcts_selection" => match value.parse() {
+            Ok(kind) => Ok(EngineOption::MctsSelection(kind)),
+            Err(_) => Err(format!("unknown selection policy '{}'", value)),
+        },
+        "mcts_final_move" => match value.parse() {
+            Ok(criterion) => Ok(EngineOption::MctsFinalMove(criterion)),
+            Err(_) => Err(format!("unknown final move criterion '{}'", value)),
+        },
+        "mcts_rollout" => match value.parse() {
+            Ok(policy) => Ok(EngineOption::MctsRollout(policy)),
+            Err(err) => Err(format!("invalid rollout policy: {}", err)),
+        },
+        "mcts_prior_visits" => Ok(EngineOption::MctsPriorVisits(parse_arg("mcts_prior_visits", value)?)),
+        "mcts_prior_bias" => Ok(EngineOption::MctsPriorBias(parse_arg("mcts_prior_bias", value)?)),
+        "mcts_memory" => Ok(EngineOption::MctsMemoryMb(parse_arg("mcts_memory", value)?)),
+        "ruleset" => match value.parse() {
+            Ok(ruleset) => Ok(EngineOption::Ruleset(ruleset)),
+            Err(err) => Err(format!("invalid ruleset: {}", err)),
+        },
+        other => Err(format!("unknown option '{}'", other)),
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let split = line.split_whitespace().collect::<Vec<&str>>();
+        let args = if split.is_empty() { &split[..] } else { &split[1..] };
+        match split.first() {
+            Some(&"uti") => Ok(Command::Uti),
+            Some(&"id") => Ok(Command::Id),
+            Some(&"isready") => Ok(Command::IsReady),
+            Some(&"option") => Ok(Command::SetOption(parse_option(args)?)),
+            Some(&"pos") => Ok(Command::Pos(parse_pos(args)?)),
+            Some(&"search") => Ok(Command::Search(parse_search(args)?)),
+            Some(&"stop") => Ok(Command::Stop),
+            Some(&"quit") => Ok(Command::Quit),
+            Some(other) => Err(format!("unknown command '{}'", other)),
+            None => Err("empty command".to_string()),
+        }
+    }
+}
+
+fn join_moves(moves: &[Idx], sep: &str) -> String {
+    moves.iter().map(|mov| mov.to_string()).collect::<Vec<_>>().join(sep)
+}
+
+impl FromStr for EngineKind {
+    type Err = ParseError;
+
+    fn from_str(repr: &str) -> Result<EngineKind, ParseError> {
+        match repr.trim() {
+            "alphabeta" => Ok(EngineKind::AlphaBeta),
+            "mcts" => Ok(EngineKind::Mcts),
+            other => Err(ParseError::expected(0, "'alphabeta' or 'mcts'", other)),
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineKind::AlphaBeta => write!(f, "alphabeta"),
+            EngineKind::Mcts => write!(f, "mcts"),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Uti => write!(f, "uti"),
+            Command::Id => write!(f, "id"),
+            Command::IsReady => write!(f, "isready"),
+            Command::SetOption(opt) => match opt {
+                EngineOption::Threads(n) => write!(f, "option threads={}", n),
+                EngineOption::HashMb(mb) => write!(f, "option hash={}", mb),
+                EngineOption::Engine(kind) => write!(f, "option engine={}", kind),
+                EngineOption::MctsC(c) => write!(f, "option mcts_c={}", c),
+                EngineOption::MctsSelection(kind) => write!(f, "option mcts_selection={}", kind),
+                EngineOption::MctsFinalMove(criterion) => write!(f, "option mcts_final_move={}", criterion),
+                EngineOption::MctsRollout(policy) => write!(f, "option mcts_rollout={}", policy),
+                EngineOption::MctsPriorVisits(visits) => write!(f, "option mcts_prior_visits={}", visits),
+                EngineOption::MctsPriorBias(bias) => write!(f, "option mcts_prior_bias={}", bias),
+                EngineOption::MctsMemoryMb(mb) => write!(f, "option mcts_memory={}", mb),
+                EngineOption::Ruleset(ruleset) => write!(f, "option ruleset={}", ruleset),
+            },
+            Command::Pos(PosCommand::Start) => write!(f, "pos start"),
+            Command::Pos(PosCommand::Bgn(bgn)) => write!(f, "pos bgn {}", bgn),
+            Command::Pos(PosCommand::Moves(moves)) => write!(f, "pos moves {}", join_moves(moves, " ")),
+            Command::Search(SearchCommand::Free(clock)) => write!(
+                f,
+                "search free xtime={} otime={} xinc={} oinc={}",
+                clock.x_millis, clock.o_millis, clock.x_inc_millis, clock.o_inc_millis
+            ),
+            Command::Search(SearchCommand::Depth(depth)) => write!(f, "search depth {}", depth),
+            Command::Search(SearchCommand::Time(millis)) => write!(f, "search time {}", millis),
+            Command::Search(SearchCommand::Nodes(nodes)) => write!(f, "search nodes {}", nodes),
+            Command::Search(SearchCommand::Forever) => write!(f, "search forever"),
+            Command::Stop => write!(f, "stop"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::UtiOk => write!(f, "utiok"),
+            Response::MyId { name, version } => write!(f, "myid name={};version={}", name, version),
+            Response::ReadyOk => write!(f, "readyok"),
+            Response::Option(spec) => {
+                write!(f, "option name={};", spec.name)?;
+                match &spec.kind {
+                    OptionKind::Spin { min, max } => write!(f, "type=spin;min={};max={};", min, max)?,
+                    OptionKind::Combo(vars) => write!(f, "type=combo;vars={};", vars.join(","))?,
+                    OptionKind::Float => write!(f, "type=float;")?,
+                    OptionKind::String => write!(f, "type=string;")?,
+                }
+                write!(f, "default={}", spec.default)
+            },
+            Response::Info(info) => {
+                write!(f, "info best_move={}; eval={}", info.best_move, info.eval)?;
+                if let Some(depth) = info.depth {
+                    write!(f, "; depth={}", depth)?;
+                }
+                if let Some(nodes) = info.nodes {
+                    write!(f, "; nodes={}", nodes)?;
+                }
+                if !info.pv.is_empty() {
+                    write!(f, "; pv={}", join_moves(&info.pv, ","))?;
+                }
+                Ok(())
+            },
+            Response::Error(msg) => write!(f, "error msg={}", msg),
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Response, String> {
+        let line = line.trim();
+        let (word, rest) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+        let kv = parse_keyvalue(&[rest]);
+        let get = |key: &str| -> Result<String, String> {
+            kv.get(key).cloned().ok_or(format!("'{}' is missing {}", word, key))
+        };
+        match word {
+            "utiok" => Ok(Response::UtiOk),
+            "readyok" => Ok(Response::ReadyOk),
+            "myid" => Ok(Response::MyId {
+                name: get("name")?,
+                version: get("version")?,
+            }),
+            "option" => {
+                let kind = match &get("type")?[..] {
+                    "spin" => OptionKind::Spin {
+                        min: parse_arg("min", &get("min")?)?,
+                        max: parse_arg("max", &get("max")?)?,
+                    },
+                    "combo" => OptionKind::Combo(get("vars")?.split(',').map(|s| s.to_string()).collect()),
+                    "float" => OptionKind::Float,
+                    "string" => OptionKind::String,
+                    other => return Err(format!("unknown option type '{}'", other)),
+                };
+                Ok(Response::Option(OptionSpec {
+                    name: get("name")?,
+                    kind: kind,
+                    default: get("default")?,
+                }))
+            },
+            "info" => Ok(Response::Info(SearchInfo {
+                best_move: parse_move(&get("best_move")?)?,
+                eval: parse_arg("eval", &get("eval")?)?,
+                depth: match kv.get("depth") {
+                    Some(depth) => Some(parse_arg("depth", depth)?),
+                    None => None,
+                },
+                nodes: match kv.get("nodes") {
+                    Some(nodes) => Some(parse_arg("nodes", nodes)?),
+                    None => None,
+                },
+                pv: match kv.get("pv") {
+                    Some(pv) => parse_move_list(pv)?,
+                    None => Vec::new(),
+                },
+            })),
+            // the message may contain anything, so don't split it
+            "error" => Ok(Response::Error(rest.trim_start_matches("msg=").to_string())),
+            other => Err(format!("unknown response '{}'", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_roundtrip() {
+        let commands = [
+            "uti",
+            "id",
+            "isready",
+            "option threads=4",
+            "option engine=mcts",
+            "option mcts_c=0.5",
+            "option mcts_selection=ucb1-tuned",
+            "option mcts_final_move=secure-child",
+            "option mcts_rollout=greedy:0.1",
+            "option mcts_prior_visits=10",
+            "option mcts_memory=256",
+            "option ruleset=standard+tied-both",
+            "pos start",
+            "pos bgn 2 0/0/0/0/0/0/0/0/0 0/0/0/0/0/0/0/0/0 9 X",
+            "pos moves 40 36",
+            "search free xtime=1000 otime=2000 xinc=10 oinc=0",
+            "search depth 6",
+            "search time 100",
+            "search nodes 5000",
+            "search forever",
+            "stop",
+            "quit",
+        ];
+        for line in commands.iter() {
+            let cmd = line.parse::<Command>().unwrap();
+            assert_eq!(&cmd.to_string(), line);
+        }
+
+        assert_eq!(
+            "search free 1000 2000".parse::<Command>().unwrap(),
+            Command::Search(SearchCommand::Free(Clock {
+                x_millis: 1000,
+                o_millis: 2000,
+                x_inc_millis: 0,
+                o_inc_millis: 0,
+            })),
+        );
+        assert!("pos moves 81".parse::<Command>().is_err());
+        assert!("search depth".parse::<Command>().is_err());
+        assert!("search depth 0".parse::<Command>().is_err());
+        assert!("option hash=0".parse::<Command>().is_err());
+        assert!("".parse::<Command>().is_err());
+        assert!("bogus".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let mut responses = vec![
+            Response::UtiOk,
+            Response::ReadyOk,
+            Response::MyId { name: ENGINE_NAME.to_string(), version: ENGINE_VERSION.to_string() },
+            Response::Info(SearchInfo { best_move: 40, eval: -1.5, depth: Some(7), nodes: Some(1234), pv: vec![40, 36, 4] }),
+            Response::Info(SearchInfo { best_move: 0, eval: 0.25, depth: None, nodes: None, pv: vec![] }),
+            Response::Error("unknown command 'bogus'".to_string()),
+        ];
+        responses.extend(option_specs().into_iter().map(Response::Option));
+        for res in responses {
+            assert_eq!(res.to_string().parse::<Response>().unwrap(), res);
+        }
+    }
+}
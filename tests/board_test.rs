@@ -0,0 +1,129 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use uttt::board::*;
+use uttt::format::*;
+use uttt::game::*;
+use uttt::moves::*;
+use uttt::rules::*;
+mod common;
+
+// a random level 3 game, 393 moves in. O to move
+const L3_LATE: &str = "3 \
+    80/29/180/182/9d/a3/85/58/31/28/109/1ff/48/20/90/52/12a/73/100/9/0/0/15/102/10a/90/152/\
+    1ff/40/20/60/1ff/105/2/161/101/0/8/c/1ff/1ff/8/0/0/1ff/142/10/1ff/109/1ff/21/106/0/1ff/\
+    b/10/5/10c/c3/140/26/20/50/41/0/1ff/104/103/20/aa/8/4/0/a2/ae/25/120/81/0/cc/33 \
+    4a/104/0/11/0/104/70/4/46/142/4/0/1ff/108/44/1/4/4/a0/180/1ff/89/160/b0/40/4c/28/\
+    98/88/52/181/100/d0/c/4/ae/1ff/1ff/162/8c/10/1ff/1ff/1ff/8a/35/43/0/50/2/112/10/1ff/190/\
+    10/1ff/190/30/10/9/100/50/24/12/2/0/b1/90/1ff/114/81/30/1ff/1ff/1ff/10/1ff/1ff/1ff/100/18c \
+    2 O";
+
+// plays a random game from board, checking it along the way
+fn random_game(rng: &mut SmallRng, mut board: Board) -> Board {
+    while !board.is_over() {
+        assert_eq!(board.validate(), Ok(()));
+        assert_eq!(Board::from_bgn(&board.to_bgn()).unwrap(), board);
+        let moves = board.legal_moves();
+        board.make_move(moves.nth_move(rng.gen_range(0, moves.size())));
+    }
+    assert_eq!(board.validate(), Ok(()));
+    assert_eq!(Board::from_bgn(&board.to_bgn()).unwrap(), board);
+    return board;
+}
+
+#[test]
+fn tic_tac_toe() {
+    common::setup();
+    // the number of games at each ply is well known
+    let board = Board::new(1);
+    assert_eq!(perft_game(0, &board), 9);
+    assert_eq!(perft_game(1, &board), 72);
+    assert_eq!(perft_game(4, &board), 15120);
+    assert_eq!(perft_game(5, &board), 54720);
+    assert_eq!(perft_game(8, &board), 127872);
+    assert_eq!(perft_game(9, &board), 0);
+
+    let mut board = Board::new(1);
+    for cell in &[4, 0, 2, 6, 3, 5, 1, 7] {
+        board.make_move(*cell);
+    }
+    assert_eq!(board.legal_moves().collect::<Vec<_>>(), vec![8]);
+    board.make_move(8);
+    // a full board is a draw, even under the majority rule
+    assert_eq!(board.get_result(), GameResult::Draw);
+    assert_eq!(board.to_pretty_board(), "OXX\nXXO\nOOX");
+    assert_eq!(board.to_bgn(), "1 11e e1 0 O");
+
+    let mut rng = SmallRng::seed_from_u64(16);
+    for _ in 0..20 {
+        random_game(&mut rng, Board::new(1));
+    }
+}
+
+#[test]
+fn level_2_is_position() {
+    common::setup();
+    assert_eq!(perft_game(4, &Board::new(2)), 473256);
+    assert_eq!(perft_game(4, &Position::new()), 473256);
+
+    let mut rng = SmallRng::seed_from_u64(16);
+    let tied_both = Ruleset::standard().with_tied_blocks(TiedBlocks::CountForBoth);
+    for ruleset in [Ruleset::codingame(), tied_both].iter() {
+        for _ in 0..20 {
+            let mut board = Board::with_ruleset(2, *ruleset);
+            let mut pos = Position::with_ruleset(*ruleset);
+            while !pos.is_over() {
+                let moves = pos.legal_moves().collect::<Vec<_>>();
+                assert_eq!(board.legal_moves().collect::<Vec<_>>(), moves.iter().map(|mov| *mov as Cell).collect::<Vec<_>>());
+                assert_eq!(board.to_bgn(), pos.to_bgn());
+                let mov = moves[rng.gen_range(0, moves.len())];
+                board.make_move(mov as Cell);
+                pos.make_move(mov);
+            }
+            assert_eq!(board.get_result(), pos.get_result());
+            assert_eq!(Board::from_bgn(&pos.to_bgn()).unwrap(), board);
+        }
+    }
+}
+
+#[test]
+fn level_3() {
+    common::setup();
+    let board = Board::new(3);
+    assert_eq!(board.n_cells(), 729);
+    assert_eq!(perft_game(2, &board), 58824);
+    assert_eq!(perft_game(3, &board), 527472);
+
+    let board = Board::from_bgn(L3_LATE).unwrap();
+    assert_eq!(board.side_to_move(), Side::O);
+    assert_eq!(perft_game(2, &board), 1124);
+    assert_eq!(perft_game(3, &board), 26326);
+
+    let mut rng = SmallRng::seed_from_u64(16);
+    let tied_both = Ruleset::standard().with_tied_blocks(TiedBlocks::CountForBoth);
+    for ruleset in [Ruleset::codingame(), tied_both].iter() {
+        for _ in 0..5 {
+            random_game(&mut rng, Board::with_ruleset(3, *ruleset));
+        }
+    }
+}
+
+#[test]
+fn bgn_errors() {
+    common::setup();
+    let err = Board::from_bgn("4 0 0 1 X").err().unwrap();
+    assert_eq!(err.offset, 0);
+    // level 2 has 9 blocks a side
+    assert!(Board::from_bgn("2 0 0 1 X").is_err());
+    assert!(Board::from_bgn("1 0 0 1 X standard").is_ok());
+    let err = Board::from_bgn("1 0 0 1 X standard+open").err().unwrap();
+    assert_eq!(err.offset, 10);
+    let err = Board::from_bgn("1 7! 38 0 O").err().unwrap();
+    assert_eq!(err.offset, 2);
+    let err = Board::from_bgn("1 3 5 0 X").err().unwrap();
+    assert_eq!(err.kind, ParseErrorKind::Invalid(PositionError::Overlap { block: 0 }));
+    // O to move after X won
+    assert!(Board::from_bgn("1 7 18 0 O").unwrap().is_over());
+    let err = Board::from_bgn("1 7 38 0 X").err().unwrap();
+    assert_eq!(err.kind, ParseErrorKind::Invalid(PositionError::BadWinner));
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use uttt::engine::*;
 use uttt::moves::*;
 
@@ -16,13 +18,16 @@ fn stupid_search() {
     let pos = Position::from_move_list(
         "36, 0, 2, 18, 4, 37, 15, 55, 12, 29, 19, 11, 25, 66, 32, 48, 31,\
     40, 39, 30, 35, 74, 24, 58, 42, 61, 63, 5, 53, 80, 77, 45, 6, 14, 50, 47, 23, 46, 9, 75",
-    );
+    ).unwrap();
     println!("{}", pos.to_pretty_board());
-    panic!("Fix best_move first. Implement generic termination function");
-    //let tup = best_move(5, &mut pos);
-    // assert!(tup.1 > 0.0);
-    //let tup = best_move(6, &mut pos);
-    // assert!(tup.1 > 0.0);
+    let manager = Manager::from_position(pos);
+    let res = manager.search(SearchLimits::depth(5), Arc::new(AtomicBool::new(false)));
+    assert!(res.eval > 0.0);
+    let res = manager.search(SearchLimits::depth(6), Arc::new(AtomicBool::new(false)));
+    assert!(res.eval > 0.0);
+    // depth 0 is taken as 1
+    let res = manager.search(SearchLimits::depth(0), Arc::new(AtomicBool::new(false)));
+    assert_eq!(res.depth, 1);
 }
 
 #[test]
@@ -32,3 +37,71 @@ fn temp() {
     let a = eval::eval_block(0b111111111, 0b000000000);
     println!("{}", a);
 }
+
+#[test]
+fn search_pv() {
+    common::setup();
+    let pos = Position::from_move_list("0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14").unwrap();
+    let manager = Manager::from_position(pos);
+    let res = manager.search_fixed_time(200);
+    assert_eq!(res.pv[0], res.best_move);
+    // the pv must be a legal line
+    let mut pos = pos;
+    for mov in res.pv {
+        assert!(!pos.is_over());
+        assert!(pos.legal_moves().contains(mov));
+        pos.make_move(mov);
+    }
+}
+
+#[test]
+fn search_progress() {
+    common::setup();
+    let pos = Position::from_move_list("0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14").unwrap();
+    let mut manager = Manager::from_position(pos);
+    let (tx, rx) = std::sync::mpsc::channel();
+    manager.set_progress_channel(tx);
+    let res = manager.search_fixed_time(200);
+    drop(manager);
+
+    let progress = rx.iter().collect::<Vec<_>>();
+    assert!(!progress.is_empty());
+    for (i, info) in progress.iter().enumerate() {
+        assert_eq!(info.depth as usize, 4 + i);
+        assert!(!info.cut_off);
+    }
+    let last = progress.last().unwrap();
+    assert_eq!(last.depth, res.depth);
+    assert_eq!(last.best_move, res.best_move);
+    assert!(res.nodes >= last.nodes && res.nodes > 0);
+    assert!(res.seldepth >= res.depth);
+    assert!(res.cut_off);
+
+    // every root move has a score, and the best one is exact
+    assert_eq!(res.root_moves.len(), pos.legal_moves().size());
+    let best = res.root_moves.iter().find(|rm| rm.mov == res.best_move).unwrap();
+    assert_eq!(best.bound, tt::Bound::Exact);
+    assert_eq!(best.score, res.eval);
+}
+
+#[test]
+fn search_limits() {
+    common::setup();
+    let pos = Position::from_move_list("0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14").unwrap();
+    let manager = Manager::from_position(pos);
+
+    let res = manager.search(SearchLimits::depth(5), Arc::new(AtomicBool::new(false)));
+    assert_eq!(res.depth, 5);
+    assert!(!res.cut_off);
+
+    let manager = Manager::from_position(pos);
+    let res = manager.search(SearchLimits::nodes(50000), Arc::new(AtomicBool::new(false)));
+    assert!(res.cut_off);
+    // workers only check the budget every so often
+    assert!(res.nodes < 60000);
+
+    // a stop flag that is already set
+    let res = manager.search(SearchLimits::infinite(), Arc::new(AtomicBool::new(true)));
+    assert!(res.cut_off);
+    assert!(pos.legal_moves().contains(res.best_move));
+}
@@ -1,3 +1,4 @@
+use uttt::format::*;
 use uttt::moves::*;
 mod common;
 
@@ -7,9 +8,153 @@ fn test_bgn_bothways() {
     let move_list = "0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21,\
                      31, 40, 37, 13, 38, 23, 49, 22, 10, 14, 52, 55, 11,\
                      50, 46, 30, 29, 27, 32, 33, 58, 78, 59, 72, 57";
-    let pos = Position::from_move_list(move_list);
+    let pos = Position::from_move_list(move_list).unwrap();
     let bgn = pos.to_bgn();
-    let pos1 = Position::from_bgn(&bgn);
+    let pos1 = Position::from_bgn(&bgn).unwrap();
     println!("{}", pos.to_bgn());
     assert_eq!(pos.to_pretty_board(), pos1.to_pretty_board());
 }
+
+fn expected_at(err: ParseError) -> usize {
+    match err.kind {
+        ParseErrorKind::Expected { .. } => err.offset,
+        ParseErrorKind::Invalid(err) => panic!("expected a syntax error, got {}", err),
+    }
+}
+
+fn invalid(err: ParseError) -> PositionError {
+    match err.kind {
+        ParseErrorKind::Invalid(err) => err,
+        _ => panic!("expected an invalid position, got {}", err),
+    }
+}
+
+#[test]
+fn bgn_errors() {
+    common::setup();
+    let empty = "0/0/0/0/0/0/0/0/0";
+    assert_eq!(expected_at(Position::from_bgn("3 0").err().unwrap()), 0);
+    let bgn = format!("2 {} {} 9", empty, empty);
+    assert_eq!(expected_at(Position::from_bgn(&bgn).err().unwrap()), bgn.len());
+    assert_eq!(expected_at(Position::from_bgn("2 0/0/zz/0/0/0/0/0/0").err().unwrap()), 6);
+    assert_eq!(expected_at(Position::from_bgn("2 0/0/200/0/0/0/0/0/0").err().unwrap()), 6);
+    assert_eq!(expected_at(Position::from_bgn(&format!("2 0/0 {} 9 X", empty)).err().unwrap()), 5);
+    assert_eq!(expected_at(Position::from_bgn(&format!("2 {} {} 9 Z", empty, empty)).err().unwrap()), 40);
+    assert_eq!(expected_at(Position::from_bgn(&format!("2 {} {} 9 X X", empty, empty)).err().unwrap()), 42);
+    // two rows in one block: the second one came after the capture
+    assert_eq!(expected_at(Position::from_bgn(&format!("2 1c7/0/0/0/0/0/0/0/0 {} 9 O", empty)).err().unwrap()), 2);
+
+    // more O than X
+    let err = Position::from_bgn(&format!("2 {} 3/0/0/0/0/0/0/0/0 9 X", empty)).err().unwrap();
+    assert_eq!(invalid(err), PositionError::PieceCount);
+    // X just moved, so it's O's turn
+    let err = Position::from_bgn(&format!("2 10/0/0/0/0/0/0/0/0 {} 4 X", empty)).err().unwrap();
+    assert_eq!(invalid(err), PositionError::PieceCount);
+    // X's only move was on cell 4, so it can't have sent O to block 0
+    let err = Position::from_bgn(&format!("2 10/0/0/0/0/0/0/0/0 {} 0 O", empty)).err().unwrap();
+    assert_eq!(invalid(err), PositionError::LastBlock(0));
+    assert!(Position::from_bgn(&format!("2 10/0/0/0/0/0/0/0/0 {} 4 O", empty)).is_ok());
+    assert!(Position::from_bgn(&format!("2 {} {} 9 X", empty, empty)).is_ok());
+}
+
+#[test]
+fn move_list_errors() {
+    common::setup();
+    assert_eq!(expected_at(Position::from_move_list("40, 40").err().unwrap()), 4);
+    assert_eq!(expected_at(Position::from_move_list("40,x").err().unwrap()), 3);
+    assert_eq!(expected_at(Position::from_move_list("81").err().unwrap()), 0);
+    assert_eq!(Position::from_move_list("").unwrap().to_bgn(), Position::new().to_bgn());
+}
+
+#[test]
+fn compact_board_errors() {
+    common::setup();
+    let board = "...|...|...\n".repeat(3) + "-----------\n";
+    let board = board.repeat(2) + "...|...|...\n...|...|...\n...|...|...";
+    let ok = board.clone() + " -";
+    assert!(Position::from_compact_board(&ok, Side::X, true).is_ok());
+    assert_eq!(expected_at(Position::from_compact_board(&(board.clone() + " 9"), Side::X, true).err().unwrap()), 132);
+    let typo = "..x".to_string() + &ok[3..];
+    assert_eq!(expected_at(Position::from_compact_board(&typo, Side::X, true).err().unwrap()), 2);
+    assert_eq!(expected_at(Position::from_compact_board("...", Side::X, true).err().unwrap()), 0);
+    let two_o = "OO.".to_string() + &ok[3..];
+    assert_eq!(expected_at(Position::from_compact_board(&two_o, Side::X, true).err().unwrap()), 0);
+    assert_eq!(invalid(Position::from_compact_board(&two_o, Side::X, false).err().unwrap()), PositionError::PieceCount);
+    // two rows of X in the center block: the capture can't have been the last move there
+    let played_on = ok[..48].to_string() + "...|XXX|...\n...|XXX|..." + &ok[71..];
+    assert_eq!(expected_at(Position::from_compact_board(&played_on, Side::X, false).err().unwrap()), 52);
+}
+
+#[test]
+fn validate_game() {
+    common::setup();
+    let move_list = [0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21, 31, 40, 37, 13, 38, 23, 49];
+    let mut pos = Position::new();
+    assert_eq!(pos.validate(), Ok(()));
+    for mov in move_list.iter() {
+        pos.make_move(*mov);
+        assert_eq!(pos.validate(), Ok(()));
+        assert_eq!(Position::from_bgn(&pos.to_bgn()).unwrap().to_bgn(), pos.to_bgn());
+    }
+}
+
+#[test]
+fn display_fromstr() {
+    common::setup();
+    let pos = Position::from_move_list("0, 1, 9, 4, 36, 7, 70, 71, 79").unwrap();
+    let repr = pos.to_string();
+    assert_eq!(repr, pos.to_bgn());
+    assert_eq!(repr.parse::<Position>().unwrap(), pos);
+    assert_ne!(Position::new(), pos);
+    assert_eq!(format!("{:?}", pos), format!("Position({})", repr));
+
+    assert_eq!(Side::O.to_string().parse::<Side>().unwrap(), Side::O);
+    assert!("Z".parse::<Side>().is_err());
+    for result in [GameResult::XWon, GameResult::OWon, GameResult::Draw, GameResult::Ongoing].iter() {
+        assert_eq!(result.to_string().parse::<GameResult>().unwrap(), *result);
+    }
+
+    let moves = Position::new().legal_moves();
+    assert_eq!(moves.to_string().parse::<Moves>().unwrap(), moves);
+    assert_eq!("3, 40".parse::<Moves>().unwrap().collect::<Vec<_>>(), vec![3, 40]);
+    assert_eq!(format!("{:?}", "40, 3".parse::<Moves>().unwrap()), "[3, 40]");
+    assert_eq!("3, 81".parse::<Moves>().err().unwrap().offset, 2);
+}
+
+#[test]
+fn position_hash_eq() {
+    use std::collections::HashSet;
+    common::setup();
+    // the same position reached in two ways
+    let mut seen = HashSet::new();
+    seen.insert(Position::from_move_list("1, 9, 2, 18").unwrap());
+    assert!(seen.contains(&Position::from_move_list("2, 18, 1, 9").unwrap()));
+    assert!(!seen.contains(&Position::from_move_list("1, 9, 2, 19").unwrap()));
+}
+
+#[test]
+fn square_notation() {
+    common::setup();
+    for square in Square::iter() {
+        let idx = square.idx();
+        assert_eq!(Square::from_idx(idx), Some(square));
+        assert_eq!(Square::from_block_cell(square.block(), square.cell()), Some(square));
+        assert_eq!(Square::from_row_col(square.row(), square.col()), Some(square));
+        assert_eq!(square.to_string().parse::<Square>().unwrap(), square);
+        assert_eq!(format!("{}.{}", idx / 9, idx % 9).parse::<Square>().unwrap(), square);
+        assert_eq!(format!("{},{}", square.row(), square.col()).parse::<Square>().unwrap(), square);
+    }
+    // block c3 is the top right, cell b2 its center
+    let square = "c3/b2".parse::<Square>().unwrap();
+    assert_eq!(square.idx(), 22);
+    assert_eq!((square.row(), square.col()), (1, 7));
+    assert_eq!("A3/C1".parse::<Square>().unwrap().idx(), 8);
+    assert_eq!(Square::from_row_col(8, 0).unwrap().to_string(), "a1/a1");
+    assert_eq!(format!("{:?}", Square::from_idx(40).unwrap()), "Square(b2/b2)");
+
+    assert_eq!(Square::from_idx(81), None);
+    assert_eq!(Square::from_row_col(9, 0), None);
+    for bad in &["d1/a1", "a4/a1", "a1a1", "9.0", "4;4", ""] {
+        assert!(bad.parse::<Square>().is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use uttt::board::*;
+use uttt::engine::*;
+use uttt::moves::*;
+mod common;
+
+fn tic_tac_toe(cells: &[Cell]) -> Board {
+    let mut board = Board::new(1);
+    for cell in cells {
+        board.make_move(*cell);
+    }
+    return board;
+}
+
+fn search(board: Board, depth: u16) -> SearchResult<Cell> {
+    let manager = Manager::from_position(board);
+    return manager.search(SearchLimits::depth(depth), Arc::new(AtomicBool::new(false)));
+}
+
+#[test]
+fn alpha_beta_tic_tac_toe() {
+    common::setup();
+    // a draw with perfect play, whatever the first move
+    let res = search(Board::new(1), 9);
+    assert_eq!(res.eval, 0.0);
+    assert_eq!(res.root_moves.len(), 9);
+    assert!(res.root_moves.iter().all(|rm| rm.score <= 0.0));
+
+    // an edge reply to a corner loses
+    let res = search(tic_tac_toe(&[0, 1]), 7);
+    assert!(res.eval > 1e5);
+    // and so does anything but blocking a row
+    let res = search(tic_tac_toe(&[0, 4, 1]), 6);
+    assert_eq!(res.best_move, 2);
+    assert_eq!(res.eval, 0.0);
+
+    // the pv is a legal line that ends the game
+    let mut board = tic_tac_toe(&[0, 1]);
+    let res = search(board.clone(), 7);
+    for mov in res.pv {
+        assert!(board.legal_moves().contains(mov));
+        board.make_move(mov);
+    }
+    assert_eq!(board.get_result(), GameResult::XWon);
+}
@@ -0,0 +1,39 @@
+use uttt::moves::*;
+mod common;
+
+#[test]
+fn incremental_hash() {
+    common::setup();
+    assert_eq!(Position::new().hash(), 0);
+    let mut pos = Position::new();
+    // includes several block captures
+    let move_list = [0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21, 31, 40, 37, 13, 38, 23, 49];
+    for mov in move_list.iter() {
+        pos.make_move(*mov);
+        assert_eq!(pos.hash(), pos.compute_hash());
+    }
+}
+
+#[test]
+fn transposition_same_hash() {
+    common::setup();
+    // same pieces and same block to play in, reached in a different order
+    let pos1 = Position::from_move_list("1, 9, 2, 18").unwrap();
+    let pos2 = Position::from_move_list("2, 18, 1, 9").unwrap();
+    assert_eq!(pos1.to_bgn(), pos2.to_bgn());
+    assert_eq!(pos1.hash(), pos2.hash());
+
+    let pos3 = Position::from_move_list("1, 9, 2, 19").unwrap();
+    assert_ne!(pos1.hash(), pos3.hash());
+}
+
+#[test]
+fn bgn_hash() {
+    common::setup();
+    let move_list = "0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21,\
+                     31, 40, 37, 13, 38, 23, 49, 22, 10, 14, 52, 55, 11,\
+                     50, 46, 30, 29, 27, 32, 33, 58, 78, 59, 72, 57";
+    let pos = Position::from_move_list(move_list).unwrap();
+    let pos1 = Position::from_bgn(&pos.to_bgn()).unwrap();
+    assert_eq!(pos.hash(), pos1.hash());
+}
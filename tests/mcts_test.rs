@@ -0,0 +1,451 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+
+use uttt::board::*;
+use uttt::engine::eval::*;
+use uttt::engine::mcts::*;
+use uttt::engine::rollout::*;
+use uttt::engine::selection::*;
+use uttt::game::*;
+use uttt::moves::*;
+mod common;
+
+fn tic_tac_toe(cells: &[Cell]) -> Board {
+    let mut board = Board::new(1);
+    for cell in cells {
+        board.make_move(*cell);
+    }
+    return board;
+}
+
+// a few moves in, with several blocks started
+const MIDGAME: &str = "40, 36, 4, 37, 13, 39, 30";
+
+fn midgame() -> Position {
+    Position::from_move_list(MIDGAME).unwrap()
+}
+
+fn no_stop() -> Arc<AtomicBool> {
+    Arc::new(AtomicBool::new(false))
+}
+
+// positions from random games, each with a move to come
+fn random_positions(seed: u64, n_games: usize) -> Vec<Position> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut ret = Vec::new();
+    for _ in 0..n_games {
+        let mut pos = Position::new();
+        while !pos.is_over() {
+            ret.push(pos);
+            pos.make_move(RolloutPolicy::Uniform.pick_move(&pos, &mut rng));
+        }
+    }
+    return ret;
+}
+
+// searches pos, plays the first n_moves of the pv on the tree and
+// searches on from what is kept, checking that the moves are legal
+fn search_and_advance<S: SelectionPolicy>(mcts: &mut MCTSWorker<SmallRng, Position, S>, pos: Position, rollouts: u32, n_moves: usize) {
+    let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(rollouts), no_stop());
+    assert_eq!(n_rollouts, rollouts);
+    assert!(pos.legal_moves().contains(res.best_move));
+    let pv = mcts.pv();
+    let mut pos = pos;
+    for node in &pv[..n_moves] {
+        mcts.advance(node.best_move);
+        pos.make_move(node.best_move);
+    }
+    let (res, _) = mcts.go(MCTSLimits::rollouts(rollouts), no_stop());
+    assert!(pos.legal_moves().contains(res.best_move));
+}
+
+#[test]
+fn mcts_tic_tac_toe() {
+    common::setup();
+    let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 4, 1]), 0.85, SmallRng::seed_from_u64(17));
+    let (res, _) = mcts.go(MCTSLimits::movetime(100), no_stop());
+    assert_eq!(res.best_move, 2);
+
+    // the same with trees on other threads
+    let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 4, 1]), 0.85, SmallRng::seed_from_u64(17));
+    mcts.set_threads(3);
+    let (res, n_rollouts) = mcts.go(MCTSLimits::movetime(100), no_stop());
+    assert_eq!(res.best_move, 2);
+    assert!(n_rollouts > 0);
+
+    // X wins on the spot
+    let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 3, 1, 4]), 0.85, SmallRng::seed_from_u64(17));
+    let (res, _) = mcts.go(MCTSLimits::movetime(100), no_stop());
+    assert_eq!(res.best_move, 2);
+    assert_eq!(res.value, 1.0);
+    assert_eq!(mcts.pv()[0].best_move, 2);
+}
+
+#[test]
+fn mcts_advance() {
+    common::setup();
+    let pos = midgame();
+    let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(18));
+    mcts.go(MCTSLimits::movetime(100), no_stop());
+    let before = mcts.n_nodes();
+    let pv = mcts.pv();
+    assert!(pv.len() >= 2);
+
+    // the subtree of the best move is kept as it was
+    mcts.advance(pv[0].best_move);
+    assert!(mcts.n_nodes() > 1 && mcts.n_nodes() < before);
+    let kept = mcts.pv();
+    assert_eq!(kept.len(), pv.len() - 1);
+    for (a, b) in kept.iter().zip(pv[1..].iter()) {
+        assert_eq!((a.best_move, a.value), (b.best_move, b.value));
+    }
+
+    // and searching goes on from there
+    mcts.advance(pv[1].best_move);
+    let (res, _) = mcts.go(MCTSLimits::movetime(50), no_stop());
+    let mut pos = pos;
+    pos.make_move(pv[0].best_move);
+    pos.make_move(pv[1].best_move);
+    assert!(pos.legal_moves().contains(res.best_move));
+
+    // a move that was never looked at starts over
+    let mut mcts = MCTSWorker::new(Board::new(1), 0.85, SmallRng::seed_from_u64(18));
+    mcts.advance(4);
+    assert_eq!(mcts.n_nodes(), 1);
+    let (res, _) = mcts.go(MCTSLimits::movetime(50), no_stop());
+    assert!(res.best_move != 4);
+}
+
+#[test]
+fn mcts_limits() {
+    common::setup();
+    let pos = midgame();
+
+    // a rollout budget gives the same search every time
+    let mut results = Vec::new();
+    for _ in 0..2 {
+        let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(20));
+        let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(3000), no_stop());
+        assert_eq!(n_rollouts, 3000);
+        results.push((res.best_move, res.value, mcts.n_nodes()));
+    }
+    assert_eq!(results[0], results[1]);
+
+    // split between threads
+    let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(20));
+    mcts.set_threads(3);
+    let (_, n_rollouts) = mcts.go(MCTSLimits::rollouts(3001), no_stop());
+    assert_eq!(n_rollouts, 3001);
+
+    // the tree stops growing at the node limit, give or take an expansion
+    let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(20));
+    mcts.go(MCTSLimits::nodes(500), no_stop());
+    assert!(mcts.n_nodes() >= 500 && mcts.n_nodes() < 500 + 81);
+
+    // stopped from outside
+    let stop = no_stop();
+    let localstop = Arc::clone(&stop);
+    let handle = std::thread::spawn(move || {
+        let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(20));
+        return mcts.go(MCTSLimits::infinite(), localstop);
+    });
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    let (res, _) = handle.join().unwrap();
+    assert!(pos.legal_moves().contains(res.best_move));
+
+    // a win on the spot is soon clear
+    let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 3, 1, 4]), 0.85, SmallRng::seed_from_u64(20));
+    let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(100000).with_early_stop(), no_stop());
+    assert_eq!(res.best_move, 2);
+    assert!(n_rollouts < 100000);
+
+    // the search also stops once the most visited move can't be overtaken
+    // with the rollouts left, before anything is proven, and those rollouts
+    // wouldn't have changed the move: with the same seed, a search without
+    // the early stop makes the same rollouts up to there
+    let mut undecided = 0;
+    for pos in random_positions(3, 1).into_iter().step_by(10) {
+        let mut early = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(0));
+        let (res, n_rollouts) = early.go(MCTSLimits::rollouts(3000).with_early_stop(), no_stop());
+        // which needs every rollout to count once
+        assert_eq!(early.report().moves.iter().map(|stats| stats.visits).sum::<f32>(), n_rollouts as f32);
+        if !res.proven && n_rollouts < 3000 {
+            undecided += 1;
+        }
+        let mut full = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(0));
+        assert_eq!(full.go(MCTSLimits::rollouts(3000), no_stop()).0.best_move, res.best_move);
+    }
+    assert!(undecided > 0);
+}
+
+#[test]
+fn rollout_policies() {
+    common::setup();
+    for repr in ["uniform", "captures", "avoid-free", "greedy:0.25"].iter() {
+        let policy = repr.parse::<RolloutPolicy>().unwrap();
+        assert_eq!(&policy.to_string(), repr);
+    }
+    assert!("greedy".parse::<RolloutPolicy>().is_err());
+    assert!("greedy:2".parse::<RolloutPolicy>().is_err());
+    assert!("heavy".parse::<RolloutPolicy>().is_err());
+
+    let mut rng = SmallRng::seed_from_u64(21);
+    for pos in random_positions(21, 20) {
+        let moves = pos.legal_moves();
+        let gains = moves.map(|mov| (mov, pos.eval_move(mov))).collect::<Vec<_>>();
+
+        // eval_move is what eval makes of the move
+        for &(mov, gain) in &gains {
+            let mut next = pos;
+            next.make_move(mov);
+            if !next.is_over() {
+                assert!((-eval(&next) - eval(&pos) - gain).abs() < 1e-3, "{} in {}", mov, pos);
+            }
+        }
+
+        let captures = moves.intersect(pos.get_1occ(pos.side_to_move()));
+        let mov = RolloutPolicy::Captures.pick_move(&pos, &mut rng);
+        assert!(moves.contains(mov));
+        assert!(captures.size() == 0 || captures.contains(mov));
+
+        let safe = moves.filter(|&mov| !pos.sends_to_free_move(mov)).count();
+        let mov = RolloutPolicy::AvoidFreeMoves.pick_move(&pos, &mut rng);
+        assert!(moves.contains(mov));
+        assert!(safe == 0 || !pos.sends_to_free_move(mov));
+
+        let best = gains.iter().map(|gain| gain.1).fold(std::f32::NEG_INFINITY, f32::max);
+        let mov = RolloutPolicy::EpsilonGreedy(0.0).pick_move(&pos, &mut rng);
+        assert_eq!(pos.eval_move(mov), best);
+    }
+}
+
+#[test]
+fn mcts_heuristics() {
+    common::setup();
+    let policies = [
+        RolloutPolicy::Uniform,
+        RolloutPolicy::Captures,
+        RolloutPolicy::AvoidFreeMoves,
+        RolloutPolicy::EpsilonGreedy(0.1),
+    ];
+    let priors = Priors { visits: 10.0, bias: 1.0 };
+    let pos = midgame();
+    for &policy in policies.iter() {
+        let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(21));
+        mcts.set_rollout_policy(policy);
+        mcts.set_priors(priors);
+        mcts.set_threads(2);
+        let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(2000), no_stop());
+        assert_eq!(n_rollouts, 2000);
+        assert!(pos.legal_moves().contains(res.best_move));
+    }
+
+    // priors don't get in the way of what the rollouts find
+    for &(visits, bias) in [(10.0, 0.0), (0.0, 1.0), (10.0, 1.0)].iter() {
+        let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 4, 1]), 0.85, SmallRng::seed_from_u64(21));
+        mcts.set_priors(Priors { visits: visits, bias: bias });
+        let (res, _) = mcts.go(MCTSLimits::rollouts(5000), no_stop());
+        assert_eq!(res.best_move, 2);
+    }
+}
+
+#[test]
+fn mcts_solver() {
+    common::setup();
+
+    // a win in one is proven, and the search ends there
+    let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 3, 1, 4]), 0.85, SmallRng::seed_from_u64(22));
+    let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(100000), no_stop());
+    assert_eq!((res.best_move, res.value, res.proven), (2, 1.0, true));
+    assert!(n_rollouts < 1000);
+
+    // O has to block, and it's a draw from there
+    for &threads in [1, 3].iter() {
+        let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 4, 1]), 0.85, SmallRng::seed_from_u64(22));
+        mcts.set_threads(threads);
+        let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(1000000), no_stop());
+        assert_eq!((res.best_move, res.value, res.proven), (2, 0.5, true));
+        assert!(n_rollouts < 1000000);
+    }
+
+    // X threatens both 3 and 7, so O loses whatever it does
+    let mut board = tic_tac_toe(&[0, 4, 8, 2, 6]);
+    let mut mcts = MCTSWorker::new(board.clone(), 0.85, SmallRng::seed_from_u64(22));
+    let (res, _) = mcts.go(MCTSLimits::rollouts(100000), no_stop());
+    assert_eq!((res.value, res.proven), (1.0, true));
+
+    // and the pv of a solved tree plays it out
+    for node in mcts.pv() {
+        board.make_move(node.best_move);
+    }
+    assert_eq!(board.get_result(), GameResult::XWon);
+}
+
+fn check_selection<S: SelectionPolicy>(selection: S) {
+    let mut mcts = MCTSWorker::with_selection(tic_tac_toe(&[0, 4, 1]), selection.clone(), SmallRng::seed_from_u64(23));
+    let (res, _) = mcts.go(MCTSLimits::rollouts(20000), no_stop());
+    assert_eq!(res.best_move, 2);
+
+    // on threads, and keeping the tree
+    let mut mcts = MCTSWorker::with_selection(midgame(), selection, SmallRng::seed_from_u64(23));
+    mcts.set_threads(2);
+    search_and_advance(&mut mcts, midgame(), 2000, 2);
+}
+
+#[test]
+fn mcts_selection_policies() {
+    common::setup();
+    for repr in ["ucb1", "ucb1-tuned", "puct", "rave"].iter() {
+        let kind = repr.parse::<SelectionKind>().unwrap();
+        assert_eq!(&kind.to_string(), repr);
+    }
+    assert!("uct".parse::<SelectionKind>().is_err());
+
+    check_selection(Ucb1 { c: 0.85 });
+    check_selection(Ucb1Tuned { c: 1.0 });
+    check_selection(Puct { c: 1.5 });
+    check_selection(Rave { c: 0.5, k: RAVE_K });
+
+    // plain UCB1 is what new sets up
+    let pos = midgame();
+    let mut results = Vec::new();
+    for explicit in [false, true].iter() {
+        let rng = SmallRng::seed_from_u64(23);
+        let mut mcts = if *explicit {
+            MCTSWorker::with_selection(pos, Ucb1 { c: 0.85 }, rng)
+        } else {
+            MCTSWorker::new(pos, 0.85, rng)
+        };
+        let (res, _) = mcts.go(MCTSLimits::rollouts(3000), no_stop());
+        results.push((res.best_move, res.value, mcts.n_nodes()));
+    }
+    assert_eq!(results[0], results[1]);
+}
+
+#[test]
+fn mcts_final_move() {
+    common::setup();
+    for repr in ["max-visits", "max-value", "robust-max", "secure-child"].iter() {
+        let criterion = repr.parse::<FinalMoveCriterion>().unwrap();
+        assert_eq!(&criterion.to_string(), repr);
+    }
+    assert!("max".parse::<FinalMoveCriterion>().is_err());
+
+    // O has to block whatever it goes by, and the report says why
+    let criteria = [
+        FinalMoveCriterion::MaxVisits,
+        FinalMoveCriterion::MaxValue,
+        FinalMoveCriterion::RobustMax,
+        FinalMoveCriterion::SecureChild,
+    ];
+    for &criterion in criteria.iter() {
+        let mut mcts = MCTSWorker::new(tic_tac_toe(&[0, 4, 1]), 0.85, SmallRng::seed_from_u64(24));
+        mcts.set_final_move(criterion);
+        mcts.set_threads(2);
+        let (res, n_rollouts) = mcts.go(MCTSLimits::rollouts(300), no_stop());
+        assert_eq!(res.best_move, 2);
+        assert_eq!(mcts.pv()[0].best_move, 2);
+
+        let report = mcts.report();
+        assert_eq!(report.side_to_move, Side::O);
+        assert_eq!(report.moves.len(), 6);
+        assert!(report.moves.windows(2).all(|pair| pair[0].visits >= pair[1].visits));
+        // every rollout of both threads, once, as the intervals assume
+        assert_eq!(report.moves.iter().map(|stats| stats.visits).sum::<f32>(), n_rollouts as f32);
+        for stats in report.moves.iter().filter(|stats| stats.proven.is_none()) {
+            assert!(stats.ci_low <= stats.value && stats.value <= stats.ci_high);
+            assert!(0.0 <= stats.ci_low && stats.ci_high <= 1.0);
+        }
+        assert_eq!(report.to_string().lines().count(), 7);
+    }
+
+    // RobustMax may go past a rollout limit, and the early stop copes
+    let pos = midgame();
+    for rollouts in 20..80 {
+        let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(rollouts as u64));
+        mcts.set_final_move(FinalMoveCriterion::RobustMax);
+        let limits = MCTSLimits::rollouts(rollouts).with_early_stop();
+        let (res, n_rollouts) = mcts.go(limits, no_stop());
+        assert!(pos.legal_moves().contains(res.best_move));
+        assert!(n_rollouts <= rollouts + rollouts / 4);
+    }
+
+    // a proven move's interval is its value, and an unvisited one knows nothing
+    let stats = MoveStats::new(3, 10.0, 0.8, Some(1.0));
+    assert_eq!((stats.ci_low, stats.ci_high), (1.0, 1.0));
+    let stats = MoveStats::new(3, 0.0, 0.0, None);
+    assert_eq!((stats.ci_low, stats.ci_high), (0.0, 1.0));
+    // and intervals narrow with visits
+    let few = MoveStats::new(3, 10.0, 0.6, None);
+    let many = MoveStats::new(3, 1000.0, 0.6, None);
+    assert!(few.ci_high - few.ci_low > many.ci_high - many.ci_low);
+
+    // the pv after any line in the tree continues the pv through it
+    let pos = midgame();
+    let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(24));
+    mcts.go(MCTSLimits::rollouts(3000), no_stop());
+    let pv = mcts.pv();
+    assert!(pv.len() >= 3);
+    let moves = pv.iter().map(|node| node.best_move).collect::<Vec<_>>();
+    for i in 0..moves.len() {
+        let after = mcts.pv_after(&moves[..i]);
+        assert_eq!(after.iter().map(|node| node.best_move).collect::<Vec<_>>(), moves[i..].to_vec());
+    }
+    // off the pv too, from the node's own side to move
+    let other = mcts.report().moves[1].mov;
+    let mut line = pos;
+    line.make_move(other);
+    for node in mcts.pv_after(&[other]) {
+        assert!(line.legal_moves().contains(node.best_move));
+        line.make_move(node.best_move);
+    }
+    // and nothing for a line the tree doesn't have
+    assert!(mcts.pv_after(&[moves[0], moves[0]]).is_empty());
+}
+
+fn check_memory_cap<S: SelectionPolicy>(selection: S) {
+    let pos = midgame();
+    let mut capped = MCTSWorker::with_selection(pos, selection.clone(), SmallRng::seed_from_u64(25));
+    capped.set_memory_cap(Some(1));
+    let mut uncapped = MCTSWorker::with_selection(pos, selection, SmallRng::seed_from_u64(25));
+    let mut max_nodes = 0;
+    for _ in 0..8 {
+        let (res, n_rollouts) = capped.go(MCTSLimits::rollouts(10000), no_stop());
+        assert_eq!(n_rollouts, 10000);
+        assert!(pos.legal_moves().contains(res.best_move));
+        max_nodes = std::cmp::max(max_nodes, capped.n_nodes());
+        // counting the room set aside for the tree
+        assert!(capped.memory_bytes() <= 1024 * 1024);
+        uncapped.go(MCTSLimits::rollouts(10000), no_stop());
+    }
+    // the capped tree stopped growing, and what is left is still a tree
+    assert!(uncapped.n_nodes() > max_nodes);
+    // a node takes more than 24 bytes
+    assert!(max_nodes < 1024 * 1024 / 24);
+    let mut line = pos;
+    for node in capped.pv() {
+        assert!(line.legal_moves().contains(node.best_move));
+        line.make_move(node.best_move);
+    }
+    // the root keeps all its moves
+    assert_eq!(capped.report().moves.len(), pos.legal_moves().size());
+
+    // and the kept subtree goes on after a move
+    search_and_advance(&mut capped, pos, 10000, 1);
+
+    // the threads' trees share the cap
+    capped.set_threads(2);
+    capped.go(MCTSLimits::rollouts(40000), no_stop());
+    assert!(capped.memory_bytes() <= 512 * 1024);
+}
+
+#[test]
+fn mcts_memory_cap() {
+    common::setup();
+    check_memory_cap(Ucb1 { c: 0.85 });
+    // with statistics kept next to the nodes
+    check_memory_cap(Rave { c: 0.5, k: RAVE_K });
+}
@@ -1,7 +1,24 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
 use uttt::moves::*;
+use uttt::rules::*;
 
 mod common;
 
+const EARLY_MID: &str = "0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14";
+
+// plays move_list from the start under ruleset, checking every move is legal
+fn play(ruleset: Ruleset, move_list: &str) -> Position {
+    let mut pos = Position::with_ruleset(ruleset);
+    for tok in move_list.split(',') {
+        let mov = tok.trim().parse::<Idx>().unwrap();
+        assert!(pos.legal_moves().any(|legal| legal == mov));
+        pos.make_move(mov);
+    }
+    return pos;
+}
+
 #[test]
 fn startpos() {
     common::setup();
@@ -15,7 +32,7 @@ fn draw_in_5() {
     let move_list = "0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21,\
                      31, 40, 37, 13, 38, 23, 49, 22, 10, 14, 52, 55, 11,\
                      50, 46, 30, 29, 27, 32, 33, 58, 78, 59, 72, 57";
-    let mut pos = Position::from_move_list(move_list);
+    let mut pos = Position::from_move_list(move_list).unwrap();
     assert_eq!(perft(5, &mut pos), 72);
     assert_eq!(perft(6, &mut pos), 0);
 }
@@ -23,11 +40,74 @@ fn draw_in_5() {
 #[test]
 fn early_mid() {
     common::setup();
-    let move_list = "0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14";
-    let mut pos = Position::from_move_list(move_list);
+    let mut pos = Position::from_move_list(EARLY_MID).unwrap();
     assert_eq!(perft(5, &mut pos), 4876350);
 }
 
+// reference numbers for the open won block variants, checked against a
+// separate implementation of the rules. The first capture can only be on
+// ply 5, so perft 4 from the start is the same for every variant
+#[test]
+fn open_won_blocks() {
+    common::setup();
+    let open = Ruleset::codingame().with_won_blocks(WonBlocks::Open);
+    let sends = Ruleset::codingame().with_won_blocks(WonBlocks::OpenSendsYouThere);
+
+    let mut pos = Position::with_ruleset(open);
+    assert_eq!(perft(4, &mut pos), 473256);
+    assert_eq!(perft(5, &mut pos), 4021248);
+    assert_eq!(perft(6, &mut pos), 33790320);
+    let mut pos = Position::with_ruleset(sends);
+    assert_eq!(perft(5, &mut pos), 4017888);
+    assert_eq!(perft(6, &mut pos), 33702480);
+
+    // blocks 0, 3 and 4 are won, and their cells are still free
+    let mut pos = play(open, EARLY_MID);
+    assert_eq!(perft(4, &mut pos), 618749);
+    assert_eq!(perft(5, &mut pos), 9926475);
+    let mut pos = play(sends, EARLY_MID);
+    assert_eq!(perft(4, &mut pos), 26691);
+    assert_eq!(perft(5, &mut pos), 195468);
+}
+
+#[test]
+fn open_won_blocks_consistency() {
+    common::setup();
+    let mut rng = SmallRng::seed_from_u64(15);
+    for won_blocks in [WonBlocks::Open, WonBlocks::OpenSendsYouThere].iter() {
+        let ruleset = Ruleset::standard().with_won_blocks(*won_blocks);
+        for _ in 0..20 {
+            let mut pos = Position::with_ruleset(ruleset);
+            while !pos.is_over() {
+                assert_eq!(pos.validate(), Ok(()));
+                assert_eq!(Position::from_bgn(&pos.to_bgn()).unwrap(), pos);
+                if pos.cur_ply() % 10 == 5 {
+                    let bgn = pos.to_bgn();
+                    assert_eq!(perft_unmake(2, &mut pos), perft(2, &mut pos));
+                    assert_eq!(pos.to_bgn(), bgn);
+                }
+                let moves = pos.legal_moves();
+                pos.make_move(moves.nth_move(rng.gen_range(0, moves.size()) as u8));
+            }
+            assert_eq!(pos.validate(), Ok(()));
+            assert_eq!(Position::from_bgn(&pos.to_bgn()).unwrap(), pos);
+        }
+    }
+}
+
+#[test]
+fn unmake_perft() {
+    common::setup();
+    let mut pos = Position::new();
+    assert_eq!(perft_unmake(5, &mut pos), perft(5, &mut pos));
+    let move_list = "0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14";
+    let mut pos = Position::from_move_list(move_list).unwrap();
+    let bgn = pos.to_bgn();
+    assert_eq!(perft_unmake(4, &mut pos), perft(4, &mut pos));
+    // back where it started
+    assert_eq!(pos.to_bgn(), bgn);
+}
+
 #[test]
 fn test_1occ() {
     common::setup();
@@ -36,18 +116,16 @@ fn test_1occ() {
      ...|.X.|...\n\
      ...|X.O|...\n\
      -----------\n\
-     O..|...|.X.\n\
+     O..|...|...\n\
      OO.|...|...\n\
      ...|...|.X.\n\
      -----------\n\
-     X.O|OOO|..O\n\
-     .O.|OOO|.O.\n\
-     X..|OOO|... 0";
-    let pos = Position::from_compact_board(BOARD, Side::O, false);
+     X..|...|...\n\
+     ...|...|...\n\
+     ...|...|... 0";
+    let pos = Position::from_compact_board(BOARD, Side::O, false).unwrap();
     println!("{}", pos.to_pretty_board());
     let occ1 = pos.get_1occ(pos.side_to_move());
-    for mov in occ1 {
-    println!("{}", mov);
-
-    }
+    // O completes block 3 with any of these
+    assert_eq!(occ1.collect::<Vec<_>>(), vec![32, 33, 35]);
 }
@@ -0,0 +1,140 @@
+use std::io::Cursor;
+
+use uttt::format::*;
+use uttt::moves::*;
+use uttt::record::*;
+use uttt::rules::*;
+mod common;
+
+const MOVE_LIST: &str = "0, 1, 9, 4, 36, 7, 70, 71, 79, 67, 43, 63, 20, 21, \
+                         31, 40, 37, 13, 38, 23, 49, 22, 10, 14, 52, 55, 11, \
+                         50, 46, 30, 29, 27, 32, 33, 58, 78, 59, 72, 57";
+
+// the first legal move every time, O wins
+const FINISHED: &str = "0, 1, 9, 2, 18, 3, 27, 4, 36, 5, 45, 10, 11, 19, 12, 28, 13, 37, 14, \
+                        46, 20, 21, 29, 22, 38, 23, 47, 30, 31, 39, 32, 48, 33, 54, 40, 41, \
+                        49, 42, 55, 43, 63, 44, 72, 50, 51, 56, 57, 58, 59, 60";
+
+fn sample_record() -> GameRecord {
+    let mut record = GameRecord::from_move_list(MOVE_LIST).unwrap();
+    record.set_header(HEADER_X, "alice").unwrap();
+    record.set_header(HEADER_O, "barbar \"the engine\"").unwrap();
+    record.set_header(HEADER_O_ENGINE, "alphabeta").unwrap();
+    record.set_header(HEADER_TIME_CONTROL, "60000+1000").unwrap();
+    record.set_header(HEADER_DATE, "2020.06.21").unwrap();
+    record.comment = Some("a test game".to_string());
+    record.moves_mut()[0].comment = Some("corner opening".to_string());
+    record.moves_mut()[1].eval = Some(-0.25);
+    record.moves_mut()[2].comment = Some("forced".to_string());
+    record.moves_mut()[2].eval = Some(1.5);
+    return record;
+}
+
+#[test]
+fn roundtrip() {
+    common::setup();
+    let record = sample_record();
+    let text = record.to_string();
+    println!("{}", text);
+    assert!(text.lines().all(|line| line.len() <= 80));
+    let parsed = text.parse::<GameRecord>().unwrap();
+    assert_eq!(parsed, record);
+    assert_eq!(parsed.header(HEADER_O), Some("barbar \"the engine\""));
+    assert_eq!(parsed.header(HEADER_RULESET), None);
+
+    let pos = Position::from_move_list(&parsed.to_move_list()).unwrap();
+    assert_eq!(pos, parsed.position());
+    assert_eq!(parsed.to_move_list(), MOVE_LIST);
+    assert_eq!(parsed.result, pos.get_result());
+}
+
+#[test]
+fn handwritten() {
+    common::setup();
+    // mixed notations, no move numbers, a comment with only an eval
+    let text = "[X \"alice\"]\n[Result \"*\"]\n\n4.4 {center} 4,3 {[%eval 0.5]} a2/a3 1... A3/b2\n*\n";
+    let record = text.parse::<GameRecord>().unwrap();
+    assert_eq!(record.moves().iter().map(|mov| mov.mov).collect::<Vec<_>>(), vec![40, 39, 27, 4]);
+    assert_eq!(record.moves()[0].comment.as_deref(), Some("center"));
+    assert_eq!(record.moves()[1].comment, None);
+    assert_eq!(record.moves()[1].eval, Some(0.5));
+    assert_eq!(record.result, GameResult::Ongoing);
+    assert_eq!(record.header(HEADER_X), Some("alice"));
+    // moves can be added to a parsed record
+    let mut record = record;
+    let mov = record.position().legal_moves().next().unwrap();
+    record.push_move(RecordMove::new(mov)).unwrap();
+    assert_eq!(record.position(), Position::from_move_list(&record.to_move_list()).unwrap());
+
+    // resignations are kept, even if the board says otherwise
+    let record = "4.4 4.0 0-1".parse::<GameRecord>().unwrap();
+    assert_eq!(record.result, GameResult::OWon);
+    assert!(!record.position().is_over());
+}
+
+#[test]
+fn multiple_games() {
+    common::setup();
+    let first = sample_record();
+    let second = GameRecord::from_move_list("40, 36").unwrap();
+    let text = write_games(&[first.clone(), second.clone(), first.clone()]);
+    assert_eq!(parse_games(&text).unwrap(), vec![first.clone(), second.clone(), first.clone()]);
+
+    let games = GameReader::new(Cursor::new(text)).map(|game| game.unwrap()).collect::<Vec<_>>();
+    assert_eq!(games, vec![first.clone(), second.clone(), first.clone()]);
+
+    // the second game has an illegal move on its third line
+    let text = format!("{}\n[X \"a\"]\n\n4.4\n4.4 *\n", first);
+    let mut reader = GameReader::new(Cursor::new(text));
+    assert_eq!(reader.next().unwrap().unwrap(), first);
+    match reader.next().unwrap() {
+        Err(RecordError::Parse { line, err }) => {
+            assert_eq!(line, first.to_string().lines().count() + 1);
+            assert_eq!(err.offset, "[X \"a\"]\n\n4.4\n".len());
+        },
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn editing() {
+    common::setup();
+    let mut record = GameRecord::from_move_list(FINISHED).unwrap();
+    let last = record.moves().last().unwrap().mov;
+    assert!(matches!(record.push_move(RecordMove::new(last)), Err(RecordError::IllegalMove { .. })));
+    assert_eq!(record.pop_move().unwrap().mov, last);
+    assert_eq!(record.result, GameResult::Ongoing);
+    assert_eq!(record.position(), Position::from_move_list(&record.to_move_list()).unwrap());
+    record.push_move(RecordMove::new(last)).unwrap();
+    assert_eq!(record.result, GameResult::OWon);
+
+    assert!(matches!(record.set_header(HEADER_RESULT, "1-0"), Err(RecordError::ResultHeader)));
+    assert!(matches!(record.set_header(HEADER_RULESET, "chess"), Err(RecordError::BadRuleset(_))));
+    // a won block sends you there, so a later move is no longer legal
+    let sends = Ruleset::codingame().with_won_blocks(WonBlocks::OpenSendsYouThere);
+    assert!(matches!(record.set_ruleset(sends), Err(RecordError::IllegalMove { .. })));
+    assert_eq!(record.header(HEADER_RULESET), None);
+    record.set_ruleset(Ruleset::standard()).unwrap();
+    assert_eq!(record.position().ruleset(), Ruleset::standard());
+}
+
+#[test]
+fn errors() {
+    common::setup();
+    let err = "4.4 4.4".parse::<GameRecord>().err().unwrap();
+    assert_eq!(err.offset, 4);
+    let err = "4.4 {unterminated".parse::<GameRecord>().err().unwrap();
+    assert_eq!(err.offset, 4);
+    let err = "4.4 {[%eval x]}".parse::<GameRecord>().err().unwrap();
+    assert_eq!(err.offset, 5);
+    let err = "[X \"alice]\n4.4".parse::<GameRecord>().err().unwrap();
+    assert!(matches!(err.kind, ParseErrorKind::Expected { .. }));
+    // the header and the movetext disagree
+    assert!("[Result \"1-0\"]\n4.4 0-1".parse::<GameRecord>().is_err());
+    // a finished game can't have another result
+    let record = GameRecord::from_move_list(FINISHED).unwrap();
+    assert_eq!(record.result, GameResult::OWon);
+    let text = record.to_string().replace(&format!("\"{}\"", record.result), "\"*\"");
+    assert!(text.parse::<GameRecord>().is_err());
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use uttt::engine::*;
+use uttt::engine::mcts::*;
+use uttt::format::PositionError;
+use uttt::moves::*;
+use uttt::record::*;
+use uttt::rules::*;
+mod common;
+
+// X has captured blocks 0 and 1, and block 2 is one move (cell 26) away
+// from filling up without a winner. X to move anywhere
+const NEAR_TIE: &str = "\
+XXX|XXX|XOX
+OO.|OO.|XOO
+...|...|OX.
+-----------
+...|O..|...
+...|...|...
+...|..O|...
+-----------
+...|...|...
+...|...|...
+...|...|... -";
+
+fn tied_both() -> Ruleset {
+    Ruleset::codingame().with_tied_blocks(TiedBlocks::CountForBoth)
+}
+
+// plays random games until one fills the board without a row of blocks.
+// Returns the final position and the moves
+fn full_board_game(rng: &mut SmallRng, ruleset: Ruleset) -> (Position, Vec<Idx>) {
+    loop {
+        let mut pos = Position::with_ruleset(ruleset);
+        let mut played = Vec::new();
+        while !pos.is_over() {
+            let moves = pos.legal_moves();
+            let mov = moves.nth_move(rng.gen_range(0, moves.size()) as u8);
+            pos.make_move(mov);
+            played.push(mov);
+        }
+        if pos.is_drawn() && !pos.is_won(Side::X) && !pos.is_won(Side::O) {
+            return (pos, played);
+        }
+    }
+}
+
+#[test]
+fn ruleset_notation() {
+    common::setup();
+    for repr in &["codingame", "standard", "codingame+tied-both", "standard+tied-both", "codingame+open",
+                  "standard+tied-both+sends-you-there"] {
+        assert_eq!(repr.parse::<Ruleset>().unwrap().to_string(), *repr);
+    }
+    assert_eq!(Ruleset::default(), Ruleset::codingame());
+    assert_eq!("standard".parse::<Ruleset>().unwrap().full_board, FullBoard::Draw);
+    assert_eq!("codingame+tied-both".parse::<Ruleset>().unwrap(), tied_both());
+    assert_eq!("chess".parse::<Ruleset>().err().unwrap().offset, 0);
+    assert_eq!("standard+bogus".parse::<Ruleset>().err().unwrap().offset, 9);
+    let open = "standard+open".parse::<Ruleset>().unwrap();
+    assert_eq!(open, Ruleset::standard().with_won_blocks(WonBlocks::Open));
+    assert!(open.won_blocks.is_open() && !Ruleset::standard().won_blocks.is_open());
+}
+
+#[test]
+fn full_board() {
+    common::setup();
+    let mut rng = SmallRng::seed_from_u64(7);
+    let mut decided = 0;
+    for _ in 0..20 {
+        let (mut pos, _) = full_board_game(&mut rng, Ruleset::codingame());
+        let majority = pos.get_result();
+        pos.set_ruleset(Ruleset::standard()).unwrap();
+        assert_eq!(pos.get_result(), GameResult::Draw);
+        assert!(pos.is_over());
+        if majority != GameResult::Draw {
+            decided += 1;
+        }
+    }
+    // usually one side captured more blocks
+    assert!(decided > 0);
+}
+
+#[test]
+fn won_blocks_switch() {
+    common::setup();
+    let open = Ruleset::standard().with_won_blocks(WonBlocks::Open);
+    let mut rng = SmallRng::seed_from_u64(15);
+    let mut pos = Position::with_ruleset(open);
+    // before the first capture, closed and open blocks look the same
+    let mut refused = false;
+    while !pos.is_over() && !refused {
+        let moves = pos.legal_moves();
+        pos.make_move(moves.nth_move(rng.gen_range(0, moves.size()) as u8));
+        let mut switched = pos;
+        match switched.set_ruleset(Ruleset::standard()) {
+            Ok(()) => assert_eq!(switched.validate(), Ok(())),
+            Err(err) => {
+                // a captured block is left open, and closed rules can't
+                // make sense of it
+                assert_eq!(err, PositionError::WonBlocksChange);
+                assert_eq!(switched, pos);
+                assert_eq!(switched.ruleset(), open);
+                refused = true;
+            },
+        }
+    }
+    assert!(refused);
+    assert_eq!(pos.validate(), Ok(()));
+
+    // the other open variant stores captures the same way
+    let mut sending = pos;
+    assert_eq!(sending.set_ruleset(open.with_won_blocks(WonBlocks::OpenSendsYouThere)), Ok(()));
+    assert_eq!(sending.validate(), Ok(()));
+
+    // and closed blocks can't be opened either
+    let played = full_board_game(&mut rng, Ruleset::standard()).1;
+    let mut closed = Position::with_ruleset(Ruleset::standard());
+    for mov in &played[..played.len() - 1] {
+        closed.make_move(*mov);
+    }
+    assert_eq!(closed.set_ruleset(open), Err(PositionError::WonBlocksChange));
+}
+
+#[test]
+fn tied_blocks() {
+    common::setup();
+    let pos = Position::from_compact_board(NEAR_TIE, Side::X, true).unwrap();
+    assert_eq!(pos.side_to_move(), Side::X);
+
+    let mut dead = pos;
+    dead.make_move(26);
+    assert_eq!(dead.get_result(), GameResult::Ongoing);
+
+    let mut both = pos;
+    both.set_ruleset(tied_both()).unwrap();
+    assert_ne!(both.hash(), pos.hash());
+    both.make_move(26);
+    assert_eq!(both.get_result(), GameResult::XWon);
+    assert_eq!(both.validate(), Ok(()));
+
+    // O tying the block hands X the win as well
+    let mut pos_o = Position::from_compact_board(NEAR_TIE, Side::O, false).unwrap();
+    pos_o.set_ruleset(tied_both()).unwrap();
+    pos_o.make_move(26);
+    assert!(pos_o.is_won(Side::X) && pos_o.side_to_move() == Side::X);
+    assert_eq!(pos_o.get_result(), GameResult::XWon);
+    assert_eq!(pos_o.validate(), Ok(()));
+}
+
+#[test]
+fn engines_follow_ruleset() {
+    common::setup();
+    let mut pos = Position::from_compact_board(NEAR_TIE, Side::X, true).unwrap();
+    let manager = Manager::from_position(pos);
+    let res = manager.search(SearchLimits::depth(2), Arc::new(AtomicBool::new(false)));
+    assert!(res.eval < 1e5);
+
+    pos.set_ruleset(tied_both()).unwrap();
+    let manager = Manager::from_position(pos);
+    let res = manager.search(SearchLimits::depth(2), Arc::new(AtomicBool::new(false)));
+    assert_eq!(res.best_move, 26);
+    assert!(res.eval > 1e5);
+
+    // one empty cell left, and the full board goes to whoever captured more
+    let mut rng = SmallRng::seed_from_u64(7);
+    let played = loop {
+        let (pos, played) = full_board_game(&mut rng, Ruleset::codingame());
+        if pos.get_result() == GameResult::XWon {
+            break played;
+        }
+    };
+    let mut before = Position::new();
+    for mov in &played[..played.len() - 1] {
+        before.make_move(*mov);
+    }
+    for (ruleset, value) in [(Ruleset::codingame(), 1.0), (Ruleset::standard(), 0.5)].iter() {
+        let mut pos = before;
+        pos.set_ruleset(*ruleset).unwrap();
+        let mut mcts = MCTSWorker::new(pos, 0.85, SmallRng::seed_from_u64(3));
+        let (res, _) = mcts.go(MCTSLimits::movetime(50), Arc::new(AtomicBool::new(false)));
+        assert_eq!(res.value, *value);
+
+        let manager = Manager::from_position(pos);
+        let res = manager.search(SearchLimits::depth(1), Arc::new(AtomicBool::new(false)));
+        let x_eval = if pos.side_to_move() == Side::X { res.eval } else { -res.eval };
+        assert_eq!(x_eval > 1e5, *value == 1.0);
+        assert_eq!(x_eval == 0.0, *value == 0.5);
+    }
+}
+
+#[test]
+fn ruleset_in_formats() {
+    common::setup();
+    let mut pos = Position::from_move_list("40, 36").unwrap();
+    assert_eq!(pos.to_bgn().split_whitespace().count(), 5);
+    pos.set_ruleset(Ruleset::standard()).unwrap();
+    let bgn = pos.to_bgn();
+    assert!(bgn.ends_with(" standard"));
+    assert_eq!(Position::from_bgn(&bgn).unwrap(), pos);
+    assert!(Position::from_bgn(&bgn.replace("standard", "chess")).is_err());
+
+    // both sides have three in a row in the open block 2, and the '!' says
+    // O got there first
+    let bgn = "2 b5/112/17/124/8a/50/e8/6a/97 10a/c9/1c0!/8b/55/f/116/194/120 1 O standard+open";
+    let pos = Position::from_bgn(bgn).unwrap();
+    assert_eq!(pos.to_bgn(), bgn);
+    assert_eq!(pos.validate(), Ok(()));
+    assert!(Position::from_bgn(&bgn.replace("!", "")).is_err());
+    assert!(Position::from_bgn(&bgn.replace("17", "17!")).is_err());
+    assert!(Position::from_bgn(&bgn.replace("+open", "")).is_err());
+
+    // the same moves are a draw under standard rules, but not on Codingame
+    let mut rng = SmallRng::seed_from_u64(7);
+    let (_, played) = loop {
+        let (pos, played) = full_board_game(&mut rng, Ruleset::codingame());
+        if pos.get_result() != GameResult::Draw {
+            break (pos, played);
+        }
+    };
+    let mut record = GameRecord::new();
+    record.set_ruleset(Ruleset::standard()).unwrap();
+    for mov in played.iter() {
+        record.push_move(RecordMove::new(*mov)).unwrap();
+    }
+    assert_eq!(record.result, GameResult::Draw);
+    let text = record.to_string();
+    assert!(text.contains("[Ruleset \"standard\"]"));
+    let parsed = text.parse::<GameRecord>().unwrap();
+    assert_eq!(parsed.ruleset(), Ruleset::standard());
+    assert_eq!(parsed.position().get_result(), GameResult::Draw);
+    assert_ne!(GameRecord::from_moves(&played).unwrap().result, GameResult::Draw);
+    // the draw doesn't match the board under Codingame rules
+    assert!(text.replace("standard", "codingame").parse::<GameRecord>().is_err());
+    assert!("[Ruleset \"chess\"]\n*".parse::<GameRecord>().is_err());
+}
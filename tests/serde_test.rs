@@ -0,0 +1,39 @@
+#![cfg(feature = "serde")]
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use uttt::engine::*;
+use uttt::moves::*;
+mod common;
+
+#[test]
+fn position_json() {
+    common::setup();
+    let pos = Position::from_move_list("0, 1, 9, 4, 36, 7, 70, 71, 79").unwrap();
+    let json = serde_json::to_string(&pos).unwrap();
+    assert_eq!(json, format!("\"{}\"", pos.to_bgn()));
+    assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), pos);
+    // not a position
+    assert!(serde_json::from_str::<Position>("\"2 0/0\"").is_err());
+
+    assert_eq!(serde_json::to_string(&Side::X).unwrap(), "\"X\"");
+    let moves = pos.legal_moves();
+    let json = serde_json::to_string(&moves).unwrap();
+    assert_eq!(serde_json::from_str::<Moves>(&json).unwrap(), moves);
+    assert!(serde_json::from_str::<Moves>("[81]").is_err());
+}
+
+#[test]
+fn search_result_json() {
+    common::setup();
+    let pos = Position::from_move_list("0, 3, 27, 4, 36, 5, 46, 13, 37, 12, 28, 14").unwrap();
+    let manager = Manager::with_hash_size(pos, 1);
+    let res = manager.search(SearchLimits::depth(4), Arc::new(AtomicBool::new(false)));
+    let json = serde_json::to_string(&res).unwrap();
+    let res1: SearchResult = serde_json::from_str(&json).unwrap();
+    assert_eq!(res1.best_move, res.best_move);
+    assert_eq!(res1.pv, res.pv);
+    assert_eq!(res1.nodes, res.nodes);
+    assert_eq!(res1.root_moves.len(), res.root_moves.len());
+}
@@ -0,0 +1,135 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command as Process, Stdio};
+
+use uttt::uti::*;
+
+// drives the main binary over its stdin/stdout
+struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Engine {
+    fn start() -> Engine {
+        let mut child = Process::new(env!("CARGO_BIN_EXE_main"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Engine {
+            child: child,
+            stdin: stdin,
+            stdout: stdout,
+        }
+    }
+
+    fn send(&mut self, cmd: &str) {
+        writeln!(self.stdin, "{}", cmd).unwrap();
+    }
+
+    fn recv(&mut self) -> Response {
+        let mut line = String::new();
+        self.stdout.read_line(&mut line).unwrap();
+        return line.parse().unwrap();
+    }
+
+    fn recv_info(&mut self) -> SearchInfo {
+        match self.recv() {
+            Response::Info(info) => info,
+            other => panic!("expected info, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn handshake() {
+    let mut engine = Engine::start();
+    engine.send("uti");
+    for spec in option_specs() {
+        assert_eq!(engine.recv(), Response::Option(spec));
+    }
+    assert_eq!(engine.recv(), Response::UtiOk);
+    engine.send("id");
+    assert_eq!(engine.recv(), Response::MyId {
+        name: ENGINE_NAME.to_string(),
+        version: ENGINE_VERSION.to_string(),
+    });
+    engine.send("isready");
+    assert_eq!(engine.recv(), Response::ReadyOk);
+    engine.send("quit");
+    assert!(engine.child.wait().unwrap().success());
+}
+
+#[test]
+fn search() {
+    let mut engine = Engine::start();
+    engine.send("option threads=2");
+    engine.send("option hash=1");
+    engine.send("pos start");
+    // 40 and 36
+    engine.send("pos moves b2/b2 4.0");
+    engine.send("search depth 4");
+    let info = engine.recv_info();
+    assert_eq!(info.depth, Some(4));
+    assert_eq!(info.pv[0], info.best_move);
+    // X has to play in block 0
+    assert!(info.best_move < 9);
+
+    // still answers while searching
+    engine.send("search forever");
+    engine.send("isready");
+    assert_eq!(engine.recv(), Response::ReadyOk);
+    engine.send("stop");
+    assert!(engine.recv_info().best_move < 9);
+
+    engine.send("option engine=mcts");
+    engine.send("search time 50");
+    let info = engine.recv_info();
+    assert!(info.best_move < 9);
+    assert!(info.nodes.unwrap() > 0);
+    engine.send("search depth 3");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("quit");
+    assert!(engine.child.wait().unwrap().success());
+}
+
+#[test]
+fn errors() {
+    let mut engine = Engine::start();
+    engine.send("bogus");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("search depth x");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("pos start");
+    engine.send("search depth 0");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("pos moves 40 40");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    // none of the moves were made, so 40 is still legal
+    engine.send("pos moves 40");
+    engine.send("isready");
+    assert_eq!(engine.recv(), Response::ReadyOk);
+    engine.send("pos moves d4/a1");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("pos bgn 2 0/0/0/0/0/0/0/0/0 3/0/0/0/0/0/0/0/0 9 X");
+    assert!(matches!(engine.recv(), Response::Error(_)));
+    engine.send("");
+    engine.send("isready");
+    assert_eq!(engine.recv(), Response::ReadyOk);
+}
+
+#[test]
+fn exits_on_eof() {
+    let mut engine = Engine::start();
+    engine.send("search depth 2");
+    drop(engine.stdin);
+    // the search in progress is still reported
+    let mut line = String::new();
+    engine.stdout.read_line(&mut line).unwrap();
+    assert!(matches!(line.parse::<Response>().unwrap(), Response::Info(_)));
+    assert!(engine.child.wait().unwrap().success());
+}